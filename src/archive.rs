@@ -0,0 +1,657 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+
+use crate::{CountOptions, FileStats, LineCountError, count, filter};
+
+/// Size of a tar header and of the blocks member data is padded to.
+const BLOCK: usize = 512;
+
+/// Longest GNU long name or pax header that is read into memory.
+const MAX_TAR_META: u64 = 1024 * 1024;
+
+/// The end of central directory record is the last thing in a zip file,
+/// followed by a comment of up to 64 KB.
+const ZIP_TAIL: u64 = 22 + 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+fn kind(path: &Path) -> Option<Kind> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if name.ends_with(".tar") {
+        Some(Kind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Kind::TarGz)
+    } else if name.ends_with(".zip") {
+        Some(Kind::Zip)
+    } else {
+        None
+    }
+}
+
+/// Returns true if `path` is named like a tar, gzipped tar or zip archive.
+pub fn is_archive(path: &Path) -> bool {
+    kind(path).is_some()
+}
+
+/// Counts every member of the archive at `path` that passes the filters,
+/// without extracting it, and passes each one's stats to `on_member`.
+/// Members are named `<archive>:<name inside>`. Returns how many members
+/// the filters left out.
+pub(crate) fn count_members(
+    path: &Path,
+    options: &CountOptions,
+    buffer: &mut [u8],
+    on_member: &mut dyn FnMut(FileStats),
+) -> Result<u64, LineCountError> {
+    let Some(kind) = kind(path) else {
+        return Err(LineCountError::Unsupported {
+            path: path.to_path_buf(),
+        });
+    };
+    let file = File::open(path).map_err(|e| LineCountError::io(path, e))?;
+    let mut members = Members {
+        archive: path,
+        options,
+        buffer,
+        on_member,
+        filtered: 0,
+    };
+    let read = match kind {
+        Kind::Tar => read_tar(BufReader::new(file), &mut members),
+        Kind::TarGz => read_tar(MultiGzDecoder::new(BufReader::new(file)), &mut members),
+        Kind::Zip => read_zip(file, &mut members),
+    };
+    read.map_err(|e| LineCountError::io(path, e))?;
+    Ok(members.filtered)
+}
+
+/// What the readers of each format hand their members to.
+struct Members<'a> {
+    archive: &'a Path,
+    options: &'a CountOptions,
+    buffer: &'a mut [u8],
+    on_member: &'a mut dyn FnMut(FileStats),
+    filtered: u64,
+}
+
+impl Members<'_> {
+    /// Returns true if a regular file called `name` of `size` bytes passes
+    /// the filters. Archives inside the archive are left out.
+    fn wanted(&mut self, name: &str, size: u64) -> bool {
+        let inner = Path::new(name);
+        let wanted = !is_archive(inner)
+            && self.options.should_count(inner)
+            && self.options.size_in_range(size);
+        if !wanted {
+            verbose!(
+                self.options,
+                1,
+                "skip {}:{}: not a counted file type",
+                self.archive.display(),
+                name
+            );
+            self.filtered += 1;
+        }
+        wanted
+    }
+
+    /// Counts the contents of the member `name`, read from `data`.
+    fn count<R: Read>(&mut self, name: &str, data: &mut R) -> io::Result<()> {
+        let mut path = OsString::from(self.archive.as_os_str());
+        path.push(":");
+        path.push(name);
+        let path = PathBuf::from(path);
+
+        let inner = Path::new(name);
+        let counts = match filter::gzip_inner(inner) {
+            Some(unzipped) => count::count_stream(
+                &mut MultiGzDecoder::new(&mut *data),
+                &unzipped,
+                self.buffer,
+                self.options,
+            )?,
+            None => count::count_stream(data, inner, self.buffer, self.options)?,
+        };
+        (self.on_member)(FileStats::from_counts(&path, counts, self.options));
+        Ok(())
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads a tar stream header by header. GNU long names and pax `path`
+/// records are honored; links, directories and other special entries are
+/// passed over.
+fn read_tar<R: Read>(mut reader: R, members: &mut Members) -> io::Result<()> {
+    let mut header = [0u8; BLOCK];
+    // Set by a GNU long name or pax header for the entry that follows it.
+    let mut long_name: Option<String> = None;
+    loop {
+        if !read_block(&mut reader, &mut header)? || header.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+        if Some(tar_checksum(&header)) != octal(&header[148..156]) {
+            return Err(invalid("not a valid tar archive"));
+        }
+        let size = tar_size(&header[124..136]).ok_or_else(|| invalid("bad tar entry size"))?;
+        let padding = (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64;
+
+        match header[156] {
+            b'L' | b'x' => {
+                let meta = read_meta(&mut reader, size)?;
+                long_name = if header[156] == b'L' {
+                    Some(String::from_utf8_lossy(until_nul(&meta)).into_owned())
+                } else {
+                    pax_path(&meta).or(long_name)
+                };
+            }
+            b'0' | b'\0' | b'7' => {
+                let name = long_name.take().unwrap_or_else(|| tar_name(&header));
+                let mut data = reader.by_ref().take(size);
+                if members.wanted(&name, size) {
+                    members.count(&name, &mut data)?;
+                }
+                // Counting may stop early, on binary data or at the line cap.
+                let unread = data.limit();
+                skip(&mut data, unread)?;
+            }
+            _ => {
+                long_name = None;
+                skip(&mut reader, size)?;
+            }
+        }
+        skip(&mut reader, padding)?;
+    }
+}
+
+/// Fills `block`, returning false at a clean end of the input.
+fn read_block<R: Read>(reader: &mut R, block: &mut [u8; BLOCK]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < BLOCK {
+        match reader.read(&mut block[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(invalid("truncated tar archive")),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+fn read_meta<R: Read>(reader: &mut R, size: u64) -> io::Result<Vec<u8>> {
+    if size > MAX_TAR_META {
+        return Err(invalid("tar extended header too long"));
+    }
+    let mut meta = vec![0u8; size as usize];
+    reader.read_exact(&mut meta)?;
+    Ok(meta)
+}
+
+fn skip<R: Read>(reader: &mut R, n: u64) -> io::Result<()> {
+    if io::copy(&mut reader.by_ref().take(n), &mut io::sink())? < n {
+        return Err(invalid("truncated archive"));
+    }
+    Ok(())
+}
+
+fn until_nul(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+/// The header's name, joined to its ustar prefix if it has one.
+fn tar_name(header: &[u8; BLOCK]) -> String {
+    let name = String::from_utf8_lossy(until_nul(&header[..100]));
+    let prefix = until_nul(&header[345..500]);
+    if &header[257..262] == b"ustar" && !prefix.is_empty() {
+        format!("{}/{}", String::from_utf8_lossy(prefix), name)
+    } else {
+        name.into_owned()
+    }
+}
+
+/// Sum of the header bytes, with the checksum field itself read as spaces.
+fn tar_checksum(header: &[u8; BLOCK]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                u64::from(b)
+            }
+        })
+        .sum()
+}
+
+fn octal(field: &[u8]) -> Option<u64> {
+    let digits = until_nul(field);
+    let digits = std::str::from_utf8(digits).ok()?.trim();
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// Sizes of 8 GB and up are stored in base 256, flagged by the high bit.
+fn tar_size(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 == 0 {
+        return octal(field);
+    }
+    let mut size = u64::from(field[0] & 0x7F);
+    for &b in &field[1..] {
+        size = size.checked_mul(256)? | u64::from(b);
+    }
+    Some(size)
+}
+
+/// The `path` record of a pax header, made of `<length> <key>=<value>\n`
+/// records.
+fn pax_path(meta: &[u8]) -> Option<String> {
+    let mut rest = meta;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ')?;
+        let len: usize = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        let record = rest.get(space + 1..len)?;
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(value) = record.strip_prefix(b"path=") {
+            return Some(String::from_utf8_lossy(value).into_owned());
+        }
+        rest = &rest[len..];
+    }
+    None
+}
+
+fn u16_at(data: &[u8], at: usize) -> u64 {
+    u64::from(u16::from_le_bytes([data[at], data[at + 1]]))
+}
+
+fn u32_at(data: &[u8], at: usize) -> u64 {
+    u64::from(u32::from_le_bytes(data[at..at + 4].try_into().unwrap()))
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+/// Reads a zip file through its central directory, so members are found
+/// without scanning the data. Stored and deflated members are counted;
+/// anything else, such as encrypted members, fails the whole archive.
+fn read_zip(mut file: File, members: &mut Members) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    let tail_start = len.saturating_sub(ZIP_TAIL);
+    file.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| tail[i..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| invalid("not a valid zip archive"))?;
+
+    let mut entries = u16_at(&tail, end + 10);
+    let mut dir_size = u32_at(&tail, end + 12);
+    let mut dir_offset = u32_at(&tail, end + 16);
+    // Too many entries or too big a file for the classic record.
+    if entries == 0xFFFF || dir_size == 0xFFFF_FFFF || dir_offset == 0xFFFF_FFFF {
+        let locator = end
+            .checked_sub(20)
+            .filter(|&at| tail[at..].starts_with(b"PK\x06\x07"))
+            .ok_or_else(|| invalid("missing zip64 end of central directory"))?;
+        let mut record = [0u8; 56];
+        file.seek(SeekFrom::Start(u64_at(&tail, locator + 8)))?;
+        file.read_exact(&mut record)?;
+        if !record.starts_with(b"PK\x06\x06") {
+            return Err(invalid("bad zip64 end of central directory"));
+        }
+        entries = u64_at(&record, 32);
+        dir_size = u64_at(&record, 40);
+        dir_offset = u64_at(&record, 48);
+    }
+    if dir_offset.saturating_add(dir_size) > len {
+        return Err(invalid("truncated zip archive"));
+    }
+
+    let mut dir = vec![0u8; dir_size as usize];
+    file.seek(SeekFrom::Start(dir_offset))?;
+    file.read_exact(&mut dir)?;
+
+    let mut at = 0;
+    for _ in 0..entries {
+        let header = dir
+            .get(at..at + 46)
+            .filter(|h| h.starts_with(b"PK\x01\x02"))
+            .ok_or_else(|| invalid("bad zip central directory"))?;
+        let flags = u16_at(header, 8);
+        let method = u16_at(header, 10);
+        let mut compressed = u32_at(header, 20);
+        let mut size = u32_at(header, 24);
+        let name_len = u16_at(header, 28) as usize;
+        let extra_len = u16_at(header, 30) as usize;
+        let comment_len = u16_at(header, 32) as usize;
+        let mut offset = u32_at(header, 42);
+        let name_start = at + 46;
+        let extra_start = name_start + name_len;
+        let next = extra_start + extra_len + comment_len;
+        let (Some(name), Some(extra)) = (
+            dir.get(name_start..extra_start),
+            dir.get(extra_start..extra_start + extra_len),
+        ) else {
+            return Err(invalid("bad zip central directory"));
+        };
+        let name = String::from_utf8_lossy(name).into_owned();
+        at = next;
+
+        // Only the fields that overflowed are in the zip64 extra field,
+        // in this order.
+        if let Some(mut zip64) = zip64_extra(extra) {
+            for field in [&mut size, &mut compressed, &mut offset] {
+                if *field == 0xFFFF_FFFF && zip64.len() >= 8 {
+                    *field = u64_at(zip64, 0);
+                    zip64 = &zip64[8..];
+                }
+            }
+        }
+
+        if name.ends_with('/') || !members.wanted(&name, size) {
+            continue;
+        }
+        if flags & 1 != 0 {
+            return Err(invalid(format!("{} is encrypted", name)));
+        }
+
+        let mut local = [0u8; 30];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut local)?;
+        if !local.starts_with(b"PK\x03\x04") {
+            return Err(invalid(format!("bad zip local header for {}", name)));
+        }
+        let data_start = offset + 30 + u16_at(&local, 26) + u16_at(&local, 28);
+        file.seek(SeekFrom::Start(data_start))?;
+        let data = (&file).take(compressed);
+        match method {
+            0 => members.count(&name, &mut BufReader::new(data))?,
+            8 => members.count(&name, &mut DeflateDecoder::new(data))?,
+            _ => {
+                return Err(invalid(format!(
+                    "{} uses unsupported compression method {}",
+                    name, method
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The data of the zip64 extended information field, if `extra` has one.
+fn zip64_extra(mut extra: &[u8]) -> Option<&[u8]> {
+    while extra.len() >= 4 {
+        let id = u16_at(extra, 0);
+        let len = u16_at(extra, 2) as usize;
+        let data = extra.get(4..4 + len)?;
+        if id == 1 {
+            return Some(data);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::DeflateEncoder;
+
+    use super::*;
+    use crate::testdir::TestDir;
+
+    /// A ustar header for an entry of `size` bytes, with `name` cut to the
+    /// 100 bytes the field holds.
+    fn tar_header(name: &str, kind: u8, size: usize) -> [u8; BLOCK] {
+        let mut header = [0u8; BLOCK];
+        let name = &name.as_bytes()[..name.len().min(100)];
+        header[..name.len()].copy_from_slice(name);
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        let checksum = tar_checksum(&header);
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        header
+    }
+
+    fn tar_entry(tar: &mut Vec<u8>, name: &str, kind: u8, data: &[u8]) {
+        tar.extend_from_slice(&tar_header(name, kind, data.len()));
+        tar.extend_from_slice(data);
+        tar.resize(tar.len().next_multiple_of(BLOCK), 0);
+    }
+
+    /// A tar of regular files. Names too long for the header get a GNU long
+    /// name entry in front of them.
+    fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = Vec::new();
+        for (name, data) in files {
+            if name.len() > 100 {
+                let long = format!("{}\0", name);
+                tar_entry(&mut tar, "././@LongLink", b'L', long.as_bytes());
+            }
+            tar_entry(&mut tar, name, b'0', data);
+        }
+        tar.resize(tar.len() + 2 * BLOCK, 0);
+        tar
+    }
+
+    /// A zip of files, each stored or, if its flag is set, deflated.
+    fn zip(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut dir = Vec::new();
+        for (name, data, deflate) in files {
+            let (method, packed) = if *deflate {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                (8u16, encoder.finish().unwrap())
+            } else {
+                (0u16, data.to_vec())
+            };
+            let mut crc = flate2::Crc::new();
+            crc.update(data);
+            let offset = zip.len() as u32;
+
+            // Version needed, flags, method, time and date, then the crc,
+            // sizes and name length, which the central directory repeats.
+            let mut common = Vec::new();
+            common.extend_from_slice(&20u16.to_le_bytes());
+            common.extend_from_slice(&0u16.to_le_bytes());
+            common.extend_from_slice(&method.to_le_bytes());
+            common.extend_from_slice(&[0; 4]);
+            common.extend_from_slice(&crc.sum().to_le_bytes());
+            common.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            common.extend_from_slice(&0u16.to_le_bytes());
+
+            zip.extend_from_slice(b"PK\x03\x04");
+            zip.extend_from_slice(&common);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(&packed);
+
+            dir.extend_from_slice(b"PK\x01\x02");
+            dir.extend_from_slice(&20u16.to_le_bytes());
+            dir.extend_from_slice(&common);
+            // Comment length, disk, internal and external attributes.
+            dir.extend_from_slice(&[0; 10]);
+            dir.extend_from_slice(&offset.to_le_bytes());
+            dir.extend_from_slice(name.as_bytes());
+        }
+        let dir_offset = zip.len() as u32;
+        zip.extend_from_slice(&dir);
+        zip.extend_from_slice(b"PK\x05\x06");
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(dir.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&dir_offset.to_le_bytes());
+        zip.extend_from_slice(&0u16.to_le_bytes());
+        zip
+    }
+
+    /// The paths and line counts of the counted members, and the total.
+    fn members(archive: &Path) -> Result<(Vec<(PathBuf, u64)>, FileStats), LineCountError> {
+        let mut members = Vec::new();
+        let total = crate::count_archive(archive, &CountOptions::default(), |m| {
+            members.push((m.path.clone(), m.lines))
+        })?;
+        Ok((members, total))
+    }
+
+    fn member(archive: &Path, name: &str) -> PathBuf {
+        PathBuf::from(format!("{}:{}", archive.display(), name))
+    }
+
+    #[test]
+    fn tar_members_are_named_after_the_archive() {
+        let dir = TestDir::new();
+        let archive = dir.file(
+            "src.tar",
+            tar(&[
+                ("src/lib.rs", b"one\ntwo\n"),
+                ("src/deep/main.rs", b"one\ntwo\nthree\n"),
+                ("logo.png", b"\x89PNG\r\n"),
+            ]),
+        );
+        let (members, total) = members(&archive).unwrap();
+        assert_eq!(
+            members,
+            [
+                (member(&archive, "src/lib.rs"), 2),
+                (member(&archive, "src/deep/main.rs"), 3),
+            ]
+        );
+        assert_eq!((total.lines, total.skipped.filtered), (5, 1));
+    }
+
+    #[test]
+    fn tar_long_names_are_read_from_gnu_and_pax_headers() {
+        let long = format!("{}/notes.txt", "nested/".repeat(20).trim_end_matches('/'));
+        assert!(long.len() > 100);
+        let mut archive = tar(&[(&long, b"a\nb\nc\n")]);
+
+        // A pax header names the next entry, whatever its own header says.
+        let pax_name = format!("{}/pax.rs", "p".repeat(120));
+        let record = format!("path={}\n", pax_name);
+        let mut len = record.len() + 2;
+        len += format!("{} ", len).len() - 2;
+        let pax = format!("{} {}", len, record);
+        assert_eq!(pax.len(), len);
+        archive.truncate(archive.len() - 2 * BLOCK);
+        tar_entry(&mut archive, "PaxHeader", b'x', pax.as_bytes());
+        tar_entry(&mut archive, "cut-off-name.rs", b'0', b"x\n");
+        archive.resize(archive.len() + 2 * BLOCK, 0);
+
+        let dir = TestDir::new();
+        let archive = dir.file("long.tar", archive);
+        let (members, _) = members(&archive).unwrap();
+        assert_eq!(
+            members,
+            [
+                (member(&archive, &long), 3),
+                (member(&archive, &pax_name), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn gzipped_tars_are_read_through_the_decoder() {
+        let dir = TestDir::new();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&tar(&[("a.txt", b"1\n2\n"), ("b.txt", b"3\n")]))
+            .unwrap();
+        let archive = dir.file("both.tgz", encoder.finish().unwrap());
+        let (members, total) = members(&archive).unwrap();
+        assert_eq!(
+            members,
+            [
+                (member(&archive, "a.txt"), 2),
+                (member(&archive, "b.txt"), 1)
+            ]
+        );
+        assert_eq!(total.lines, 3);
+    }
+
+    #[test]
+    fn zip_stored_and_deflated_members_are_counted() {
+        let dir = TestDir::new();
+        let text = "line\n".repeat(1000);
+        let archive = dir.file(
+            "mixed.zip",
+            zip(&[
+                ("stored/a.rs", b"fn a() {}\nfn b() {}\n", false),
+                ("deflated/b.txt", text.as_bytes(), true),
+                ("skipped.bin", b"\0\0\0", false),
+            ]),
+        );
+        let (members, total) = members(&archive).unwrap();
+        assert_eq!(
+            members,
+            [
+                (member(&archive, "stored/a.rs"), 2),
+                (member(&archive, "deflated/b.txt"), 1000),
+            ]
+        );
+        assert_eq!((total.lines, total.skipped.filtered), (1002, 1));
+    }
+
+    #[test]
+    fn truncated_tar_is_an_error() {
+        let dir = TestDir::new();
+        let full = tar(&[("a.txt", "x\n".repeat(600).as_bytes())]);
+        // Cut inside the member's data and inside its header.
+        for len in [BLOCK + 100, 200] {
+            let archive = dir.file(&format!("cut-{}.tar", len), &full[..len]);
+            assert!(matches!(members(&archive), Err(LineCountError::Io { .. })));
+        }
+    }
+
+    #[test]
+    fn truncated_zip_is_an_error() {
+        let dir = TestDir::new();
+        let full = zip(&[("a.txt", b"a\nb\n", false), ("b.txt", b"c\n", true)]);
+        for len in 0..full.len() {
+            let archive = dir.file(&format!("cut-{}.zip", len), &full[..len]);
+            assert!(
+                matches!(members(&archive), Err(LineCountError::Io { .. })),
+                "{} of {} bytes",
+                len,
+                full.len()
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt_archives_do_not_panic() {
+        let dir = TestDir::new();
+        let zipped = zip(&[("a.txt", b"a\nb\n", false), ("b.txt", b"c\n", true)]);
+        for at in 0..zipped.len() {
+            let mut bad = zipped.clone();
+            bad[at] ^= 0xFF;
+            let _ = members(&dir.file(&format!("bad-{}.zip", at), bad));
+        }
+        let tarred = tar(&[(&"x".repeat(150), b"a\n"), ("b.txt", b"c\n")]);
+        for len in (0..tarred.len()).step_by(7) {
+            let _ = members(&dir.file(&format!("cut-{}.tar", len), &tarred[..len]));
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use linecount::FileStats;
+use serde_json::Value;
+
+use crate::output::path_text;
+
+/// Line counts of an earlier `--format=json --per-file` report.
+#[derive(Debug)]
+pub struct Baseline {
+    pub total: u64,
+    pub files: HashMap<String, u64>,
+}
+
+/// How the line count of one file changed since the baseline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: String,
+    /// `None` for a file that is new since the baseline.
+    pub before: Option<u64>,
+    /// `None` for a file that has been removed since the baseline.
+    pub after: Option<u64>,
+}
+
+impl Change {
+    pub fn delta(&self) -> i64 {
+        self.after.unwrap_or(0) as i64 - self.before.unwrap_or(0) as i64
+    }
+}
+
+pub fn load(path: &Path) -> Result<Baseline, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let report: Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+    let Some(files) = report.get("files").and_then(Value::as_array) else {
+        return Err("no per-file counts; write it with --format=json --per-file".to_string());
+    };
+
+    let mut baseline = Baseline {
+        total: 0,
+        files: HashMap::new(),
+    };
+    for file in files {
+        let path = file.get("path").and_then(Value::as_str);
+        let lines = file.get("lines").and_then(Value::as_u64);
+        let (Some(path), Some(lines)) = (path, lines) else {
+            return Err("every entry of `files` needs a `path` and `lines`".to_string());
+        };
+        baseline.files.insert(path.to_string(), lines);
+    }
+    // A hand-written baseline may leave out the total.
+    baseline.total = match report.get("total").and_then(Value::as_u64) {
+        Some(total) => total,
+        None => baseline.files.values().sum(),
+    };
+    Ok(baseline)
+}
+
+impl Baseline {
+    /// Keeps only the files in `paths`, with the total adjusted to match.
+    pub fn retain_paths(&mut self, paths: &HashSet<String>) {
+        self.files.retain(|path, _| paths.contains(path));
+        self.total = self.files.values().sum();
+    }
+}
+
+/// Compares `files` against `baseline`, returning the files whose count
+/// changed, sorted by path. Files on only one side count as wholly added or
+/// removed.
+pub fn diff(baseline: &Baseline, files: &[FileStats]) -> Vec<Change> {
+    let mut current: HashMap<String, u64> = HashMap::new();
+    for stats in files {
+        *current.entry(path_text(&stats.path)).or_default() += stats.lines;
+    }
+
+    let mut changes: Vec<Change> = current
+        .iter()
+        .map(|(path, &lines)| Change {
+            path: path.clone(),
+            before: baseline.files.get(path).copied(),
+            after: Some(lines),
+        })
+        .chain(
+            baseline
+                .files
+                .iter()
+                .filter(|(path, _)| !current.contains_key(*path))
+                .map(|(path, &lines)| Change {
+                    path: path.clone(),
+                    before: Some(lines),
+                    after: None,
+                }),
+        )
+        .filter(|c| c.before != c.after)
+        .collect();
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use linecount::CountOptions;
+
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn file(path: &str, lines: u64) -> FileStats {
+        let mut stats = FileStats::new(Path::new(path), &CountOptions::default());
+        stats.lines = lines;
+        stats
+    }
+
+    fn baseline(files: &[(&str, u64)]) -> Baseline {
+        Baseline {
+            total: files.iter().map(|(_, lines)| lines).sum(),
+            files: files
+                .iter()
+                .map(|&(p, lines)| (p.to_string(), lines))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn added_removed_and_changed_files_are_reported_by_path() {
+        let before = baseline(&[("a.rs", 1), ("b.rs", 2), ("c.rs", 3)]);
+        let after = [file("d.rs", 4), file("b.rs", 5), file("a.rs", 1)];
+        let change = |path: &str, before, after| Change {
+            path: path.to_string(),
+            before,
+            after,
+        };
+        let changes = diff(&before, &after);
+        assert_eq!(
+            changes,
+            [
+                change("b.rs", Some(2), Some(5)),
+                change("c.rs", Some(3), None),
+                change("d.rs", None, Some(4)),
+            ]
+        );
+        let deltas: Vec<i64> = changes.iter().map(Change::delta).collect();
+        assert_eq!(deltas, [3, -3, 4]);
+        assert!(
+            diff(
+                &before,
+                &[file("a.rs", 1), file("b.rs", 2), file("c.rs", 3)]
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn a_report_loads_with_or_without_its_total() {
+        let dir = TestDir::new();
+        let report = r#"{"files":[{"path":"a.rs","lines":2},{"path":"b.rs","lines":3}],"total":9}"#;
+        let loaded = load(&dir.file("report.json", report)).unwrap();
+        assert_eq!(loaded.total, 9);
+        assert_eq!(loaded.files, baseline(&[("a.rs", 2), ("b.rs", 3)]).files);
+
+        let report = r#"{"files":[{"path":"a.rs","lines":2},{"path":"b.rs","lines":3}]}"#;
+        assert_eq!(load(&dir.file("no-total.json", report)).unwrap().total, 5);
+    }
+
+    #[test]
+    fn a_missing_or_malformed_baseline_is_an_error() {
+        let dir = TestDir::new();
+        assert!(load(&dir.path().join("missing.json")).is_err());
+        assert!(load(&dir.file("broken.json", "{\"files\": [")).is_err());
+        let summary = load(&dir.file("summary.json", r#"{"total":3}"#)).unwrap_err();
+        assert!(summary.contains("--per-file"), "{}", summary);
+        let entry = r#"{"files":[{"path":"a.rs"}]}"#;
+        let entry = load(&dir.file("entry.json", entry)).unwrap_err();
+        assert!(entry.contains("`path` and `lines`"), "{}", entry);
+    }
+
+    #[test]
+    fn retained_paths_adjust_the_total() {
+        let mut before = baseline(&[("a.rs", 1), ("b.rs", 2), ("c.rs", 3)]);
+        before.retain_paths(&HashSet::from(["a.rs".to_string(), "c.rs".to_string()]));
+        assert_eq!(before.total, 4);
+        assert_eq!(before.files.len(), 2);
+    }
+}
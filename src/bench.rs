@@ -0,0 +1,50 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use linecount::CountOptions;
+
+use crate::output;
+
+/// Buffer sizes tried by `--bench`, in KB.
+const SIZES_KB: &[usize] = &[4, 8, 64, 256, 1024];
+/// Timed runs per buffer size; the median is reported.
+const RUNS: usize = 5;
+
+/// Times `count` at each buffer size and returns the median duration per
+/// size in KB.
+///
+/// One untimed run comes first so the page cache is equally warm for every
+/// size; otherwise the first size would pay for reading from disk.
+pub fn bench<F: FnMut(&CountOptions)>(
+    options: &CountOptions,
+    mut count: F,
+) -> Vec<(usize, Duration)> {
+    let mut options = options.clone();
+    count(&options);
+    SIZES_KB
+        .iter()
+        .map(|&kb| {
+            options.buffer_size = kb * 1024;
+            let mut times: Vec<Duration> = (0..RUNS)
+                .map(|_| {
+                    let start = Instant::now();
+                    count(&options);
+                    start.elapsed()
+                })
+                .collect();
+            times.sort_unstable();
+            (kb, times[RUNS / 2])
+        })
+        .collect()
+}
+
+pub fn print(out: &mut dyn Write, results: &[(usize, Duration)], human: bool) -> io::Result<()> {
+    writeln!(out, "Buffer size\tMedian of {} runs", RUNS)?;
+    for &(kb, time) in results {
+        writeln!(out, "{} KB\t{}", kb, output::duration(time, human))?;
+    }
+    if let Some((kb, _)) = results.iter().min_by_key(|(_, time)| *time) {
+        writeln!(out, "Fastest: {} KB (--buffer-size={})", kb, kb)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use serde_json::{Value, json};
+
+use crate::{CountOptions, FileStats};
+
+/// Bumped whenever the file format or the meaning of a count changes.
+const VERSION: u64 = 1;
+
+/// Modification time and size of a file when it was counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    /// Nanoseconds since the Unix epoch.
+    mtime: u64,
+    size: u64,
+}
+
+impl Stamp {
+    pub(crate) fn of(file: &File) -> Option<Stamp> {
+        let metadata = file.metadata().ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp {
+            mtime: u64::try_from(mtime.as_nanos()).ok()?,
+            size: metadata.len(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    stamp: Stamp,
+    lines: u64,
+    binary: bool,
+    truncated: bool,
+}
+
+/// Line counts from earlier runs, keyed by path and reused for files whose
+/// modification time and size haven't changed since.
+///
+/// Only line counts are stored, so the cache is bypassed when
+/// [`CountOptions`] asks for words, characters or any other per-line
+/// detail.
+#[derive(Debug)]
+pub struct FileCache {
+    path: PathBuf,
+    /// The options that affect a line count, as they were when the counts
+    /// were taken.
+    settings: String,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+impl FileCache {
+    /// Reads the cache at `path`. A missing file, or one written with
+    /// different counting options, gives an empty cache.
+    pub fn load(path: &Path, options: &CountOptions) -> Result<FileCache, String> {
+        let mut cache = FileCache {
+            path: path.to_path_buf(),
+            settings: settings(options),
+            entries: Mutex::new(HashMap::new()),
+        };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        // Anything else is refused rather than overwritten by save().
+        let not_cache = || "not a linecount cache".to_string();
+        let data: Value = serde_json::from_str(&text).map_err(|_| not_cache())?;
+        let version = data.get("linecount_cache").and_then(Value::as_u64);
+        let files = data.get("files").and_then(Value::as_array);
+        let (Some(version), Some(files)) = (version, files) else {
+            return Err(not_cache());
+        };
+        if version != VERSION
+            || data.get("settings").and_then(Value::as_str) != Some(&cache.settings)
+        {
+            return Ok(cache);
+        }
+
+        let entries = cache.entries.get_mut().unwrap();
+        for file in files {
+            let field = |name| file.get(name).and_then(Value::as_u64);
+            let flag = |name| file.get(name).and_then(Value::as_bool).unwrap_or(false);
+            let path = file.get("path").and_then(Value::as_str);
+            let (Some(path), Some(mtime), Some(size), Some(lines)) =
+                (path, field("mtime"), field("size"), field("lines"))
+            else {
+                continue;
+            };
+            entries.insert(
+                PathBuf::from(path),
+                Entry {
+                    stamp: Stamp { mtime, size },
+                    lines,
+                    binary: flag("binary"),
+                    truncated: flag("truncated"),
+                },
+            );
+        }
+        Ok(cache)
+    }
+
+    /// Writes the cache back to where it was loaded from, dropping the
+    /// entries of files that no longer exist.
+    pub fn save(&self) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|path, _| path.exists());
+        let mut files: Vec<Value> = entries
+            .iter()
+            .filter_map(|(path, entry)| {
+                Some(json!({
+                    "path": path.to_str()?,
+                    "mtime": entry.stamp.mtime,
+                    "size": entry.stamp.size,
+                    "lines": entry.lines,
+                    "binary": entry.binary,
+                    "truncated": entry.truncated,
+                }))
+            })
+            .collect();
+        drop(entries);
+        files.sort_by(|a, b| a["path"].as_str().cmp(&b["path"].as_str()));
+        let data = json!({
+            "linecount_cache": VERSION,
+            "settings": self.settings,
+            "files": files,
+        });
+
+        // Written aside and renamed, so an interrupted run can't leave
+        // half a cache behind.
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        fs::write(&temp, data.to_string())?;
+        fs::rename(&temp, &self.path)
+    }
+
+    /// Where the cache is saved.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true if the per-file stats for `options` are just a line
+    /// count, which is all the cache can provide.
+    pub fn supports(options: &CountOptions) -> bool {
+        !(options.words
+            || options.chars
+            || options.tokens
+            || options.bytes
+            || options.classify
+            || options.longest
+            || options.check_endings
+            || options.dedup_lines
+            || options.jsonl_validate)
+    }
+
+    /// The cached stats of `path`, if it was counted at `stamp`.
+    pub(crate) fn get(
+        &self,
+        path: &Path,
+        stamp: Stamp,
+        options: &CountOptions,
+    ) -> Option<FileStats> {
+        if !Self::supports(options) {
+            return None;
+        }
+        let entry = *self.entries.lock().unwrap().get(path)?;
+        if entry.stamp != stamp {
+            return None;
+        }
+        verbose!(
+            options,
+            2,
+            "cached {}: {} lines",
+            path.display(),
+            entry.lines
+        );
+        let mut stats = FileStats::new(path, options);
+        stats.lines = entry.lines;
+        stats.skipped.binary = entry.binary as u64;
+        stats.truncated = entry.truncated as u64;
+        Some(stats)
+    }
+
+    /// Remembers `stats`, counted at `stamp`.
+    pub(crate) fn insert(&self, stamp: Stamp, stats: &FileStats, options: &CountOptions) {
+        if !Self::supports(options) || stats.path.to_str().is_none() {
+            return;
+        }
+        let entry = Entry {
+            stamp,
+            lines: stats.lines,
+            binary: stats.skipped.binary > 0,
+            truncated: stats.truncated > 0,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(stats.path.clone(), entry);
+    }
+}
+
+/// The options a line count depends on. Binary detection only looks at
+/// the first buffer, so its size matters with `skip_binary`, as do the
+/// extensions it leaves alone.
+fn settings(options: &CountOptions) -> String {
+    format!(
+        "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+        options.skip_empty,
+        options.empty_lines,
+        options.line_ending,
+        options.extra_breaks,
+        options.encoding,
+        options.sql_statements,
+        options.matching.as_ref().map(|re| re.as_str()),
+        options.not_matching.as_ref().map(|re| re.as_str()),
+        options.max_lines,
+        options.skip_binary,
+        options.skip_binary.then_some(options.buffer_size),
+        options.skip_binary.then_some(&options.text_extensions),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::count_file;
+    use crate::testdir::TestDir;
+
+    fn with_cache(cache: &Path, options: CountOptions) -> CountOptions {
+        let cache = FileCache::load(cache, &options).unwrap();
+        CountOptions {
+            cache: Some(Arc::new(cache)),
+            ..options
+        }
+    }
+
+    fn set_mtime(path: &Path, mtime: SystemTime) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    /// Rewrites `path` with `contents` of the same size, keeping its
+    /// modification time, so only the cache can tell the old count.
+    fn rewrite_unnoticed(path: &Path, contents: &str) {
+        let mtime = fs::metadata(path).unwrap().modified().unwrap();
+        assert_eq!(fs::metadata(path).unwrap().len(), contents.len() as u64);
+        fs::write(path, contents).unwrap();
+        set_mtime(path, mtime);
+    }
+
+    #[test]
+    fn a_saved_count_is_reused_until_the_file_changes() {
+        let dir = TestDir::new();
+        let file = dir.file("a.txt", "1\n2\n");
+        let cache_path = dir.path().join("cache.json");
+        let options = with_cache(&cache_path, CountOptions::default());
+        assert_eq!(count_file(&file, &options).unwrap().lines, 2);
+        options.cache.as_ref().unwrap().save().unwrap();
+
+        // Loaded again, the unchanged stamp gives the old count.
+        rewrite_unnoticed(&file, "1 2\n");
+        let options = with_cache(&cache_path, CountOptions::default());
+        assert_eq!(count_file(&file, &options).unwrap().lines, 2);
+
+        // A new modification time makes it count again.
+        let mtime = fs::metadata(&file).unwrap().modified().unwrap();
+        set_mtime(&file, mtime + Duration::from_secs(1));
+        assert_eq!(count_file(&file, &options).unwrap().lines, 1);
+
+        // So does a new size, even at the same modification time.
+        let mtime = fs::metadata(&file).unwrap().modified().unwrap();
+        fs::write(&file, "1\n2\n3\n").unwrap();
+        set_mtime(&file, mtime);
+        assert_eq!(count_file(&file, &options).unwrap().lines, 3);
+    }
+
+    #[test]
+    fn counts_that_need_more_than_lines_skip_the_cache() {
+        let dir = TestDir::new();
+        let file = dir.file("a.txt", "1\n2\n");
+        let cache_path = dir.path().join("cache.json");
+        let options = with_cache(&cache_path, CountOptions::default());
+        count_file(&file, &options).unwrap();
+        rewrite_unnoticed(&file, "1 2\n");
+
+        let words = CountOptions {
+            words: true,
+            ..options.clone()
+        };
+        assert!(!FileCache::supports(&words));
+        let stats = count_file(&file, &words).unwrap();
+        assert_eq!((stats.lines, stats.words), (1, Some(2)));
+        assert_eq!(count_file(&file, &options).unwrap().lines, 2);
+    }
+
+    #[test]
+    fn a_cache_saved_with_other_counting_options_starts_empty() {
+        let dir = TestDir::new();
+        let file = dir.file("a.txt", "1\n\n");
+        let cache_path = dir.path().join("cache.json");
+        let options = with_cache(&cache_path, CountOptions::default());
+        assert_eq!(count_file(&file, &options).unwrap().lines, 2);
+        options.cache.as_ref().unwrap().save().unwrap();
+
+        let skip_empty = CountOptions {
+            skip_empty: true,
+            ..CountOptions::default()
+        };
+        let options = with_cache(&cache_path, skip_empty);
+        assert_eq!(count_file(&file, &options).unwrap().lines, 1);
+    }
+
+    #[test]
+    fn saving_drops_deleted_files() {
+        let dir = TestDir::new();
+        let kept = dir.file("kept.txt", "1\n");
+        let gone = dir.file("gone.txt", "1\n");
+        let cache_path = dir.path().join("cache.json");
+        let options = with_cache(&cache_path, CountOptions::default());
+        count_file(&kept, &options).unwrap();
+        count_file(&gone, &options).unwrap();
+        fs::remove_file(&gone).unwrap();
+        options.cache.as_ref().unwrap().save().unwrap();
+
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&cache_path).unwrap()).unwrap();
+        let paths: Vec<&str> = saved["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(paths, [kept.to_str().unwrap()]);
+        assert!(!dir.path().join("cache.json.tmp").exists());
+    }
+
+    #[test]
+    fn a_file_that_isnt_a_cache_is_refused() {
+        let dir = TestDir::new();
+        let options = CountOptions::default();
+        assert!(FileCache::load(&dir.path().join("missing.json"), &options).is_ok());
+        for (name, text) in [("bad.json", "{not json"), ("other.json", "{\"files\":[]}")] {
+            let path = dir.file(name, text);
+            let error = FileCache::load(&path, &options).unwrap_err();
+            assert_eq!(error, "not a linecount cache");
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::CountOptions;
+use crate::count::LineSplitter;
+
+/// Comment syntax of a language.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommentSyntax {
+    /// Markers that start a comment running to the end of the line.
+    pub line: Vec<String>,
+    /// Start and end markers of block comments.
+    pub block: Vec<(String, String)>,
+    /// Quotes that delimit string literals, which may contain comment
+    /// markers. Strings end at the matching quote or the end of the line,
+    /// and a backslash escapes the next character.
+    pub strings: Vec<String>,
+}
+
+impl CommentSyntax {
+    fn builtin(line: &[&str], block: &[(&str, &str)], strings: &[&str]) -> Self {
+        CommentSyntax {
+            line: line.iter().map(|m| m.to_string()).collect(),
+            block: block
+                .iter()
+                .map(|(start, end)| (start.to_string(), end.to_string()))
+                .collect(),
+            strings: strings.iter().map(|q| q.to_string()).collect(),
+        }
+    }
+}
+
+static C_STYLE: LazyLock<CommentSyntax> =
+    LazyLock::new(|| CommentSyntax::builtin(&["//"], &[("/*", "*/")], &["\"", "'"]));
+/// Like `C_STYLE`, but a `'` may start a lifetime rather than a char literal.
+static RUST: LazyLock<CommentSyntax> =
+    LazyLock::new(|| CommentSyntax::builtin(&["//"], &[("/*", "*/")], &["\""]));
+static HASH: LazyLock<CommentSyntax> =
+    LazyLock::new(|| CommentSyntax::builtin(&["#"], &[], &["\"", "'"]));
+static PHP: LazyLock<CommentSyntax> =
+    LazyLock::new(|| CommentSyntax::builtin(&["//", "#"], &[("/*", "*/")], &["\"", "'"]));
+static CSS: LazyLock<CommentSyntax> =
+    LazyLock::new(|| CommentSyntax::builtin(&[], &[("/*", "*/")], &["\"", "'"]));
+static SQL: LazyLock<CommentSyntax> =
+    LazyLock::new(|| CommentSyntax::builtin(&["--"], &[("/*", "*/")], &["'", "\""]));
+static MARKUP: LazyLock<CommentSyntax> =
+    LazyLock::new(|| CommentSyntax::builtin(&[], &[("<!--", "-->")], &[]));
+static INI: LazyLock<CommentSyntax> =
+    LazyLock::new(|| CommentSyntax::builtin(&[";", "#"], &[], &[]));
+/// Languages without comments: every non-blank line is code.
+static PLAIN: LazyLock<CommentSyntax> = LazyLock::new(CommentSyntax::default);
+
+/// Looks up the comment syntax for `path` by its extension, preferring
+/// `options.comment_syntax` over the built-in table.
+pub(crate) fn language_for<'a>(path: &Path, options: &'a CountOptions) -> &'a CommentSyntax {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    if let Some(syntax) = ext.as_ref().and_then(|e| options.comment_syntax.get(e)) {
+        return syntax;
+    }
+    match ext.as_deref() {
+        Some(
+            "c" | "h" | "cpp" | "hpp" | "java" | "js" | "ts" | "go" | "swift" | "kt" | "scala"
+            | "scss" | "sass" | "less",
+        ) => &C_STYLE,
+        Some("rs") => &RUST,
+        Some("py" | "rb" | "sh" | "bash" | "r" | "yaml" | "yml" | "toml" | "conf" | "config") => {
+            &HASH
+        }
+        Some("php") => &PHP,
+        Some("css") => &CSS,
+        Some("sql") => &SQL,
+        Some("html" | "htm" | "xml" | "svg" | "md" | "markdown") => &MARKUP,
+        Some("ini") => &INI,
+        _ => &PLAIN,
+    }
+}
+
+/// Blank, comment and code line totals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineClasses {
+    pub blank: u64,
+    pub comment: u64,
+    pub code: u64,
+}
+
+impl LineClasses {
+    pub fn add(&mut self, other: &LineClasses) {
+        self.blank += other.blank;
+        self.comment += other.comment;
+        self.code += other.code;
+    }
+}
+
+/// Sorts lines into blank, comment and code.
+///
+/// The current line is buffered until its terminator arrives, and an open
+/// block comment carries over to the following lines, so input can be fed
+/// in arbitrary chunks. Comment markers inside string literals are only
+/// recognized as such for languages that list their string quotes.
+pub(crate) struct Classifier<'a> {
+    lang: &'a CommentSyntax,
+    splitter: LineSplitter,
+    line: Vec<u8>,
+    /// End marker of the block comment the current position is inside.
+    in_block: Option<&'a str>,
+    classes: LineClasses,
+}
+
+impl<'a> Classifier<'a> {
+    pub(crate) fn new(lang: &'a CommentSyntax, options: &CountOptions) -> Self {
+        Classifier {
+            lang,
+            splitter: LineSplitter::new(options),
+            line: Vec::new(),
+            in_block: None,
+            classes: LineClasses::default(),
+        }
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            if self.splitter.is_terminator(b) {
+                self.end_line();
+            } else {
+                self.line.push(b);
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> LineClasses {
+        if self.splitter.line_open {
+            self.end_line();
+        }
+        self.classes
+    }
+
+    fn end_line(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        match self.classify(&line) {
+            Class::Blank => self.classes.blank += 1,
+            Class::Comment => self.classes.comment += 1,
+            Class::Code => self.classes.code += 1,
+        }
+        // Keep the allocation for the next line.
+        self.line = line;
+        self.line.clear();
+    }
+
+    fn classify(&mut self, line: &[u8]) -> Class {
+        let mut code = false;
+        let mut comment = false;
+        let mut i = 0;
+
+        while i < line.len() {
+            if let Some(end) = self.in_block {
+                comment = true;
+                match find(&line[i..], end.as_bytes()) {
+                    Some(pos) => {
+                        i += pos + end.len();
+                        self.in_block = None;
+                    }
+                    None => i = line.len(),
+                }
+                continue;
+            }
+
+            let rest = &line[i..];
+            if rest[0].is_ascii_whitespace() {
+                i += 1;
+            } else if self
+                .lang
+                .line
+                .iter()
+                .any(|m| rest.starts_with(m.as_bytes()))
+            {
+                comment = true;
+                break;
+            } else if let Some((start, end)) = self
+                .lang
+                .block
+                .iter()
+                .find(|(start, _)| rest.starts_with(start.as_bytes()))
+            {
+                self.in_block = Some(end);
+                comment = true;
+                i += start.len();
+            } else if let Some(quote) = self
+                .lang
+                .strings
+                .iter()
+                .find(|q| rest.starts_with(q.as_bytes()))
+            {
+                code = true;
+                i += quote.len() + string_len(&rest[quote.len()..], quote.as_bytes());
+            } else {
+                code = true;
+                i += 1;
+            }
+        }
+
+        if code {
+            Class::Code
+        } else if comment {
+            Class::Comment
+        } else {
+            Class::Blank
+        }
+    }
+}
+
+enum Class {
+    Blank,
+    Comment,
+    Code,
+}
+
+/// Length of a string literal's contents up to and including the closing
+/// `quote`, or to the end of the line if it isn't closed.
+fn string_len(rest: &[u8], quote: &[u8]) -> usize {
+    let mut i = 0;
+    while i < rest.len() {
+        if rest[i] == b'\\' {
+            i += 2;
+        } else if rest[i..].starts_with(quote) {
+            return i + quote.len();
+        } else {
+            i += 1;
+        }
+    }
+    rest.len()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes(name: &str, text: &str) -> LineClasses {
+        let options = CountOptions::default();
+        let mut classifier = Classifier::new(language_for(Path::new(name), &options), &options);
+        classifier.feed(text.as_bytes());
+        classifier.finish()
+    }
+
+    fn lines(blank: u64, comment: u64, code: u64) -> LineClasses {
+        LineClasses {
+            blank,
+            comment,
+            code,
+        }
+    }
+
+    #[test]
+    fn block_comment_marker_in_a_string_is_code() {
+        let text = "let s = \"/*\";\nlet t = 1;\n";
+        assert_eq!(classes("a.rs", text), lines(0, 0, 2));
+        assert_eq!(
+            classes("a.js", "var s = '/*';\nvar t = 1;\n"),
+            lines(0, 0, 2)
+        );
+        assert_eq!(classes("a.c", "char c = '\"'; /* x\n*/\n"), lines(0, 1, 1));
+    }
+
+    #[test]
+    fn line_comment_marker_in_a_string_is_code() {
+        assert_eq!(classes("a.py", "x = \"#\"\n# real\n"), lines(0, 1, 1));
+        assert_eq!(classes("a.sh", "echo '# not a comment'\n"), lines(0, 0, 1));
+        assert_eq!(classes("a.php", "$u = \"http://x\";\n"), lines(0, 0, 1));
+        assert_eq!(classes("a.sql", "SELECT '--';\n-- real\n"), lines(0, 1, 1));
+    }
+
+    #[test]
+    fn escaped_quote_does_not_end_the_string() {
+        let text = "let s = \"\\\" /*\";\nlet t = 1;\n";
+        assert_eq!(classes("a.rs", text), lines(0, 0, 2));
+    }
+
+    #[test]
+    fn rust_lifetimes_do_not_open_strings() {
+        let text = "fn f<'a>(x: &'a str) { /* start\n end */ }\n";
+        assert_eq!(classes("a.rs", text), lines(0, 0, 2));
+        assert_eq!(
+            classes("a.rs", "impl<'a> A<'a> {} /*\n*/\n"),
+            lines(0, 1, 1)
+        );
+    }
+
+    #[test]
+    fn comment_after_a_closed_string_still_counts() {
+        let text = "// only\n\nlet s = \"x\"; /* a\nb */\n";
+        assert_eq!(classes("a.rs", text), lines(1, 2, 1));
+    }
+}
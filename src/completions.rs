@@ -0,0 +1,165 @@
+//! Shell completion scripts for `--generate-completions`.
+
+/// Every flag the parser accepts. Flags taking a value end in `=`.
+///
+/// Debug builds assert that each flag `main` parses is listed here.
+pub const FLAGS: &[&str] = &[
+    "--buffer-size=",
+    "--skip-empty",
+    "--empty-definition=",
+    "--recursive",
+    "--recursive-paths=",
+    "--max-depth=",
+    "--hidden",
+    "--count-vcs",
+    "--sort-walk=",
+    "--walk-order=",
+    "--exclude=",
+    "--follow-symlinks",
+    "--gitignore",
+    "--no-ignore-files",
+    "--jobs=",
+    "--io-threads=",
+    "--cpu-threads=",
+    "--max-open-files=",
+    "--ext=",
+    "--add-ext=",
+    "--include-ext=",
+    "--exclude-ext=",
+    "--text-ext=",
+    "--binary-ext=",
+    "--case-sensitive-ext",
+    "--name=",
+    "--min-size=",
+    "--max-size=",
+    "--since=",
+    "--file-timeout=",
+    "--dedup-inodes",
+    "--count-all",
+    "--archives",
+    "--skip-binary",
+    "--mmap",
+    "--line-ending=",
+    "--delimiter=",
+    "--break-on=",
+    "--words",
+    "--chars",
+    "--tokens",
+    "--bytes",
+    "--encoding=",
+    "--dedup-lines",
+    "--longest",
+    "--sql-statements",
+    "--jsonl-validate",
+    "--total-cap=",
+    "--max-lines=",
+    "--match=",
+    "--no-match=",
+    "--show-empty-dirs",
+    "--check-endings",
+    "--classify",
+    "--config=",
+    "--verbose",
+    "-v",
+    "-vv",
+    "--progress",
+    "--watch",
+    "--bench",
+    "--list",
+    "--per-file",
+    "--sort=",
+    "--top=",
+    "--subtotals",
+    "--by-ext",
+    "--by-dir",
+    "--summary-only",
+    "--quiet",
+    "-q",
+    "--relative",
+    "--human",
+    "--files-from=",
+    "--format=",
+    "--template=",
+    "--summary-template=",
+    "--baseline=",
+    "--compare",
+    "--git-changed",
+    "--cache=",
+    "--git-changed=",
+    "--output=",
+    "--csv-total",
+    "--help",
+    "-h",
+    "--version",
+    "-V",
+];
+
+/// Whether `arg` is one of `FLAGS`, with or without its value.
+pub fn is_known(arg: &str) -> bool {
+    FLAGS.iter().any(|flag| match flag.strip_suffix('=') {
+        Some(_) => arg.starts_with(flag),
+        None => arg == *flag,
+    })
+}
+
+/// Returns the completion script for `shell`, if it's supported.
+pub fn script(shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash()),
+        "zsh" => Some(zsh()),
+        "fish" => Some(fish()),
+        _ => None,
+    }
+}
+
+fn bash() -> String {
+    format!(
+        r#"_linecount() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{}" -- "$cur"))
+        [[ "${{COMPREPLY[0]}}" == *= ]] && compopt -o nospace
+    fi
+}}
+complete -o default -F _linecount linecount
+"#,
+        FLAGS.join(" ")
+    )
+}
+
+fn zsh() -> String {
+    let (valued, plain): (Vec<&str>, Vec<&str>) = FLAGS.iter().partition(|f| f.ends_with('='));
+    format!(
+        r#"#compdef linecount
+
+if [[ $PREFIX == -* ]]; then
+    compadd -S '' -- {}
+    compadd -- {}
+else
+    _files
+fi
+"#,
+        valued.join(" "),
+        plain.join(" ")
+    )
+}
+
+fn fish() -> String {
+    let mut script = String::new();
+    for flag in FLAGS {
+        let (name, valued) = match flag.strip_suffix('=') {
+            Some(name) => (name, true),
+            None => (*flag, false),
+        };
+        let option = match name.strip_prefix("--") {
+            Some(long) => format!("-l {}", long),
+            None => format!("-s {}", &name[1..]),
+        };
+        script.push_str(&format!(
+            "complete -c linecount {}{}\n",
+            option,
+            if valued { " -r" } else { "" }
+        ));
+    }
+    script
+}
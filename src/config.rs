@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::LineCountError;
+use crate::classify::CommentSyntax;
+
+/// Loads a TOML file that defines the comment syntax of extra languages,
+/// keyed by lowercase extension.
+///
+/// Each table names a language and lists its extensions along with
+/// optional `line` markers, `block` marker pairs and string `strings`
+/// quotes:
+///
+/// ```toml
+/// [jinja]
+/// extensions = ["j2", "jinja"]
+/// block = [["{#", "#}"]]
+/// strings = ['"', "'"]
+/// ```
+pub fn load_comment_syntax(path: &Path) -> Result<HashMap<String, CommentSyntax>, LineCountError> {
+    let text = fs::read_to_string(path).map_err(|e| LineCountError::io(path, e))?;
+    parse(&text).map_err(|message| LineCountError::Config {
+        path: path.to_path_buf(),
+        message,
+    })
+}
+
+/// `~/.config/linecount/languages.toml`, if the home directory is known.
+pub fn default_config_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".config/linecount/languages.toml"))
+}
+
+fn parse(text: &str) -> Result<HashMap<String, CommentSyntax>, String> {
+    let table: Table = text.parse().map_err(|e: toml::de::Error| {
+        let message = e.message().trim().replace('\n', ", ");
+        match e.span() {
+            Some(span) => format!("line {}: {}", line_of(text, span.start), message),
+            None => message,
+        }
+    })?;
+
+    let mut languages = HashMap::new();
+    for (name, value) in &table {
+        let Value::Table(entry) = value else {
+            return Err(format!("`{}` must be a table", name));
+        };
+
+        let mut extensions = Vec::new();
+        let mut syntax = CommentSyntax::default();
+        for (key, value) in entry {
+            let field = format!("{}.{}", name, key);
+            match key.as_str() {
+                "extensions" => extensions = strings(&field, value)?,
+                "line" => syntax.line = strings(&field, value)?,
+                "strings" => syntax.strings = strings(&field, value)?,
+                "block" => syntax.block = pairs(&field, value)?,
+                _ => return Err(format!("unknown key `{}`", field)),
+            }
+        }
+        if extensions.is_empty() {
+            return Err(format!(
+                "`{}.extensions` must list at least one extension",
+                name
+            ));
+        }
+        for ext in extensions {
+            let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+            languages.insert(ext, syntax.clone());
+        }
+    }
+    Ok(languages)
+}
+
+/// Reads a list of non-empty strings.
+fn strings(field: &str, value: &Value) -> Result<Vec<String>, String> {
+    let error = || format!("`{}` must be a list of non-empty strings", field);
+    let Value::Array(items) = value else {
+        return Err(error());
+    };
+    items
+        .iter()
+        .map(|item| match item {
+            Value::String(s) if !s.is_empty() => Ok(s.clone()),
+            _ => Err(error()),
+        })
+        .collect()
+}
+
+/// Reads a list of `[start, end]` marker pairs.
+fn pairs(field: &str, value: &Value) -> Result<Vec<(String, String)>, String> {
+    let error = || format!("`{}` must be a list of [start, end] pairs", field);
+    let Value::Array(items) = value else {
+        return Err(error());
+    };
+    items
+        .iter()
+        .map(|item| {
+            let pair = strings(field, item).map_err(|_| error())?;
+            match <[String; 2]>::try_from(pair) {
+                Ok([start, end]) => Ok((start, end)),
+                Err(_) => Err(error()),
+            }
+        })
+        .collect()
+}
+
+/// 1-based line number of byte `offset` in `text`.
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
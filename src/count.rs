@@ -0,0 +1,978 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::CountOptions;
+use crate::classify::{self, Classifier, LineClasses};
+use crate::dedup::LineHasher;
+use crate::encoding::Decoder;
+use crate::jsonl::{self, JsonlRecords, JsonlValidator};
+use crate::matcher::LineMatcher;
+use crate::sql::{self, SqlCounter};
+
+/// Share of control bytes above which a chunk is considered binary.
+const BINARY_RATIO: f64 = 0.3;
+
+/// UTF-8 byte order mark, skipped at the start of every input.
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Which byte sequences end a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    /// `\n`, which also covers `\r\n` since the `\r` is ignored.
+    #[default]
+    Lf,
+    /// Only `\r\n`; a bare `\n` is part of the line.
+    Crlf,
+    /// Any of `\n`, `\r\n` or a bare `\r`, so mixed files count correctly.
+    Auto,
+    /// An arbitrary byte, e.g. `\0` for `find -print0` output.
+    Delimiter(u8),
+}
+
+/// Which lines `CountOptions::skip_empty` leaves out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyLines {
+    /// Lines of nothing but spaces, tabs and line break characters.
+    #[default]
+    Whitespace,
+    /// Only lines with no bytes at all before their line break.
+    Strict,
+}
+
+/// Raw totals produced by the counting loops.
+#[derive(Debug, Default)]
+pub(crate) struct Counts {
+    pub lines: u64,
+    pub words: u64,
+    pub chars: u64,
+    pub tokens: u64,
+    pub classes: LineClasses,
+    /// Length and 1-based number of the longest line, if there was one.
+    pub longest: Option<(u64, u64)>,
+    pub endings: Endings,
+    /// Set for JSON Lines files with `CountOptions::jsonl_validate`.
+    pub jsonl: Option<JsonlRecords>,
+    /// Hashes of the distinct lines, with `CountOptions::dedup_lines`.
+    pub line_hashes: HashSet<u64>,
+    /// Bytes read and the number of chunks they came in, for
+    /// `CountOptions::bytes` and `CountOptions::verbose`.
+    pub bytes: u64,
+    pub chunks: u64,
+    /// Set when the input was skipped for looking binary.
+    pub binary: bool,
+    /// Set when counting stopped at `CountOptions::max_lines` before the
+    /// end of the input.
+    pub truncated: bool,
+}
+
+/// How many lines of an input ended in a bare `\n` and how many in `\r\n`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Endings {
+    pub lf: u64,
+    pub crlf: u64,
+}
+
+impl Endings {
+    /// Returns true if both kinds of line ending occur.
+    pub fn is_mixed(&self) -> bool {
+        self.lf > 0 && self.crlf > 0
+    }
+
+    /// `"lf"`, `"crlf"`, `"mixed"`, or `"none"` for input without line
+    /// breaks.
+    pub fn kind(&self) -> &'static str {
+        match (self.lf > 0, self.crlf > 0) {
+            (true, true) => "mixed",
+            (true, false) => "lf",
+            (false, true) => "crlf",
+            (false, false) => "none",
+        }
+    }
+
+    pub fn add(&mut self, other: &Endings) {
+        self.lf += other.lf;
+        self.crlf += other.crlf;
+    }
+}
+
+/// Tells `\r\n` apart from bare `\n`, including pairs split across reads.
+struct EndingTracker {
+    prev_cr: bool,
+}
+
+impl EndingTracker {
+    fn update(&mut self, chunk: &[u8], endings: &mut Endings) {
+        let Some(&last) = chunk.last() else {
+            return;
+        };
+        let newlines = bytecount::count(chunk, b'\n') as u64;
+        let mut crlf = chunk.windows(2).filter(|w| w == b"\r\n").count() as u64;
+        if self.prev_cr && chunk[0] == b'\n' {
+            crlf += 1;
+        }
+        self.prev_cr = last == b'\r';
+        endings.crlf += crlf;
+        endings.lf += newlines - crlf;
+    }
+}
+
+/// Counts words, characters and tokens alongside lines when requested.
+struct TextCounter {
+    words: bool,
+    chars: bool,
+    tokens: bool,
+    in_word: bool,
+    /// Whether the last byte was part of a run of identifier characters.
+    in_ident: bool,
+}
+
+impl TextCounter {
+    fn new(options: &CountOptions) -> Self {
+        TextCounter {
+            words: options.words,
+            chars: options.chars,
+            tokens: options.tokens,
+            in_word: false,
+            in_ident: false,
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8], counts: &mut Counts) {
+        if self.words {
+            for &b in chunk {
+                let space = b.is_ascii_whitespace() || b == 0x0b;
+                if !space && !self.in_word {
+                    counts.words += 1;
+                }
+                self.in_word = !space;
+            }
+        }
+        // Counting every byte that isn't a UTF-8 continuation byte counts
+        // each scalar value exactly once, even when a character is split
+        // across two reads.
+        if self.chars {
+            counts.chars += chunk.iter().filter(|&&b| b & 0xC0 != 0x80).count() as u64;
+        }
+        // A rough stand-in for a real tokenizer: every run of letters,
+        // digits and underscores is one token, and so is every other
+        // character that isn't whitespace. Bytes above 0x7F count as
+        // letters, so non-ASCII words stay whole.
+        if self.tokens {
+            for &b in chunk {
+                if b.is_ascii_whitespace() || b == 0x0b {
+                    self.in_ident = false;
+                } else if b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80 {
+                    if !self.in_ident {
+                        counts.tokens += 1;
+                    }
+                    self.in_ident = true;
+                } else {
+                    counts.tokens += 1;
+                    self.in_ident = false;
+                }
+            }
+        }
+    }
+}
+
+/// Finds the longest line, measured in bytes or, with `chars`, in UTF-8
+/// characters. Line break characters don't count towards the length.
+struct LongestTracker {
+    splitter: LineSplitter,
+    chars: bool,
+    len: u64,
+    line: u64,
+    best: Option<(u64, u64)>,
+}
+
+impl LongestTracker {
+    fn new(options: &CountOptions) -> Self {
+        LongestTracker {
+            splitter: LineSplitter::new(options),
+            chars: options.chars,
+            len: 0,
+            line: 1,
+            best: None,
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            if self.splitter.is_terminator(b) {
+                self.end_line();
+            } else if b != b'\n' && b != b'\r' && (!self.chars || b & 0xC0 != 0x80) {
+                self.len += 1;
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if self.best.is_none_or(|(len, _)| self.len > len) {
+            self.best = Some((self.len, self.line));
+        }
+        self.len = 0;
+        self.line += 1;
+    }
+
+    fn finish(mut self) -> Option<(u64, u64)> {
+        if self.splitter.line_open {
+            self.end_line();
+        }
+        self.best
+    }
+}
+
+/// Finds where `CountOptions::max_lines` cuts off the input.
+struct LineCap {
+    splitter: LineSplitter,
+    remaining: u64,
+    prev_cr: bool,
+}
+
+impl LineCap {
+    /// Returns how many leading bytes of `chunk` are within the cap.
+    fn take(&mut self, chunk: &[u8]) -> usize {
+        for (i, &b) in chunk.iter().enumerate() {
+            if self.remaining == 0 {
+                // In auto mode the `\n` of a `\r\n` still ends the last line.
+                let pair = self.splitter.ending == LineEnding::Auto && self.prev_cr && b == b'\n';
+                if !pair {
+                    return i;
+                }
+            } else if self.splitter.is_terminator(b) {
+                self.remaining -= 1;
+            }
+            self.prev_cr = b == b'\r';
+        }
+        chunk.len()
+    }
+}
+
+/// Tracks line terminators across buffer boundaries.
+pub(crate) struct LineSplitter {
+    ending: LineEnding,
+    /// `CountOptions::extra_breaks`.
+    extra: Vec<u8>,
+    prev_cr: bool,
+    /// Whether bytes have been seen since the last terminator.
+    pub line_open: bool,
+}
+
+impl LineSplitter {
+    pub(crate) fn new(options: &CountOptions) -> Self {
+        LineSplitter {
+            ending: options.line_ending,
+            extra: options.extra_breaks.clone(),
+            prev_cr: false,
+            line_open: false,
+        }
+    }
+
+    /// Counts the terminators in `chunk`.
+    fn count(&mut self, chunk: &[u8]) -> u64 {
+        let delimiter = match self.ending {
+            LineEnding::Lf => b'\n',
+            LineEnding::Delimiter(d) => d,
+            _ => return chunk.iter().filter(|&&b| self.is_terminator(b)).count() as u64,
+        };
+
+        if let Some(&last) = chunk.last() {
+            self.line_open = last != delimiter && !self.extra.contains(&last);
+        }
+        let extra: usize = self
+            .extra
+            .iter()
+            .filter(|&&b| b != delimiter)
+            .map(|&b| bytecount::count(chunk, b))
+            .sum();
+        (bytecount::count(chunk, delimiter) + extra) as u64
+    }
+
+    /// Returns true if `b` ends a line.
+    ///
+    /// In auto mode the `\n` of a `\r\n` pair returns false, since the line
+    /// was already ended by the `\r`.
+    pub(crate) fn is_terminator(&mut self, b: u8) -> bool {
+        let prev_cr = std::mem::replace(&mut self.prev_cr, b == b'\r');
+        let (ends, closes) = match self.ending {
+            LineEnding::Lf => (b == b'\n', b == b'\n'),
+            LineEnding::Delimiter(d) => (b == d, b == d),
+            LineEnding::Crlf => {
+                let crlf = b == b'\n' && prev_cr;
+                (crlf, crlf)
+            }
+            LineEnding::Auto => (
+                b == b'\r' || (b == b'\n' && !prev_cr),
+                b == b'\r' || b == b'\n',
+            ),
+        };
+        let extra = self.extra.contains(&b);
+        let (ends, closes) = (ends || extra, closes || extra);
+        self.line_open = !closes;
+        ends
+    }
+}
+
+/// Splits input fed in arbitrary chunks into whole lines, for the counts
+/// that need to look at what a line says.
+pub(crate) struct LineBuffer {
+    splitter: LineSplitter,
+    line: Vec<u8>,
+    prev_cr: bool,
+}
+
+impl LineBuffer {
+    pub(crate) fn new(options: &CountOptions) -> Self {
+        LineBuffer {
+            splitter: LineSplitter::new(options),
+            line: Vec::new(),
+            prev_cr: false,
+        }
+    }
+
+    /// Calls `f` with each line that `chunk` completes, without its
+    /// terminator or a `\r` right before it.
+    pub(crate) fn feed(&mut self, chunk: &[u8], mut f: impl FnMut(&[u8])) {
+        for &b in chunk {
+            let prev_cr = std::mem::replace(&mut self.prev_cr, b == b'\r');
+            if self.splitter.is_terminator(b) {
+                self.end_line(&mut f);
+            } else if !(b == b'\n' && prev_cr && matches!(self.splitter.ending, LineEnding::Auto)) {
+                // In auto mode the `\n` of a `\r\n` is left over from the
+                // line the `\r` ended.
+                self.line.push(b);
+            }
+        }
+    }
+
+    /// Calls `f` with the last line, if it wasn't terminated.
+    pub(crate) fn finish(mut self, mut f: impl FnMut(&[u8])) {
+        if self.splitter.line_open {
+            self.end_line(&mut f);
+        }
+    }
+
+    fn end_line(&mut self, f: &mut impl FnMut(&[u8])) {
+        f(self.line.strip_suffix(b"\r").unwrap_or(&self.line));
+        self.line.clear();
+    }
+}
+
+/// Returns true if `line` is empty by the definition of `empty`.
+pub(crate) fn is_blank(line: &[u8], empty: EmptyLines) -> bool {
+    match empty {
+        EmptyLines::Whitespace => line.iter().all(u8::is_ascii_whitespace),
+        EmptyLines::Strict => line.is_empty(),
+    }
+}
+
+/// Counts one input that is fed in consecutive chunks.
+///
+/// Both the buffered reader and the memory-mapped path go through this, so
+/// they produce identical results for the same bytes.
+pub(crate) struct Counter<'a> {
+    options: &'a CountOptions,
+    counts: Counts,
+    splitter: LineSplitter,
+    text: TextCounter,
+    classifier: Option<Classifier<'a>>,
+    longest: Option<LongestTracker>,
+    /// Replaces the line count of SQL files with `sql_statements`.
+    sql: Option<SqlCounter>,
+    endings: Option<EndingTracker>,
+    /// Replaces the line count with the number of matching lines.
+    matcher: Option<LineMatcher<'a>>,
+    dedup: Option<LineHasher>,
+    jsonl: Option<JsonlValidator>,
+    /// Converts non-UTF-8 input, into `decoded`.
+    decoder: Option<Decoder>,
+    decoded: Vec<u8>,
+    cap: Option<LineCap>,
+    /// Whether the current line has content that makes it non-empty
+    /// (`skip_empty`).
+    has_data: bool,
+    started: bool,
+    /// Whether to look at the start of the input for `skip_binary`.
+    detect_binary: bool,
+    binary: bool,
+    /// How many leading bytes matched the BOM so far, or `None` once the
+    /// start of the input has been dealt with.
+    bom_matched: Option<usize>,
+}
+
+impl<'a> Counter<'a> {
+    /// `path` selects the language used by `--classify`.
+    pub(crate) fn new(path: &Path, options: &'a CountOptions) -> Self {
+        let classifier = options
+            .classify
+            .then(|| Classifier::new(classify::language_for(path, options), options));
+
+        Counter {
+            options,
+            counts: Counts::default(),
+            splitter: LineSplitter::new(options),
+            text: TextCounter::new(options),
+            classifier,
+            longest: options.longest.then(|| LongestTracker::new(options)),
+            sql: (options.sql_statements && sql::is_sql(path)).then(SqlCounter::new),
+            endings: options
+                .check_endings
+                .then_some(EndingTracker { prev_cr: false }),
+            matcher: (options.matching.is_some() || options.not_matching.is_some())
+                .then(|| LineMatcher::new(options)),
+            dedup: options.dedup_lines.then(|| LineHasher::new(options)),
+            jsonl: (options.jsonl_validate && jsonl::is_jsonl(path))
+                .then(|| JsonlValidator::new(options)),
+            decoder: Decoder::new(options.encoding),
+            decoded: Vec::new(),
+            cap: options.max_lines.map(|max| LineCap {
+                splitter: LineSplitter::new(options),
+                remaining: max,
+                prev_cr: false,
+            }),
+            has_data: false,
+            started: false,
+            detect_binary: options.skip_binary && !options.is_forced_text(path),
+            binary: false,
+            bom_matched: Some(0),
+        }
+    }
+
+    /// Counts the next chunk of input.
+    ///
+    /// Returns false once the input has turned out to be binary or hit the
+    /// line cap, and doesn't need to be read any further.
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> bool {
+        self.counts.bytes += chunk.len() as u64;
+        self.counts.chunks += 1;
+        let Some(decoder) = &mut self.decoder else {
+            return self.feed_text(chunk);
+        };
+        let mut text = std::mem::take(&mut self.decoded);
+        text.clear();
+        decoder.decode(chunk, &mut text);
+        let more = self.feed_text(&text);
+        self.decoded = text;
+        more
+    }
+
+    /// Counts the next chunk of UTF-8, or of bytes in no particular
+    /// encoding.
+    fn feed_text(&mut self, chunk: &[u8]) -> bool {
+        // A UTF-16 chunk of one byte decodes to nothing.
+        if chunk.is_empty() {
+            return true;
+        }
+        if !self.started {
+            self.started = true;
+            // Only inspect what a single buffered read would have returned.
+            let head = &chunk[..chunk.len().min(self.options.buffer_size)];
+            if self.detect_binary && is_binary(head) {
+                self.binary = true;
+                return false;
+            }
+        }
+
+        let Some(matched) = self.bom_matched else {
+            self.count_bytes(chunk);
+            return !self.counts.truncated;
+        };
+
+        // The BOM may be split across reads, so the bytes matching it are
+        // held back until it's clear whether they are one.
+        let n = chunk
+            .iter()
+            .zip(&BOM[matched..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if matched + n == BOM.len() {
+            self.bom_matched = None;
+            self.count_bytes(&chunk[n..]);
+        } else if n == chunk.len() {
+            self.bom_matched = Some(matched + n);
+        } else {
+            self.bom_matched = None;
+            self.count_bytes(&BOM[..matched]);
+            self.count_bytes(chunk);
+        }
+        !self.counts.truncated
+    }
+
+    fn count_bytes(&mut self, chunk: &[u8]) {
+        let chunk = match &mut self.cap {
+            Some(cap) => {
+                let n = cap.take(chunk);
+                if n < chunk.len() {
+                    self.counts.truncated = true;
+                }
+                &chunk[..n]
+            }
+            None => chunk,
+        };
+        if self.options.skip_empty {
+            self.count_nonempty_lines(chunk);
+        } else {
+            self.count_newlines_fast(chunk);
+        }
+        self.text.update(chunk, &mut self.counts);
+        if let Some(classifier) = &mut self.classifier {
+            classifier.feed(chunk);
+        }
+        if let Some(longest) = &mut self.longest {
+            longest.update(chunk);
+        }
+        if let Some(sql) = &mut self.sql {
+            sql.feed(chunk);
+        }
+        if let Some(endings) = &mut self.endings {
+            endings.update(chunk, &mut self.counts.endings);
+        }
+        if let Some(matcher) = &mut self.matcher {
+            matcher.feed(chunk);
+        }
+        if let Some(dedup) = &mut self.dedup {
+            dedup.feed(chunk);
+        }
+        if let Some(jsonl) = &mut self.jsonl {
+            jsonl.feed(chunk);
+        }
+    }
+
+    fn count_newlines_fast(&mut self, chunk: &[u8]) {
+        self.counts.lines += self.splitter.count(chunk);
+    }
+
+    fn count_nonempty_lines(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            if self.splitter.is_terminator(b) {
+                if self.has_data {
+                    self.counts.lines += 1;
+                }
+                self.has_data = false;
+                continue;
+            }
+            match b {
+                b'\n' | b'\r' => {}
+                b' ' | b'\t' if self.options.empty_lines == EmptyLines::Whitespace => {}
+                _ => self.has_data = true,
+            }
+        }
+    }
+
+    /// Accounts for a final line without a terminator and returns the totals.
+    pub(crate) fn finish(mut self) -> Counts {
+        if self.binary {
+            return Counts {
+                binary: true,
+                ..Counts::default()
+            };
+        }
+        if let Some(decoder) = &mut self.decoder {
+            let mut rest = Vec::new();
+            decoder.finish(&mut rest);
+            self.feed_text(&rest);
+        }
+        // An input that is a proper prefix of the BOM is just text.
+        if let Some(matched) = self.bom_matched.take() {
+            self.count_bytes(&BOM[..matched]);
+        }
+
+        let line_open = if self.options.skip_empty {
+            self.has_data
+        } else {
+            self.splitter.line_open
+        };
+        if line_open {
+            self.counts.lines += 1;
+        }
+        if let Some(classifier) = self.classifier {
+            self.counts.classes = classifier.finish();
+        }
+        if let Some(longest) = self.longest {
+            self.counts.longest = longest.finish();
+        }
+        if let Some(sql) = self.sql {
+            self.counts.lines = sql.finish();
+        }
+        if let Some(matcher) = self.matcher {
+            self.counts.lines = matcher.finish();
+        }
+        if let Some(dedup) = self.dedup {
+            self.counts.line_hashes = dedup.finish();
+        }
+        if let Some(jsonl) = self.jsonl {
+            self.counts.jsonl = Some(jsonl.finish());
+        }
+        self.counts
+    }
+}
+
+/// Counts everything `reader` yields, reading through `buffer`.
+pub(crate) fn count_stream<R: Read>(
+    reader: &mut R,
+    path: &Path,
+    buffer: &mut [u8],
+    options: &CountOptions,
+) -> io::Result<Counts> {
+    let mut counter = Counter::new(path, options);
+
+    loop {
+        let n = reader.read(buffer)?;
+        if n == 0 || !counter.feed(&buffer[..n]) {
+            break;
+        }
+    }
+
+    Ok(counter.finish())
+}
+
+/// Counts `file` by memory-mapping it in one piece.
+///
+/// Returns `None` when the file can't be mapped, such as empty files, pipes
+/// or when mapping fails, so the caller can fall back to [`count_stream`].
+pub(crate) fn count_mapped(file: &File, path: &Path, options: &CountOptions) -> Option<Counts> {
+    if file.metadata().ok()?.len() == 0 {
+        return None;
+    }
+
+    // SAFETY: the mapping is only read while `file` is open. If another
+    // process truncates the file meanwhile the read may fault, which is the
+    // accepted tradeoff of opting into `--mmap`.
+    let map = unsafe { Mmap::map(file) }.ok()?;
+
+    Some(count_slice(&map, path, options))
+}
+
+/// Counts input that is already in memory.
+pub(crate) fn count_slice(data: &[u8], path: &Path, options: &CountOptions) -> Counts {
+    let mut counter = Counter::new(path, options);
+    counter.feed(data);
+    counter.finish()
+}
+
+/// Guesses whether a chunk of data comes from a binary file.
+///
+/// Any NUL byte, or too many control characters other than common
+/// whitespace, marks the data as binary. Bytes above 0x7F are treated as
+/// text so UTF-8 isn't misdetected.
+pub(crate) fn is_binary(chunk: &[u8]) -> bool {
+    if chunk.contains(&0) {
+        return true;
+    }
+
+    let control = chunk
+        .iter()
+        .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || b == 0x7f)
+        .count();
+
+    control as f64 > chunk.len() as f64 * BINARY_RATIO
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use std::path::Path;
+
+    use super::*;
+    use crate::Encoding;
+
+    /// A reader that remembers how often it was read from.
+    struct CountingReader<R> {
+        inner: R,
+        reads: usize,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            self.inner.read(buf)
+        }
+    }
+
+    /// Counts `data` read `chunk` bytes at a time.
+    fn count_in_chunks(data: &[u8], chunk: usize, options: &CountOptions) -> Counts {
+        let mut buffer = vec![0u8; chunk];
+        count_stream(&mut &data[..], Path::new("x.txt"), &mut buffer, options).unwrap()
+    }
+
+    fn lines(data: &[u8], chunk: usize) -> u64 {
+        count_in_chunks(data, chunk, &CountOptions::default()).lines
+    }
+
+    #[test]
+    fn a_final_line_without_a_newline_counts() {
+        for chunk in [1, 2, 8192] {
+            assert_eq!(lines(b"", chunk), 0);
+            assert_eq!(lines(b"a", chunk), 1);
+            assert_eq!(lines(b"a\n", chunk), 1);
+            assert_eq!(lines(b"a\nb", chunk), 2);
+            assert_eq!(lines(b"\n\n", chunk), 2);
+        }
+    }
+
+    #[test]
+    fn crlf_ends_one_line() {
+        for line_ending in [LineEnding::Lf, LineEnding::Crlf, LineEnding::Auto] {
+            let options = CountOptions {
+                line_ending,
+                ..CountOptions::default()
+            };
+            for chunk in [1, 2, 3, 8192] {
+                assert_eq!(count_in_chunks(b"a\r\nb\r\n", chunk, &options).lines, 2);
+            }
+        }
+    }
+
+    #[test]
+    fn crlf_split_across_reads_ends_one_line() {
+        // With 2-byte reads the \r ends the first read and the \n starts
+        // the second.
+        let data = b"a\r\nb\r\nc";
+        for line_ending in [LineEnding::Crlf, LineEnding::Auto] {
+            let options = CountOptions {
+                line_ending,
+                ..CountOptions::default()
+            };
+            assert_eq!(count_in_chunks(data, 2, &options).lines, 3);
+        }
+        let skip_empty = CountOptions {
+            skip_empty: true,
+            line_ending: LineEnding::Auto,
+            ..CountOptions::default()
+        };
+        assert_eq!(count_in_chunks(b"a\r\n\r\nb\r\n", 2, &skip_empty).lines, 2);
+    }
+
+    #[test]
+    fn crlf_mode_keeps_a_bare_lf_in_the_line() {
+        let options = CountOptions {
+            line_ending: LineEnding::Crlf,
+            ..CountOptions::default()
+        };
+        assert_eq!(count_in_chunks(b"a\nb\r\n", 1, &options).lines, 1);
+    }
+
+    #[test]
+    fn a_leading_bom_is_not_a_char() {
+        let options = CountOptions {
+            chars: true,
+            ..CountOptions::default()
+        };
+        let plain = "h\u{e9}llo\nw\u{f6}rld\n".as_bytes();
+        let with_bom = [BOM, plain].concat();
+        let expected = count_in_chunks(plain, 8192, &options).chars;
+        assert_eq!(expected, 12);
+        for chunk in [1, 2, 3, 8192] {
+            assert_eq!(count_in_chunks(&with_bom, chunk, &options).chars, expected);
+        }
+        assert_eq!(
+            count_slice(&with_bom, Path::new("x.txt"), &options).chars,
+            expected
+        );
+    }
+
+    #[test]
+    fn only_a_leading_bom_is_skipped() {
+        let options = CountOptions {
+            chars: true,
+            ..CountOptions::default()
+        };
+        let data = [b"a\n", BOM].concat();
+        assert_eq!(count_in_chunks(&data, 1, &options).chars, 3);
+    }
+
+    #[test]
+    fn a_partial_bom_is_counted_as_text() {
+        let options = CountOptions {
+            words: true,
+            ..CountOptions::default()
+        };
+        for chunk in [1, 2, 8192] {
+            let counts = count_in_chunks(b"\xEF\xBB x\n", chunk, &options);
+            assert_eq!((counts.lines, counts.words), (1, 2));
+            // Input that ends partway into a BOM is a line of its own.
+            assert_eq!(count_in_chunks(b"\xEF\xBB", chunk, &options).lines, 1);
+            assert_eq!(count_in_chunks(b"a\n\xEF", chunk, &options).lines, 2);
+        }
+    }
+
+    #[test]
+    fn form_feeds_only_break_lines_when_asked() {
+        let data = b"page one\x0cpage two\npage three\x0c\x0bend";
+        assert_eq!(lines(data, 8192), 2);
+        let options = CountOptions {
+            extra_breaks: vec![0x0c],
+            ..CountOptions::default()
+        };
+        for chunk in [1, 2, 8192] {
+            assert_eq!(count_in_chunks(data, chunk, &options).lines, 4);
+        }
+        let options = CountOptions {
+            extra_breaks: vec![0x0c, 0x0b],
+            ..CountOptions::default()
+        };
+        assert_eq!(count_in_chunks(data, 1, &options).lines, 5);
+    }
+
+    #[test]
+    fn a_line_of_spaces_is_only_empty_by_the_whitespace_definition() {
+        let data = b"a\n   \n\t\n\nb\n";
+        let whitespace = CountOptions {
+            skip_empty: true,
+            empty_lines: EmptyLines::Whitespace,
+            ..CountOptions::default()
+        };
+        let strict = CountOptions {
+            empty_lines: EmptyLines::Strict,
+            ..whitespace.clone()
+        };
+        for chunk in [1, 8192] {
+            assert_eq!(count_in_chunks(data, chunk, &whitespace).lines, 2);
+            assert_eq!(count_in_chunks(data, chunk, &strict).lines, 4);
+        }
+    }
+
+    #[test]
+    fn max_lines_stops_reading_soon_after_the_cap() {
+        let data = "a\n".repeat(100_000);
+        let options = CountOptions {
+            max_lines: Some(3000),
+            buffer_size: 1024,
+            ..CountOptions::default()
+        };
+        let mut reader = CountingReader {
+            inner: data.as_bytes(),
+            reads: 0,
+        };
+        let mut buffer = vec![0u8; options.buffer_size];
+        let counts = count_stream(&mut reader, Path::new("x.txt"), &mut buffer, &options).unwrap();
+        assert_eq!(counts.lines, 3000);
+        assert!(counts.truncated);
+        // 3000 lines are 6000 bytes, so 6 reads of 1 KB, and one more for
+        // the chunk the cap falls in.
+        assert!(reader.reads <= 7, "read {} times", reader.reads);
+    }
+
+    #[test]
+    fn crlf_lines_match_without_their_cr() {
+        let data = b"ok\r\nno\r\nok\r\nok";
+        let ok = regex::bytes::Regex::new("^ok$").unwrap();
+        let cr = regex::bytes::Regex::new("\r").unwrap();
+        for line_ending in [LineEnding::Lf, LineEnding::Crlf, LineEnding::Auto] {
+            let options = CountOptions {
+                line_ending,
+                matching: Some(ok.clone()),
+                ..CountOptions::default()
+            };
+            let not_cr = CountOptions {
+                line_ending,
+                not_matching: Some(cr.clone()),
+                ..CountOptions::default()
+            };
+            // A chunk of 3 ends right after the first `\r`.
+            for chunk in [1, 2, 3, 8192] {
+                let context = format!("{:?}/{}", line_ending, chunk);
+                assert_eq!(
+                    count_in_chunks(data, chunk, &options).lines,
+                    3,
+                    "{}",
+                    context
+                );
+                assert_eq!(
+                    count_in_chunks(data, chunk, &not_cr).lines,
+                    4,
+                    "{}",
+                    context
+                );
+            }
+        }
+    }
+
+    fn utf16(text: &str, encoding: Encoding) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| match encoding {
+                Encoding::Utf16Be => unit.to_be_bytes(),
+                _ => unit.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    fn decoded(data: &[u8], chunk: usize, encoding: Encoding) -> (u64, u64) {
+        let options = CountOptions {
+            chars: true,
+            encoding,
+            ..CountOptions::default()
+        };
+        let counts = count_in_chunks(data, chunk, &options);
+        (counts.lines, counts.chars)
+    }
+
+    #[test]
+    fn utf16_counts_lines_and_chars() {
+        let text = "héllo\nwörld\n\nend";
+        for encoding in [Encoding::Utf16Le, Encoding::Utf16Be] {
+            let data = utf16(text, encoding);
+            for chunk in [1, 2, 3] {
+                assert_eq!(
+                    decoded(&data, chunk, encoding),
+                    (4, 16),
+                    "{:?}/{}",
+                    encoding,
+                    chunk
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn utf16_code_units_and_surrogate_pairs_split_across_reads() {
+        // Odd chunk sizes split code units, and every size splits the pairs.
+        let text = "a\u{1D11E}\n\u{1F600}\u{1F600}b\n";
+        assert_eq!(text.encode_utf16().count(), 10);
+        for encoding in [Encoding::Utf16Le, Encoding::Utf16Be] {
+            let data = utf16(text, encoding);
+            for chunk in [1, 2, 3] {
+                assert_eq!(
+                    decoded(&data, chunk, encoding),
+                    (2, 7),
+                    "{:?}/{}",
+                    encoding,
+                    chunk
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn utf16_trailing_odd_byte_is_a_replacement_char() {
+        for encoding in [Encoding::Utf16Le, Encoding::Utf16Be] {
+            let mut data = utf16("ab\ncd", encoding);
+            data.push(b'e');
+            for chunk in [1, 2, 3] {
+                assert_eq!(
+                    decoded(&data, chunk, encoding),
+                    (2, 6),
+                    "{:?}/{}",
+                    encoding,
+                    chunk
+                );
+            }
+            // A high surrogate left waiting at the end goes the same way.
+            let mut data = utf16("ab\n", encoding);
+            data.extend_from_slice(&utf16("\u{1D11E}", encoding)[..2]);
+            for chunk in [1, 2, 3] {
+                assert_eq!(
+                    decoded(&data, chunk, encoding),
+                    (2, 4),
+                    "{:?}/{}",
+                    encoding,
+                    chunk
+                );
+            }
+        }
+    }
+}
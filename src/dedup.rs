@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hasher};
+
+use crate::CountOptions;
+use crate::count::{self, EmptyLines, LineBuffer};
+
+/// Collects a 64-bit hash of every distinct line for
+/// `CountOptions::dedup_lines`.
+///
+/// Only the hashes are kept, so memory grows with the number of distinct
+/// lines but not with their length. Two different lines sharing a hash
+/// would be counted once, which is vanishingly unlikely at 64 bits.
+pub(crate) struct LineHasher {
+    skip_empty: Option<EmptyLines>,
+    lines: LineBuffer,
+    hashes: HashSet<u64>,
+}
+
+impl LineHasher {
+    pub(crate) fn new(options: &CountOptions) -> Self {
+        LineHasher {
+            skip_empty: options.skip_empty.then_some(options.empty_lines),
+            lines: LineBuffer::new(options),
+            hashes: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        let (skip_empty, hashes) = (self.skip_empty, &mut self.hashes);
+        self.lines
+            .feed(chunk, |line| insert(hashes, skip_empty, line));
+    }
+
+    pub(crate) fn finish(self) -> HashSet<u64> {
+        let (skip_empty, mut hashes) = (self.skip_empty, self.hashes);
+        self.lines
+            .finish(|line| insert(&mut hashes, skip_empty, line));
+        hashes
+    }
+}
+
+fn insert(hashes: &mut HashSet<u64>, skip_empty: Option<EmptyLines>, line: &[u8]) {
+    if skip_empty.is_some_and(|empty| count::is_blank(line, empty)) {
+        return;
+    }
+    let mut hasher = DefaultHasher::new();
+    hasher.write(line);
+    hashes.insert(hasher.finish());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LineEnding;
+
+    /// Distinct lines in `text`, checked to be the same for every chunk
+    /// size.
+    fn unique_with(text: &[u8], options: &CountOptions) -> usize {
+        let counts: Vec<usize> = [1, 2, 3, text.len().max(1)]
+            .iter()
+            .map(|&size| {
+                let mut hasher = LineHasher::new(options);
+                for chunk in text.chunks(size) {
+                    hasher.feed(chunk);
+                }
+                hasher.finish().len()
+            })
+            .collect();
+        assert!(counts.iter().all(|&n| n == counts[0]), "{:?}", counts);
+        counts[0]
+    }
+
+    fn unique(text: &[u8]) -> usize {
+        unique_with(text, &CountOptions::default())
+    }
+
+    #[test]
+    fn duplicates_split_across_chunks_are_counted_once() {
+        assert_eq!(unique(b"abc\nabc\nabd\nabc\n"), 2);
+        assert_eq!(unique(b"longer line\nlonger line\nlonger\n"), 2);
+        assert_eq!(unique(b""), 0);
+    }
+
+    #[test]
+    fn crlf_lines_equal_their_lf_twins() {
+        // With `LineEnding::Crlf` a lone `\n` doesn't end a line at all.
+        for line_ending in [LineEnding::Lf, LineEnding::Auto] {
+            let options = CountOptions {
+                line_ending,
+                ..CountOptions::default()
+            };
+            assert_eq!(
+                unique_with(b"a\r\nb\r\na\nb\n", &options),
+                2,
+                "{:?}",
+                line_ending
+            );
+        }
+    }
+
+    #[test]
+    fn a_final_unterminated_line_counts() {
+        assert_eq!(unique(b"a\nb"), 2);
+        assert_eq!(unique(b"a\na"), 1);
+        assert_eq!(unique(b"a\r\na"), 1);
+    }
+
+    #[test]
+    fn blank_lines_are_one_line_unless_skipped() {
+        assert_eq!(unique(b"a\n\n\n  \n"), 3);
+        let options = CountOptions {
+            skip_empty: true,
+            ..CountOptions::default()
+        };
+        assert_eq!(unique_with(b"a\n\n\n  \n", &options), 1);
+    }
+}
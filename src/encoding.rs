@@ -0,0 +1,102 @@
+/// Text encoding of the input. Anything other than UTF-8 is converted to
+/// UTF-8 before it's counted, so every count sees characters rather than
+/// code units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO 8859-1, where every byte is the character of the same value.
+    Latin1,
+}
+
+/// Converts input fed in arbitrary chunks to UTF-8, keeping code units and
+/// surrogate pairs that are split across reads.
+pub(crate) struct Decoder {
+    encoding: Encoding,
+    /// First byte of a UTF-16 code unit whose second byte is still to come.
+    odd: Option<u8>,
+    /// High surrogate waiting for its low half.
+    high: Option<u16>,
+}
+
+impl Decoder {
+    /// Returns `None` for UTF-8, which needs no conversion.
+    pub(crate) fn new(encoding: Encoding) -> Option<Self> {
+        (encoding != Encoding::Utf8).then_some(Decoder {
+            encoding,
+            odd: None,
+            high: None,
+        })
+    }
+
+    /// Appends the UTF-8 for `chunk` to `out`. Unpaired surrogates become
+    /// U+FFFD.
+    pub(crate) fn decode(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        let big_endian = match self.encoding {
+            Encoding::Utf8 => {
+                out.extend_from_slice(chunk);
+                return;
+            }
+            Encoding::Latin1 => {
+                for &b in chunk {
+                    push_char(out, char::from(b));
+                }
+                return;
+            }
+            Encoding::Utf16Le => false,
+            Encoding::Utf16Be => true,
+        };
+
+        for &b in chunk {
+            let Some(first) = self.odd.take() else {
+                self.odd = Some(b);
+                continue;
+            };
+            let bytes = [first, b];
+            let unit = if big_endian {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            };
+            self.push_unit(unit, out);
+        }
+    }
+
+    /// Appends U+FFFD for a code unit or surrogate pair the input ended in
+    /// the middle of.
+    pub(crate) fn finish(&mut self, out: &mut Vec<u8>) {
+        if self.odd.take().is_some() || self.high.take().is_some() {
+            push_char(out, char::REPLACEMENT_CHARACTER);
+        }
+    }
+
+    fn push_unit(&mut self, unit: u16, out: &mut Vec<u8>) {
+        let c = match (self.high.take(), unit) {
+            (high, 0xD800..=0xDBFF) => {
+                if high.is_some() {
+                    push_char(out, char::REPLACEMENT_CHARACTER);
+                }
+                self.high = Some(unit);
+                return;
+            }
+            (Some(high), 0xDC00..=0xDFFF) => {
+                let c = 0x10000 + ((u32::from(high) - 0xD800) << 10) + (u32::from(unit) - 0xDC00);
+                char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER)
+            }
+            (high, _) => {
+                if high.is_some() {
+                    push_char(out, char::REPLACEMENT_CHARACTER);
+                }
+                char::from_u32(u32::from(unit)).unwrap_or(char::REPLACEMENT_CHARACTER)
+            }
+        };
+        push_char(out, c);
+    }
+}
+
+fn push_char(out: &mut Vec<u8>, c: char) {
+    let mut buf = [0; 4];
+    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+}
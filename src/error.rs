@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Why a file or directory couldn't be counted.
+#[derive(Debug)]
+pub enum LineCountError {
+    /// The path doesn't exist.
+    NotFound { path: PathBuf },
+    /// The file was rejected by the extension and file name filters, or
+    /// the path isn't something that can be counted.
+    Unsupported { path: PathBuf },
+    /// Reading a file failed. The path is `-` for stdin.
+    Io { path: PathBuf, source: io::Error },
+    /// Listing a directory failed.
+    ReadDir { path: PathBuf, source: io::Error },
+    /// A configuration file is malformed.
+    Config { path: PathBuf, message: String },
+    /// Reading a file took longer than `CountOptions::file_timeout`.
+    Timeout { path: PathBuf, timeout: Duration },
+}
+
+impl LineCountError {
+    /// Wraps an error from reading `path`, telling a missing file apart
+    /// from other failures.
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        if source.kind() == io::ErrorKind::NotFound {
+            LineCountError::NotFound {
+                path: path.to_path_buf(),
+            }
+        } else {
+            LineCountError::Io {
+                path: path.to_path_buf(),
+                source,
+            }
+        }
+    }
+
+    /// Returns true for read errors caused by missing permissions.
+    pub fn is_permission_denied(&self) -> bool {
+        match self {
+            LineCountError::Io { source, .. } | LineCountError::ReadDir { source, .. } => {
+                source.kind() == io::ErrorKind::PermissionDenied
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true for files given up on after `CountOptions::file_timeout`.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, LineCountError::Timeout { .. })
+    }
+
+    /// What went wrong, without the path.
+    pub fn reason(&self) -> String {
+        match self {
+            LineCountError::NotFound { .. } => "no such file or directory".to_string(),
+            LineCountError::Unsupported { .. } => "not a counted file type".to_string(),
+            LineCountError::Io { source, .. } => source.to_string(),
+            LineCountError::ReadDir { source, .. } => format!("cannot read directory: {}", source),
+            LineCountError::Config { message, .. } => message.clone(),
+            LineCountError::Timeout { timeout, .. } => {
+                format!("gave up after {} ms", timeout.as_millis())
+            }
+        }
+    }
+
+    /// The file or directory the error is about.
+    pub fn path(&self) -> &Path {
+        match self {
+            LineCountError::NotFound { path }
+            | LineCountError::Unsupported { path }
+            | LineCountError::Io { path, .. }
+            | LineCountError::ReadDir { path, .. }
+            | LineCountError::Config { path, .. }
+            | LineCountError::Timeout { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for LineCountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineCountError::NotFound { path } => {
+                write!(f, "No such file or directory: {}", path.display())
+            }
+            LineCountError::Unsupported { path } => {
+                write!(f, "Not a counted file type: {}", path.display())
+            }
+            LineCountError::Io { path, source } if path == Path::new("-") => {
+                write!(f, "Cannot read stdin: {}", source)
+            }
+            LineCountError::Io { path, source } => {
+                write!(f, "Cannot read {}: {}", path.display(), source)
+            }
+            LineCountError::ReadDir { path, source } => {
+                write!(f, "Cannot read directory {}: {}", path.display(), source)
+            }
+            LineCountError::Config { path, message } => {
+                write!(f, "Invalid config {}: {}", path.display(), message)
+            }
+            LineCountError::Timeout { path, timeout } => write!(
+                f,
+                "Gave up on {} after {} ms",
+                path.display(),
+                timeout.as_millis()
+            ),
+        }
+    }
+}
+
+impl Error for LineCountError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LineCountError::Io { source, .. } | LineCountError::ReadDir { source, .. } => {
+                Some(source)
+            }
+            _ => None,
+        }
+    }
+}
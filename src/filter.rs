@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+#[rustfmt::skip]
+pub const FILE_EXT_LIST: [&str; 41] = [
+    "txt", "text", "md", "markdown", "log",
+    "rs", "py", "js", "ts", "java", "c", "cpp", "h", "hpp",
+    "go", "rb", "php", "swift", "kt", "scala", "r",
+    "html", "htm", "css", "scss", "sass", "less",
+    "xml", "svg", "json", "yaml", "yml", "toml", "ini",
+    "csv", "tsv", "sql", "sh", "bash", "conf", "config",
+];
+
+/// File names that are counted even though their extension isn't listed.
+#[rustfmt::skip]
+pub const FILE_NAME_LIST: [&str; 16] = [
+    "Makefile", "makefile", "GNUmakefile", "Dockerfile", "Containerfile",
+    "Rakefile", "Gemfile", "Vagrantfile", "Jenkinsfile", "Procfile",
+    "Justfile", "justfile", ".gitignore", ".gitattributes", ".dockerignore",
+    ".editorconfig",
+];
+
+/// For a gzip-compressed file such as `app.log.gz`, returns the path it
+/// decompresses to (`app.log`), which is what the filters look at.
+pub(crate) fn gzip_inner(path: &Path) -> Option<PathBuf> {
+    let ext = path.extension()?;
+    ext.eq_ignore_ascii_case("gz")
+        .then(|| path.with_extension(""))
+}
+
+/// The built-in file name list as owned strings.
+pub fn default_names() -> Vec<String> {
+    FILE_NAME_LIST.iter().map(|n| n.to_string()).collect()
+}
+
+/// Returns true if the file name of `path` is exactly one of `names`.
+pub fn is_known_name(path: &Path, names: &[String]) -> bool {
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => names.iter().any(|known| known == name),
+        None => false,
+    }
+}
+
+/// The built-in extension list as owned strings.
+pub fn default_extensions() -> Vec<String> {
+    FILE_EXT_LIST.iter().map(|e| e.to_string()).collect()
+}
+
+/// Parses a comma-separated extension list such as `rs,.go,Vue`.
+///
+/// Leading dots are stripped and extensions are lowercased.
+pub fn parse_extensions(s: &str) -> Vec<String> {
+    split_extensions(s)
+        .map(|e| e.to_ascii_lowercase())
+        .collect()
+}
+
+/// Like [`parse_extensions`], but keeps the case of each extension.
+pub fn parse_extensions_exact(s: &str) -> Vec<String> {
+    split_extensions(s).map(str::to_string).collect()
+}
+
+fn split_extensions(s: &str) -> impl Iterator<Item = &str> {
+    s.split(',')
+        .map(|e| e.trim().trim_start_matches('.'))
+        .filter(|e| !e.is_empty())
+}
+
+/// Adds `include` to `extensions` and then takes out `exclude`, so an
+/// extension in both lists isn't counted.
+pub fn combine_extensions(
+    mut extensions: Vec<String>,
+    include: &[String],
+    exclude: &[String],
+) -> Vec<String> {
+    extensions.extend(include.iter().cloned());
+    extensions.retain(|e| !exclude.contains(e));
+    extensions
+}
+
+/// Returns true if the extension of `path` is in `extensions`.
+///
+/// `extensions` must be lowercase; the path's extension is compared
+/// case-insensitively.
+pub fn is_valid_ext(path: &Path, extensions: &[String]) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => {
+            let ext_lower = ext.to_ascii_lowercase();
+            extensions.iter().any(|allowed| allowed == &ext_lower)
+        }
+        None => false,
+    }
+}
+
+/// Returns true if the extension of `path` is in `extensions`, with the
+/// same case.
+pub fn is_exact_ext(path: &Path, extensions: &[String]) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => extensions.iter().any(|allowed| allowed == ext),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(s: &str) -> Vec<String> {
+        parse_extensions(s)
+    }
+
+    #[test]
+    fn well_known_names_are_counted_without_an_extension() {
+        let names = default_names();
+        for name in ["Makefile", "Dockerfile", "dir/Makefile", ".gitignore"] {
+            assert!(is_known_name(Path::new(name), &names), "{}", name);
+        }
+        for name in ["makefile.bak", "Dockerfiles", "MAKEFILE", "LICENSE"] {
+            assert!(!is_known_name(Path::new(name), &names), "{}", name);
+        }
+        assert!(!is_valid_ext(Path::new("Makefile"), &default_extensions()));
+    }
+
+    #[test]
+    fn exclusions_win_over_inclusions() {
+        let extensions =
+            combine_extensions(default_extensions(), &list("proto,tf"), &list("json,proto"));
+        assert!(is_valid_ext(Path::new("api.tf"), &extensions));
+        assert!(is_valid_ext(Path::new("main.rs"), &extensions));
+        assert!(!is_valid_ext(Path::new("api.proto"), &extensions));
+        assert!(!is_valid_ext(Path::new("package.json"), &extensions));
+        // An explicit list is included and excluded the same way.
+        let extensions = combine_extensions(list(".rs,Go"), &list("vue"), &list("rs"));
+        assert_eq!(extensions, ["go", "vue"]);
+    }
+}
@@ -0,0 +1,82 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Files under a target that differ from a git ref.
+#[derive(Debug, Default)]
+pub struct Changed {
+    /// Added or modified files, which still exist in the working tree.
+    pub files: Vec<PathBuf>,
+    /// Files that have been deleted since the ref.
+    pub deleted: Vec<PathBuf>,
+}
+
+/// Asks `git diff` which files under `target` differ from `base`. The
+/// working tree is compared, so staged and unstaged changes both count;
+/// untracked files don't. Renames are listed as a deletion and an addition.
+pub fn changed(target: &Path, base: &str) -> Result<Changed, String> {
+    let (dir, prefix, spec) = if target.is_dir() {
+        (target, target, Path::new("."))
+    } else {
+        let parent = target.parent().unwrap_or(Path::new(""));
+        let dir = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        (
+            dir,
+            parent,
+            Path::new(target.file_name().unwrap_or_default()),
+        )
+    };
+
+    // Outside a work tree `git diff` compares paths instead of failing.
+    let inside = git(dir, ["rev-parse", "--is-inside-work-tree"].map(OsStr::new))?;
+    if !inside.status.success() {
+        return Err("not inside a git work tree".to_string());
+    }
+
+    let diff = ["diff", "--name-status", "-z", "--relative", "--no-renames"];
+    let args = diff.iter().map(OsStr::new);
+    let args = args.chain([OsStr::new(base), OsStr::new("--"), spec.as_os_str()]);
+    let output = git(dir, args)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.trim().to_string());
+    }
+
+    // With -z the output is a status field and a path field per file, each
+    // ended by a NUL.
+    let mut changed = Changed::default();
+    let mut fields = output.stdout.split(|&b| b == 0);
+    while let (Some(status), Some(path)) = (fields.next(), fields.next()) {
+        let path = prefix.join(path_from_bytes(path));
+        if status.starts_with(b"D") {
+            changed.deleted.push(path);
+        } else {
+            changed.files.push(path);
+        }
+    }
+    Ok(changed)
+}
+
+fn git<'a>(dir: &Path, args: impl IntoIterator<Item = &'a OsStr>) -> Result<Output, String> {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("cannot run git: {}", e))
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
@@ -0,0 +1,274 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Matches `text` against a shell-style glob.
+///
+/// `*` and `?` don't match `/`, `**` matches across directories (and
+/// `**/` also matches zero directories), `[abc]`, `[a-z]` and `[!abc]`
+/// match character classes, and `\` escapes the next character.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches(&pattern, &text)
+}
+
+/// Returns true if `s` contains glob wildcards.
+pub fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Expands a glob such as `src/**/*.rs` into the files it matches, sorted.
+///
+/// The pattern's leading literal directories are used as the starting point
+/// and only what's below is matched. Directories are never returned, and
+/// symlinked directories aren't descended into. Hidden entries are only
+/// matched when `hidden` is set.
+pub fn expand_glob(pattern: &str, hidden: bool) -> Vec<PathBuf> {
+    let parts: Vec<&str> = pattern.split('/').collect();
+    let literal = parts.iter().take_while(|p| !is_glob(p)).count();
+    // A pattern without wildcards is a plain path.
+    if literal == parts.len() {
+        return vec![PathBuf::from(pattern)];
+    }
+
+    let base = parts[..literal].join("/");
+    let rest = parts[literal..].join("/");
+    // Without `**`, nothing deeper than the pattern's own components can match.
+    let max_depth = if rest.contains("**") {
+        usize::MAX
+    } else {
+        parts.len() - literal
+    };
+
+    let root = match base.as_str() {
+        "" if pattern.starts_with('/') => PathBuf::from("/"),
+        "" => PathBuf::new(),
+        _ => PathBuf::from(&base),
+    };
+    let mut found = Vec::new();
+    expand_dir(&root, "", &rest, hidden, max_depth, &mut found);
+    found.sort();
+    found
+}
+
+fn expand_dir(
+    dir: &Path,
+    rel: &str,
+    pattern: &str,
+    hidden: bool,
+    depth_left: usize,
+    found: &mut Vec<PathBuf>,
+) {
+    if depth_left == 0 {
+        return;
+    }
+    let read_from = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = fs::read_dir(read_from) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !hidden && name.starts_with('.') {
+            continue;
+        }
+        let child_rel = if rel.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", rel, name)
+        };
+        let path = dir.join(&name);
+
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            expand_dir(&path, &child_rel, pattern, hidden, depth_left - 1, found);
+        } else if path.is_file() && glob_match(pattern, &child_rel) {
+            found.push(path);
+        }
+    }
+}
+
+/// Joins the components of `path` with `/`, for matching against globs.
+pub(crate) fn to_slash(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn matches(p: &[char], t: &[char]) -> bool {
+    match p.first() {
+        None => t.is_empty(),
+        Some('*') if p.get(1) == Some(&'*') => {
+            let rest = &p[2..];
+            if rest.first() == Some(&'/') && matches(&rest[1..], t) {
+                return true;
+            }
+            (0..=t.len()).any(|i| matches(rest, &t[i..]))
+        }
+        Some('*') => {
+            let rest = &p[1..];
+            for i in 0..=t.len() {
+                if matches(rest, &t[i..]) {
+                    return true;
+                }
+                if i < t.len() && t[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => !t.is_empty() && t[0] != '/' && matches(&p[1..], &t[1..]),
+        Some('[') => match match_class(&p[1..], t.first().copied()) {
+            Some((true, len)) => matches(&p[1 + len..], &t[1..]),
+            Some((false, _)) => false,
+            // No closing bracket: treat `[` literally.
+            None => t.first() == Some(&'[') && matches(&p[1..], &t[1..]),
+        },
+        Some('\\') if p.len() > 1 => t.first() == Some(&p[1]) && matches(&p[2..], &t[1..]),
+        Some(c) => t.first() == Some(c) && matches(&p[1..], &t[1..]),
+    }
+}
+
+/// Matches `c` against the class starting right after a `[`.
+///
+/// Returns whether it matched and how many pattern characters the class
+/// used (including the closing `]`), or `None` if the class is unterminated.
+fn match_class(p: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(p.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < p.len() {
+        if p[i] == ']' && !first {
+            let hit = match c {
+                Some(c) => c != '/' && matched != negated,
+                None => false,
+            };
+            return Some((hit, i + 1));
+        }
+        first = false;
+
+        let lo = p[i];
+        if i + 2 < p.len() && p[i + 1] == '-' && p[i + 2] != ']' {
+            let hi = p[i + 2];
+            if c.is_some_and(|c| lo <= c && c <= hi) {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if c == Some(lo) {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[test]
+    fn double_star_matches_across_directories() {
+        assert!(glob_match("src/**/*.rs", "src/a/b/c.rs"));
+        assert!(glob_match("**/*.rs", "a/b.rs"));
+        assert!(glob_match("src/**", "src/a/b"));
+        assert!(!glob_match("src/**/*.rs", "lib/a.rs"));
+        // A single star stays within one component.
+        assert!(!glob_match("src/*.rs", "src/a/b.rs"));
+        assert!(!glob_match("*", "a/b"));
+    }
+
+    #[test]
+    fn double_star_slash_matches_zero_directories() {
+        assert!(glob_match("src/**/*.rs", "src/main.rs"));
+        assert!(glob_match("**/main.rs", "main.rs"));
+        assert!(glob_match("a/**/b/**/c", "a/b/c"));
+        assert!(!glob_match("src/**/*.rs", "srcmain.rs"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob_match("f?.rs", "f1.rs"));
+        assert!(glob_match("f?.rs", "fé.rs"));
+        assert!(!glob_match("f?.rs", "f.rs"));
+        assert!(!glob_match("f?.rs", "f12.rs"));
+        assert!(!glob_match("a?b", "a/b"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(glob_match("[abc].rs", "b.rs"));
+        assert!(!glob_match("[abc].rs", "d.rs"));
+        assert!(glob_match("v[0-9].txt", "v7.txt"));
+        assert!(!glob_match("v[0-9].txt", "vx.txt"));
+        assert!(glob_match("[!abc].rs", "d.rs"));
+        assert!(!glob_match("[!abc].rs", "a.rs"));
+        assert!(glob_match("[^a-c].rs", "z.rs"));
+        assert!(!glob_match("[^a-c].rs", "b.rs"));
+        // A leading `]` is part of the class, and `-` at the end is literal.
+        assert!(glob_match("[]x]", "]"));
+        assert!(glob_match("[a-]", "-"));
+        // A negated class still doesn't match `/`.
+        assert!(!glob_match("a[!x]b", "a/b"));
+        // An unclosed `[` is a literal.
+        assert!(glob_match("a[b", "a[b"));
+        assert!(glob_match("\\*.rs", "*.rs"));
+        assert!(!glob_match("\\*.rs", "a.rs"));
+    }
+
+    #[test]
+    fn expand_glob_walks_below_the_literal_prefix() {
+        let dir = TestDir::new();
+        for name in [
+            "a.rs",
+            "b.txt",
+            "src/c.rs",
+            "src/deep/d.rs",
+            "src/.hidden.rs",
+        ] {
+            dir.file(name, "");
+        }
+        let base = to_slash(dir.path());
+        let relative = |found: Vec<PathBuf>| -> Vec<String> {
+            found
+                .iter()
+                .map(|p| to_slash(p.strip_prefix(dir.path()).unwrap()))
+                .collect()
+        };
+        assert_eq!(
+            relative(expand_glob(&format!("{}/*.rs", base), false)),
+            ["a.rs"]
+        );
+        assert_eq!(
+            relative(expand_glob(&format!("{}/**/*.rs", base), false)),
+            ["a.rs", "src/c.rs", "src/deep/d.rs"]
+        );
+        assert_eq!(
+            relative(expand_glob(&format!("{}/src/*.rs", base), true)),
+            ["src/.hidden.rs", "src/c.rs"]
+        );
+    }
+
+    #[test]
+    fn a_pattern_without_wildcards_is_returned_as_is() {
+        assert_eq!(
+            expand_glob("no/such/file.rs", false),
+            [PathBuf::from("no/such/file.rs")]
+        );
+    }
+}
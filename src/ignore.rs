@@ -0,0 +1,157 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::CountOptions;
+use crate::glob::{glob_match, to_slash};
+
+/// Tool-specific ignore file, read like `.gitignore` in every directory.
+pub(crate) const IGNORE_FILE_NAME: &str = ".linecountignore";
+
+/// The ignore files that apply to a directory, outermost first.
+pub(crate) type IgnoreRules = Arc<Vec<Arc<IgnoreFile>>>;
+
+/// Patterns from one ignore file, using `.gitignore` syntax.
+#[derive(Debug)]
+pub(crate) struct IgnoreFile {
+    base: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug)]
+struct Pattern {
+    glob: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl IgnoreFile {
+    /// Reads an ignore file whose patterns are relative to `base`.
+    ///
+    /// Returns `None` if the file doesn't exist or can't be read.
+    pub(crate) fn load(path: &Path, base: &Path) -> Option<IgnoreFile> {
+        let content = fs::read_to_string(path).ok()?;
+        Some(IgnoreFile::parse(&content, base))
+    }
+
+    pub(crate) fn parse(content: &str, base: &Path) -> IgnoreFile {
+        let patterns = content.lines().filter_map(Pattern::parse).collect();
+        IgnoreFile {
+            base: base.to_path_buf(),
+            patterns,
+        }
+    }
+
+    /// Returns `Some(true)` if the last matching pattern ignores `path`,
+    /// `Some(false)` if it re-includes it, and `None` if nothing matched.
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let rel = to_slash(path.strip_prefix(&self.base).ok()?);
+        let name = path.file_name()?.to_string_lossy();
+
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| {
+                if p.dir_only && !is_dir {
+                    return false;
+                }
+                if p.anchored {
+                    glob_match(&p.glob, &rel)
+                } else {
+                    glob_match(&p.glob, &name)
+                }
+            })
+            .map(|p| !p.negated)
+    }
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Pattern> {
+        let mut line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let negated = line.starts_with('!');
+        if negated {
+            line = &line[1..];
+        }
+        // `\#` and `\!` escape a leading `#` or `!`.
+        if line.starts_with("\\#") || line.starts_with("\\!") {
+            line = &line[1..];
+        }
+
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        let glob = line.trim_start_matches('/');
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            glob: glob.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+}
+
+/// Returns true if `path` is ignored by `rules`.
+///
+/// Later files take precedence over earlier ones, and within a file the
+/// last matching pattern wins, so `!pattern` can re-include a path.
+pub(crate) fn is_ignored(rules: &[Arc<IgnoreFile>], path: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for file in rules {
+        if let Some(m) = file.matched(path, is_dir) {
+            ignored = m;
+        }
+    }
+    ignored
+}
+
+/// The rules a walk of `root` starts with: the global ignore file, with
+/// its patterns relative to `root`.
+pub(crate) fn root_rules(root: &Path, options: &CountOptions) -> IgnoreRules {
+    let global = options
+        .ignore_files
+        .then(global_ignore_path)
+        .flatten()
+        .and_then(|path| IgnoreFile::load(&path, root));
+    Arc::new(global.into_iter().map(Arc::new).collect())
+}
+
+/// `~/.config/linecount/ignore`, if the home directory is known.
+fn global_ignore_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".config/linecount/ignore"))
+}
+
+/// Extends `parent` with the `.gitignore` and `.linecountignore` in `dir`,
+/// as enabled by `options`. The latter takes precedence.
+pub(crate) fn rules_for_dir(
+    dir: &Path,
+    parent: &IgnoreRules,
+    options: &CountOptions,
+) -> IgnoreRules {
+    let names = [
+        (options.gitignore, ".gitignore"),
+        (options.ignore_files, IGNORE_FILE_NAME),
+    ];
+    let files: Vec<IgnoreFile> = names
+        .into_iter()
+        .filter(|&(enabled, _)| enabled)
+        .filter_map(|(_, name)| IgnoreFile::load(&dir.join(name), dir))
+        .collect();
+
+    if files.is_empty() {
+        return parent.clone();
+    }
+    let mut rules = (**parent).clone();
+    rules.extend(files.into_iter().map(Arc::new));
+    Arc::new(rules)
+}
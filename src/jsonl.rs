@@ -0,0 +1,139 @@
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::CountOptions;
+use crate::count::{self, EmptyLines, LineBuffer};
+
+/// How many invalid line numbers are kept per file.
+const MAX_INVALID_LINES: usize = 100;
+
+/// Returns true if `path` has a `.jsonl` or `.ndjson` extension.
+pub(crate) fn is_jsonl(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("jsonl") || e.eq_ignore_ascii_case("ndjson"))
+}
+
+/// Records of a JSON Lines file that do and don't parse as JSON. Blank
+/// lines are neither.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonlRecords {
+    pub valid: u64,
+    pub invalid: u64,
+    /// 1-based numbers of the first 100 invalid lines. Only kept for a
+    /// single file, not for sums.
+    pub invalid_lines: Vec<u64>,
+}
+
+impl JsonlRecords {
+    pub fn add(&mut self, other: &JsonlRecords) {
+        self.valid += other.valid;
+        self.invalid += other.invalid;
+    }
+}
+
+/// Parses every line of a JSON Lines file for `CountOptions::jsonl_validate`.
+pub(crate) struct JsonlValidator {
+    lines: LineBuffer,
+    line: u64,
+    records: JsonlRecords,
+}
+
+impl JsonlValidator {
+    pub(crate) fn new(options: &CountOptions) -> Self {
+        JsonlValidator {
+            lines: LineBuffer::new(options),
+            line: 0,
+            records: JsonlRecords::default(),
+        }
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        let (line, records) = (&mut self.line, &mut self.records);
+        self.lines.feed(chunk, |text| check(line, records, text));
+    }
+
+    pub(crate) fn finish(self) -> JsonlRecords {
+        let (mut line, mut records) = (self.line, self.records);
+        self.lines
+            .finish(|text| check(&mut line, &mut records, text));
+        records
+    }
+}
+
+fn check(line: &mut u64, records: &mut JsonlRecords, text: &[u8]) {
+    *line += 1;
+    if count::is_blank(text, EmptyLines::Whitespace) {
+        return;
+    }
+    if serde_json::from_slice::<Value>(text).is_ok() {
+        records.valid += 1;
+    } else {
+        records.invalid += 1;
+        if records.invalid_lines.len() < MAX_INVALID_LINES {
+            records.invalid_lines.push(*line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Validates `text`, checked to come out the same for every chunk size.
+    fn validate(text: &str) -> JsonlRecords {
+        let options = CountOptions::default();
+        let results: Vec<JsonlRecords> = [1, 2, 3, text.len().max(1)]
+            .iter()
+            .map(|&size| {
+                let mut validator = JsonlValidator::new(&options);
+                for chunk in text.as_bytes().chunks(size) {
+                    validator.feed(chunk);
+                }
+                validator.finish()
+            })
+            .collect();
+        assert!(results.iter().all(|r| *r == results[0]), "{:?}", results);
+        results[0].clone()
+    }
+
+    fn records(valid: u64, invalid_lines: &[u64]) -> JsonlRecords {
+        JsonlRecords {
+            valid,
+            invalid: invalid_lines.len() as u64,
+            invalid_lines: invalid_lines.to_vec(),
+        }
+    }
+
+    #[test]
+    fn blank_lines_are_neither_valid_nor_invalid() {
+        assert_eq!(validate("{}\n\n  \n\t\n[1]\n"), records(2, &[]));
+        assert_eq!(validate(""), records(0, &[]));
+    }
+
+    #[test]
+    fn invalid_lines_are_numbered_counting_blank_ones() {
+        let text = "{\"a\":1}\n{oops\n\n\"str\"\n[1,\n42\n";
+        assert_eq!(validate(text), records(3, &[2, 5]));
+        // CRLF endings don't make a line invalid.
+        assert_eq!(validate("{}\r\n{\r\n1\r\n"), records(2, &[2]));
+    }
+
+    #[test]
+    fn a_final_line_without_a_newline_is_checked() {
+        assert_eq!(validate("{}\n{\"b\":2}"), records(2, &[]));
+        assert_eq!(validate("{}\n{\"b\":"), records(1, &[2]));
+    }
+
+    #[test]
+    fn only_the_first_invalid_line_numbers_are_kept() {
+        let text = "x\n".repeat(MAX_INVALID_LINES + 5);
+        let result = validate(&text);
+        assert_eq!(result.invalid, MAX_INVALID_LINES as u64 + 5);
+        assert_eq!(result.invalid_lines.len(), MAX_INVALID_LINES);
+        assert_eq!(
+            result.invalid_lines.last(),
+            Some(&(MAX_INVALID_LINES as u64))
+        );
+    }
+}
@@ -0,0 +1,2063 @@
+//! Line counting for text-based files and directory trees.
+//!
+//! This is the library behind the `linecount` binary; everything the CLI
+//! does goes through [`count_file`] and [`count_directory`].
+//!
+//! Results can be taken in three ways. [`walk_and_count`] is an iterator
+//! that counts one file per call to `next` on the calling thread.
+//! [`count_directory`] counts on `CountOptions::jobs` threads and hands each
+//! file to a callback on the calling thread. [`count_tree`] calls its
+//! callback right on the worker threads, for live aggregation:
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use std::sync::Mutex;
+//! use std::collections::HashMap;
+//! use linecount::{count_tree, CountOptions};
+//!
+//! let options = CountOptions { recursive: true, ..CountOptions::default() };
+//! let by_dir = Mutex::new(HashMap::new());
+//! count_tree(Path::new("."), &options, |stats| {
+//!     let dir = stats.path.parent().unwrap_or(Path::new("")).to_path_buf();
+//!     *by_dir.lock().unwrap().entry(dir).or_insert(0) += stats.lines;
+//! })?;
+//! for (dir, lines) in by_dir.into_inner().unwrap() {
+//!     println!("{}: {}", dir.display(), lines);
+//! }
+//! # Ok::<(), linecount::LineCountError>(())
+//! ```
+//!
+//! Failures are returned as [`LineCountError`] rather than printed, so a
+//! file that couldn't be read is never mistaken for an empty one.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use flate2::read::MultiGzDecoder;
+
+/// Explains a decision on stderr if `CountOptions::verbose` is at least
+/// `$level`.
+macro_rules! verbose {
+    ($options:expr, $level:expr, $($arg:tt)*) => {
+        if $options.verbose >= $level {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod archive;
+mod cache;
+mod classify;
+mod config;
+mod count;
+mod dedup;
+mod encoding;
+mod error;
+mod filter;
+mod glob;
+mod ignore;
+mod jsonl;
+mod matcher;
+mod sql;
+#[cfg(test)]
+mod testdir;
+mod walk;
+
+pub use archive::is_archive;
+pub use cache::FileCache;
+pub use classify::{CommentSyntax, LineClasses};
+pub use config::{default_config_path, load_comment_syntax};
+pub use count::{EmptyLines, Endings, LineEnding};
+pub use encoding::Encoding;
+pub use error::LineCountError;
+pub use filter::{
+    FILE_EXT_LIST, FILE_NAME_LIST, combine_extensions, default_extensions, default_names,
+    is_exact_ext, is_known_name, is_valid_ext, parse_extensions, parse_extensions_exact,
+};
+pub use glob::{expand_glob, is_glob};
+pub use jsonl::JsonlRecords;
+pub use regex::bytes::Regex;
+pub use walk::WalkOrder;
+
+/// Settings shared by every counting entry point.
+#[derive(Debug, Clone)]
+pub struct CountOptions {
+    /// Size in bytes of the read buffer.
+    pub buffer_size: usize,
+    /// Only count lines that aren't empty.
+    pub skip_empty: bool,
+    /// What `skip_empty` considers empty; by default also lines of only
+    /// spaces and tabs.
+    pub empty_lines: EmptyLines,
+    /// Descend into subdirectories when counting a directory.
+    pub recursive: bool,
+    /// Without `recursive`, still descend fully into these subdirectories,
+    /// given as `/`-separated paths relative to the directory being
+    /// counted.
+    pub recursive_paths: Vec<String>,
+    /// Limit how deep a recursive walk goes; 0 means only the directory's
+    /// own files. Setting this implies `recursive`.
+    pub max_depth: Option<usize>,
+    /// Skip paths matched by `.gitignore` files found while walking.
+    pub gitignore: bool,
+    /// Skip paths matched by `.linecountignore` files found while walking
+    /// and by the global `~/.config/linecount/ignore`.
+    pub ignore_files: bool,
+    /// Include files and directories whose name starts with a dot. The
+    /// path being counted is never skipped, only what's found inside it,
+    /// and files in `names` such as `.gitignore` are walked either way.
+    pub hidden: bool,
+    /// Also walk version control metadata such as `.git` and `.svn`,
+    /// which is skipped by default even with `hidden`.
+    pub count_vcs: bool,
+    /// List each directory's entries sorted by name, so walks come out in
+    /// the same order everywhere. Turning it off keeps the file system's
+    /// order, which saves sorting huge directories.
+    pub sort_walk: bool,
+    /// Whether recursive walks go depth- or breadth-first.
+    pub walk_order: WalkOrder,
+    /// Glob patterns of files and directories to skip while walking.
+    pub exclude: Vec<String>,
+    /// Follow symlinks found while walking: count symlinked files and
+    /// descend into symlinked directories. Without it both are skipped.
+    /// Paths passed in directly are always followed.
+    pub follow_symlinks: bool,
+    /// Number of worker threads used when counting a directory.
+    pub jobs: usize,
+    /// Read files on this many threads and count them on `cpu_threads`
+    /// others, instead of doing both on `jobs` threads. Setting either
+    /// one enables the split; the other then defaults to `jobs`.
+    pub io_threads: Option<usize>,
+    /// See `io_threads`.
+    pub cpu_threads: Option<usize>,
+    /// Extensions, without the dot, of files that get counted. Lowercase
+    /// unless `case_sensitive_ext` is set.
+    pub extensions: Vec<String>,
+    /// Only count files whose extension matches one of `extensions` in
+    /// case, too.
+    pub case_sensitive_ext: bool,
+    /// Exact file names that are counted regardless of their extension.
+    pub names: Vec<String>,
+    /// Extensions that are always counted, and never taken for binary by
+    /// `skip_binary`. Spelled like `extensions`.
+    pub text_extensions: Vec<String>,
+    /// Extensions that are never counted, even with `count_all` or as a
+    /// file named on the command line. Wins over `text_extensions`.
+    pub binary_extensions: Vec<String>,
+    /// Count every file regardless of its extension.
+    pub count_all: bool,
+    /// Count the members of tar, gzipped tar and zip archives, each
+    /// through the filters like a file of its own, instead of skipping the
+    /// archive.
+    pub archives: bool,
+    /// Count a file reachable through several hardlinks only once. Only
+    /// has an effect on Unix.
+    pub dedup_inodes: bool,
+    /// Skip files smaller than this many bytes.
+    pub min_size: Option<u64>,
+    /// Skip files larger than this many bytes.
+    pub max_size: Option<u64>,
+    /// Skip files last modified longer ago than this. Files whose
+    /// modification time can't be read are counted, with a warning.
+    pub since: Option<Duration>,
+    /// Report zero lines for files that look binary.
+    pub skip_binary: bool,
+    /// Count files through a memory map instead of the read buffer, falling
+    /// back to reading for files that can't be mapped.
+    pub mmap: bool,
+    /// What counts as the end of a line.
+    pub line_ending: LineEnding,
+    /// Bytes that also end a line, e.g. `0x0c` for form feeds.
+    pub extra_breaks: Vec<u8>,
+    /// Also count whitespace-delimited words.
+    pub words: bool,
+    /// Also count characters.
+    pub chars: bool,
+    /// Also estimate how many tokens the text would take up for a language
+    /// model, by counting identifiers and punctuation. It's a heuristic,
+    /// not a real tokenizer.
+    pub tokens: bool,
+    /// Also report how many bytes were read, after decompression.
+    pub bytes: bool,
+    /// How the input is encoded. Every count other than bytes is taken
+    /// after converting it to UTF-8.
+    pub encoding: Encoding,
+    /// Also sort lines into blank, comment and code based on the comment
+    /// syntax of the file's language.
+    pub classify: bool,
+    /// Comment syntax by lowercase extension, used by `classify` ahead of
+    /// the built-in languages.
+    pub comment_syntax: HashMap<String, CommentSyntax>,
+    /// Also find the longest line, in characters if `chars` is set and in
+    /// bytes otherwise.
+    pub longest: bool,
+    /// Count `;`-terminated statements instead of lines in `.sql` files.
+    pub sql_statements: bool,
+    /// Also check that every line of `.jsonl` and `.ndjson` files is valid
+    /// JSON.
+    pub jsonl_validate: bool,
+    /// Only count lines matching this pattern.
+    pub matching: Option<Regex>,
+    /// Only count lines not matching this pattern.
+    pub not_matching: Option<Regex>,
+    /// Stop reading a file after this many line terminators. The
+    /// resulting stats are marked as `truncated`.
+    pub max_lines: Option<u64>,
+    /// Stop walking and counting once every call sharing this cap has
+    /// counted this many lines between them. Files already being counted
+    /// are finished, so the total ends up a little past the cap.
+    pub total_cap: Option<Arc<LineCap>>,
+    /// How many files may be open at once between every call sharing the
+    /// limit, whatever the number of jobs. Threads wait for a file to be
+    /// closed rather than fail to open one. Defaults to half the soft
+    /// `RLIMIT_NOFILE` on Unix, and no limit elsewhere.
+    pub open_files: Option<Arc<OpenFileLimit>>,
+    /// Explain on stderr why paths are skipped (1), and also how each
+    /// file was read (2).
+    pub verbose: u8,
+    /// Give up on a file if counting it takes longer than this. Each file
+    /// is then read on a thread of its own, which is left behind if the
+    /// read never returns.
+    pub file_timeout: Option<Duration>,
+    /// Also count how many distinct lines there are.
+    pub dedup_lines: bool,
+    /// Also tally which lines end in `\n` and which in `\r\n`.
+    pub check_endings: bool,
+    /// Have [`count_directory`] collect the directories in which no lines
+    /// were counted. Implies walking the root directory even without
+    /// `recursive`.
+    pub empty_dirs: bool,
+    /// Reuse the line counts of unchanged files from this cache, and
+    /// store the counts of the others in it.
+    pub cache: Option<Arc<FileCache>>,
+    /// Count each file at most once across every call sharing this set,
+    /// e.g. for overlapping targets. Later copies are skipped as
+    /// duplicates.
+    pub counted: Option<Arc<CountedFiles>>,
+}
+
+impl Default for CountOptions {
+    fn default() -> Self {
+        CountOptions {
+            buffer_size: 8 * 1024,
+            skip_empty: false,
+            empty_lines: EmptyLines::default(),
+            recursive: false,
+            recursive_paths: Vec::new(),
+            max_depth: None,
+            gitignore: false,
+            ignore_files: true,
+            hidden: false,
+            count_vcs: false,
+            sort_walk: true,
+            walk_order: WalkOrder::default(),
+            exclude: Vec::new(),
+            follow_symlinks: false,
+            jobs: default_jobs(),
+            io_threads: None,
+            cpu_threads: None,
+            extensions: default_extensions(),
+            case_sensitive_ext: false,
+            names: default_names(),
+            text_extensions: Vec::new(),
+            binary_extensions: Vec::new(),
+            count_all: false,
+            archives: false,
+            dedup_inodes: false,
+            min_size: None,
+            max_size: None,
+            since: None,
+            skip_binary: false,
+            mmap: false,
+            line_ending: LineEnding::default(),
+            extra_breaks: Vec::new(),
+            words: false,
+            chars: false,
+            tokens: false,
+            bytes: false,
+            encoding: Encoding::default(),
+            classify: false,
+            comment_syntax: HashMap::new(),
+            longest: false,
+            sql_statements: false,
+            jsonl_validate: false,
+            matching: None,
+            not_matching: None,
+            max_lines: None,
+            total_cap: None,
+            open_files: default_open_files().map(|n| Arc::new(OpenFileLimit::new(n))),
+            verbose: 0,
+            file_timeout: None,
+            dedup_lines: false,
+            check_endings: false,
+            empty_dirs: false,
+            cache: None,
+            counted: None,
+        }
+    }
+}
+
+impl CountOptions {
+    /// Returns true if `path` passes the extension and file name filters.
+    ///
+    /// A `.gz` file is judged by the name it decompresses to, so
+    /// `app.log.gz` passes wherever `app.log` does. Archives pass with
+    /// `archives`.
+    pub fn should_count(&self, path: &Path) -> bool {
+        let inner = filter::gzip_inner(path);
+        let name = inner.as_deref().unwrap_or(path);
+        if self.has_ext(name, &self.binary_extensions) {
+            return false;
+        }
+        if self.archives && is_archive(path) {
+            return true;
+        }
+        self.count_all
+            || self.has_ext(name, &self.extensions)
+            || self.is_forced_text(name)
+            || is_known_name(name, &self.names)
+    }
+
+    /// Returns true if `path` is in `text_extensions`, so it's counted
+    /// however binary it looks.
+    pub(crate) fn is_forced_text(&self, path: &Path) -> bool {
+        self.has_ext(path, &self.text_extensions)
+    }
+
+    fn has_ext(&self, path: &Path, extensions: &[String]) -> bool {
+        if self.case_sensitive_ext {
+            is_exact_ext(path, extensions)
+        } else {
+            is_valid_ext(path, extensions)
+        }
+    }
+
+    /// Returns true if a file of `len` bytes passes the size filters.
+    pub fn size_in_range(&self, len: u64) -> bool {
+        self.min_size.is_none_or(|min| len >= min) && self.max_size.is_none_or(|max| len <= max)
+    }
+
+    /// Returns true if `path`, with `metadata`, passes `CountOptions::since`.
+    pub fn modified_in_window(&self, path: &Path, metadata: &Metadata) -> bool {
+        let Some(since) = self.since else {
+            return true;
+        };
+        match metadata.modified() {
+            // A time in the future is as recent as it gets.
+            Ok(mtime) => SystemTime::now()
+                .duration_since(mtime)
+                .ok()
+                .is_none_or(|age| age <= since),
+            Err(_) => {
+                eprintln!(
+                    "Warning: no modification time for {}, counting it anyway",
+                    path.display()
+                );
+                true
+            }
+        }
+    }
+}
+
+/// Number of logical CPUs, or 1 if that can't be determined.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Where the longest line was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LongestLine {
+    pub path: PathBuf,
+    /// 1-based line number.
+    pub line: u64,
+    /// Length in bytes, or in characters with `CountOptions::chars`.
+    pub length: u64,
+}
+
+impl LongestLine {
+    /// Compares by length, breaking ties by path so the result doesn't
+    /// depend on the order files were counted in.
+    fn beats(&self, other: Option<&LongestLine>) -> bool {
+        other.is_none_or(|o| (self.length, &o.path) > (o.length, &self.path))
+    }
+}
+
+/// Files that were passed over instead of being counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Skipped {
+    /// Rejected by the extension, file name or size filters.
+    pub filtered: u64,
+    /// Rejected by `CountOptions::skip_binary`.
+    pub binary: u64,
+    /// Files that were already counted, through another hardlink with
+    /// `CountOptions::dedup_inodes` or as part of `CountOptions::counted`.
+    pub duplicates: u64,
+    /// Symlinks to files, without `CountOptions::follow_symlinks`.
+    pub symlinks: u64,
+}
+
+/// Result of counting a file, or the sum over several files.
+#[derive(Debug, Clone, Default)]
+pub struct FileStats {
+    pub path: PathBuf,
+    pub lines: u64,
+    /// Set when `CountOptions::words` is enabled.
+    pub words: Option<u64>,
+    /// Set when `CountOptions::chars` is enabled.
+    pub chars: Option<u64>,
+    /// Set when `CountOptions::tokens` is enabled.
+    pub tokens: Option<u64>,
+    /// Set when `CountOptions::bytes` is enabled.
+    pub bytes: Option<u64>,
+    /// Set when `CountOptions::classify` is enabled.
+    pub classes: Option<LineClasses>,
+    /// Set when `CountOptions::longest` is enabled and there was at least
+    /// one line.
+    pub longest: Option<LongestLine>,
+    /// Set when `CountOptions::check_endings` is enabled.
+    pub endings: Option<Endings>,
+    /// Set when `CountOptions::dedup_lines` is enabled: how many of the
+    /// lines are distinct.
+    pub unique_lines: Option<u64>,
+    /// Set when `CountOptions::jsonl_validate` is enabled, for JSON Lines
+    /// files and for sums.
+    pub jsonl: Option<JsonlRecords>,
+    /// Hashes of the distinct lines, so [`FileStats::add`] can tell the
+    /// distinct lines of a sum. Taken out of the per-file stats that
+    /// [`count_directory`] passes to its callback.
+    pub line_hashes: Option<HashSet<u64>>,
+    pub skipped: Skipped,
+    /// Files or directories that couldn't be read. Always 0 for the stats
+    /// of a single file that was counted successfully.
+    pub errors: u64,
+    /// How many of `errors` were caused by missing permissions.
+    pub permission_denied: u64,
+    /// How many of `errors` were files given up on after
+    /// `CountOptions::file_timeout`.
+    pub timed_out: u64,
+    /// Files that were only counted up to `CountOptions::max_lines`.
+    pub truncated: u64,
+    /// Set when `CountOptions::empty_dirs` is enabled: the walked
+    /// directories without a counted line anywhere below them, sorted.
+    pub empty_dirs: Option<Vec<PathBuf>>,
+}
+
+impl FileStats {
+    /// Empty stats for `path`, with words and chars present if `options`
+    /// asks for them.
+    pub fn new(path: &Path, options: &CountOptions) -> Self {
+        FileStats {
+            path: path.to_path_buf(),
+            lines: 0,
+            words: options.words.then_some(0),
+            chars: options.chars.then_some(0),
+            tokens: options.tokens.then_some(0),
+            bytes: options.bytes.then_some(0),
+            classes: options.classify.then(LineClasses::default),
+            longest: None,
+            endings: options.check_endings.then(Endings::default),
+            unique_lines: options.dedup_lines.then_some(0),
+            jsonl: options.jsonl_validate.then(JsonlRecords::default),
+            line_hashes: options.dedup_lines.then(HashSet::new),
+            skipped: Skipped::default(),
+            errors: 0,
+            permission_denied: 0,
+            timed_out: 0,
+            truncated: 0,
+            empty_dirs: options.empty_dirs.then(Vec::new),
+        }
+    }
+
+    fn from_counts(path: &Path, counts: count::Counts, options: &CountOptions) -> Self {
+        if counts.binary {
+            verbose!(options, 1, "skip {}: looks binary", path.display());
+        } else {
+            verbose!(
+                options,
+                2,
+                "count {}: {} lines, {} bytes in {} chunks of up to {} bytes",
+                path.display(),
+                counts.lines,
+                counts.bytes,
+                counts.chunks,
+                options.buffer_size
+            );
+        }
+        FileStats {
+            path: path.to_path_buf(),
+            lines: counts.lines,
+            words: options.words.then_some(counts.words),
+            chars: options.chars.then_some(counts.chars),
+            tokens: options.tokens.then_some(counts.tokens),
+            bytes: options.bytes.then_some(counts.bytes),
+            classes: options.classify.then_some(counts.classes),
+            longest: counts.longest.map(|(length, line)| LongestLine {
+                path: path.to_path_buf(),
+                line,
+                length,
+            }),
+            endings: options.check_endings.then_some(counts.endings),
+            unique_lines: options
+                .dedup_lines
+                .then_some(counts.line_hashes.len() as u64),
+            line_hashes: options.dedup_lines.then_some(counts.line_hashes),
+            jsonl: counts.jsonl,
+            skipped: Skipped {
+                binary: counts.binary as u64,
+                ..Skipped::default()
+            },
+            errors: 0,
+            permission_denied: 0,
+            timed_out: 0,
+            truncated: counts.truncated as u64,
+            empty_dirs: options.empty_dirs.then(Vec::new),
+        }
+    }
+
+    /// Adds the counts of `other` to these stats.
+    pub fn add(&mut self, other: &FileStats) {
+        self.lines += other.lines;
+        self.words = sum_optional(self.words, other.words);
+        self.chars = sum_optional(self.chars, other.chars);
+        self.tokens = sum_optional(self.tokens, other.tokens);
+        self.bytes = sum_optional(self.bytes, other.bytes);
+        self.skipped.filtered += other.skipped.filtered;
+        self.skipped.binary += other.skipped.binary;
+        self.skipped.duplicates += other.skipped.duplicates;
+        self.skipped.symlinks += other.skipped.symlinks;
+        self.errors += other.errors;
+        self.permission_denied += other.permission_denied;
+        self.timed_out += other.timed_out;
+        self.truncated += other.truncated;
+        self.classes = match (self.classes, other.classes) {
+            (None, None) => None,
+            (a, b) => {
+                let mut sum = a.unwrap_or_default();
+                sum.add(&b.unwrap_or_default());
+                Some(sum)
+            }
+        };
+        self.endings = match (self.endings, other.endings) {
+            (None, None) => None,
+            (a, b) => {
+                let mut sum = a.unwrap_or_default();
+                sum.add(&b.unwrap_or_default());
+                Some(sum)
+            }
+        };
+        if let Some(other) = &other.longest
+            && other.beats(self.longest.as_ref())
+        {
+            self.longest = Some(other.clone());
+        }
+        if let Some(records) = &other.jsonl {
+            self.jsonl
+                .get_or_insert_with(JsonlRecords::default)
+                .add(records);
+        }
+        if let Some(hashes) = &other.line_hashes {
+            let sum = self.line_hashes.get_or_insert_with(HashSet::new);
+            sum.extend(hashes);
+            self.unique_lines = Some(sum.len() as u64);
+        }
+        if let Some(dirs) = &other.empty_dirs {
+            let sum = self.empty_dirs.get_or_insert_with(Vec::new);
+            sum.extend_from_slice(dirs);
+            sum.sort();
+        }
+    }
+}
+
+fn sum_optional(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    }
+}
+
+/// Counts the lines of one file.
+///
+/// Files rejected by [`CountOptions::should_count`] or
+/// [`CountOptions::size_in_range`] are not read and fail with
+/// [`LineCountError::Unsupported`]. A file already in
+/// `CountOptions::counted` gets empty stats with one skipped duplicate.
+pub fn count_file(path: &Path, options: &CountOptions) -> Result<FileStats, LineCountError> {
+    if let Some(counted) = &options.counted
+        && !counted.insert(path)
+    {
+        verbose!(options, 1, "skip {}: already counted", path.display());
+        let mut stats = FileStats::new(path, options);
+        stats.skipped.duplicates = 1;
+        return Ok(stats);
+    }
+    let mut buffer = vec![0u8; options.buffer_size];
+    let stats = count_file_with(path, options, &mut buffer)?;
+    spend(options, &stats);
+    Ok(stats)
+}
+
+/// Lines counted so far against `CountOptions::total_cap`.
+#[derive(Debug)]
+pub struct LineCap {
+    limit: u64,
+    counted: AtomicU64,
+}
+
+impl LineCap {
+    pub fn new(limit: u64) -> Self {
+        LineCap {
+            limit,
+            counted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Returns true once the lines counted have reached the limit.
+    pub fn is_reached(&self) -> bool {
+        self.counted.load(Ordering::Relaxed) >= self.limit
+    }
+
+    fn add(&self, lines: u64) {
+        self.counted.fetch_add(lines, Ordering::Relaxed);
+    }
+}
+
+/// Returns true if `options` has a total cap that has been reached.
+fn capped(options: &CountOptions) -> bool {
+    options
+        .total_cap
+        .as_ref()
+        .is_some_and(|cap| cap.is_reached())
+}
+
+/// Counts `stats` against `options.total_cap`.
+fn spend(options: &CountOptions, stats: &FileStats) {
+    if let Some(cap) = &options.total_cap {
+        cap.add(stats.lines);
+    }
+}
+
+/// Files held open against `CountOptions::open_files`.
+#[derive(Debug)]
+pub struct OpenFileLimit {
+    limit: usize,
+    open: Mutex<usize>,
+    closed: Condvar,
+    peak: AtomicUsize,
+}
+
+impl OpenFileLimit {
+    /// A limit of at least one file.
+    pub fn new(limit: usize) -> Self {
+        OpenFileLimit {
+            limit: limit.max(1),
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The most files that have been open at the same time so far.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Waits until fewer than `limit` files are open, and holds a place
+    /// for one more until the guard is dropped.
+    fn acquire(&self) -> OpenFile<'_> {
+        let mut open = self.open.lock().unwrap();
+        while *open >= self.limit {
+            open = self.closed.wait(open).unwrap();
+        }
+        *open += 1;
+        self.peak.fetch_max(*open, Ordering::Relaxed);
+        OpenFile { limit: self }
+    }
+}
+
+/// A place taken in an [`OpenFileLimit`].
+struct OpenFile<'a> {
+    limit: &'a OpenFileLimit,
+}
+
+impl Drop for OpenFile<'_> {
+    fn drop(&mut self) {
+        *self.limit.open.lock().unwrap() -= 1;
+        self.limit.closed.notify_one();
+    }
+}
+
+/// Waits for a place to open a file in, if `options.open_files` is set.
+fn open_file_slot(options: &CountOptions) -> Option<OpenFile<'_>> {
+    options.open_files.as_ref().map(|limit| limit.acquire())
+}
+
+/// Half the soft limit on open file descriptors, leaving the rest for
+/// directories being listed, stdio and whatever else the process has open.
+#[cfg(unix)]
+pub fn default_open_files() -> Option<usize> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to the struct it's given.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+    usize::try_from(limit.rlim_cur / 2).ok()
+}
+
+/// Without `RLIMIT_NOFILE` there's no limit to stay under.
+#[cfg(not(unix))]
+pub fn default_open_files() -> Option<usize> {
+    None
+}
+
+/// Files counted so far, by canonical path, for `CountOptions::counted`.
+#[derive(Debug, Default)]
+pub struct CountedFiles {
+    paths: Mutex<HashSet<PathBuf>>,
+}
+
+impl CountedFiles {
+    pub fn new() -> Self {
+        CountedFiles::default()
+    }
+
+    /// Adds `path`, returning false if it was already there. A path that
+    /// can't be resolved is always new.
+    pub fn insert(&self, path: &Path) -> bool {
+        match fs::canonicalize(path) {
+            Ok(path) => self.paths.lock().unwrap().insert(path),
+            Err(_) => true,
+        }
+    }
+}
+
+/// Counts the members of a tar, gzipped tar or zip archive and returns
+/// their sum, as `CountOptions::archives` does for the archives found while
+/// walking. `on_member` is called with the stats of each member that
+/// passes the filters, named `<archive>:<name inside>`, in archive order.
+///
+/// Fails with [`LineCountError::Unsupported`] if `path` isn't named like an
+/// archive, and with [`LineCountError::Io`] if it turns out not to be one.
+pub fn count_archive<F>(
+    path: &Path,
+    options: &CountOptions,
+    mut on_member: F,
+) -> Result<FileStats, LineCountError>
+where
+    F: FnMut(&FileStats),
+{
+    if let Some(counted) = &options.counted
+        && !counted.insert(path)
+    {
+        verbose!(options, 1, "skip {}: already counted", path.display());
+        let mut stats = FileStats::new(path, options);
+        stats.skipped.duplicates = 1;
+        return Ok(stats);
+    }
+    let mut buffer = vec![0u8; options.buffer_size];
+    let stats = sum_archive(path, options, &mut buffer, &mut on_member)?;
+    spend(options, &stats);
+    Ok(stats)
+}
+
+fn sum_archive(
+    path: &Path,
+    options: &CountOptions,
+    buffer: &mut [u8],
+    on_member: &mut dyn FnMut(&FileStats),
+) -> Result<FileStats, LineCountError> {
+    let (members, filtered) = archive_members(path, options, buffer)?;
+    let mut total = FileStats::new(path, options);
+    total.skipped.filtered = filtered;
+    for member in &members {
+        on_member(member);
+        total.add(member);
+    }
+    Ok(total)
+}
+
+/// The stats of every counted member of an archive, and how many members
+/// were filtered out.
+fn archive_members(
+    path: &Path,
+    options: &CountOptions,
+    buffer: &mut [u8],
+) -> Result<(Vec<FileStats>, u64), LineCountError> {
+    if let Some(timeout) = options.file_timeout {
+        return with_timeout(path, options, timeout, |path, options| {
+            archive_members(path, options, &mut vec![0u8; options.buffer_size])
+        });
+    }
+    let _slot = open_file_slot(options);
+    let mut members = Vec::new();
+    let filtered = archive::count_members(path, options, buffer, &mut |m| members.push(m))?;
+    Ok((members, filtered))
+}
+
+/// Like [`count_file`], but reads through a caller-provided scratch buffer.
+/// An archive gets the sum of its members.
+fn count_file_with(
+    path: &Path,
+    options: &CountOptions,
+    buffer: &mut [u8],
+) -> Result<FileStats, LineCountError> {
+    if options.archives && is_archive(path) {
+        return sum_archive(path, options, buffer, &mut |_| {});
+    }
+    if let Some(timeout) = options.file_timeout {
+        return with_timeout(path, options, timeout, |path, options| {
+            count_file_with(path, options, &mut vec![0u8; options.buffer_size])
+        });
+    }
+    let _slot = open_file_slot(options);
+    let file = open_counted(path, options)?;
+    let stamp = cache_stamp(&file, options);
+    if let Some(stats) = cached(path, stamp, options) {
+        return Ok(stats);
+    }
+    let stats = count_open_file(path, file, options, buffer)?;
+    remember(stamp, &stats, options);
+    Ok(stats)
+}
+
+/// The stamp to look `file` up in `CountOptions::cache` with, if there is
+/// a cache.
+fn cache_stamp(file: &File, options: &CountOptions) -> Option<cache::Stamp> {
+    options.cache.as_ref().and_then(|_| cache::Stamp::of(file))
+}
+
+fn cached(path: &Path, stamp: Option<cache::Stamp>, options: &CountOptions) -> Option<FileStats> {
+    let (cache, stamp) = options.cache.as_ref().zip(stamp)?;
+    cache.get(path, stamp, options)
+}
+
+fn remember(stamp: Option<cache::Stamp>, stats: &FileStats, options: &CountOptions) {
+    if let Some((cache, stamp)) = options.cache.as_ref().zip(stamp) {
+        cache.insert(stamp, stats, options);
+    }
+}
+
+/// Opens `path` if it passes the filters.
+fn open_counted(path: &Path, options: &CountOptions) -> Result<File, LineCountError> {
+    if !options.should_count(path) {
+        let path = path.to_path_buf();
+        return Err(if path.exists() {
+            LineCountError::Unsupported { path }
+        } else {
+            LineCountError::NotFound { path }
+        });
+    }
+
+    let file = File::open(path).map_err(|e| LineCountError::io(path, e))?;
+    if options.min_size.is_some() || options.max_size.is_some() || options.since.is_some() {
+        let metadata = file.metadata().map_err(|e| LineCountError::io(path, e))?;
+        if !options.size_in_range(metadata.len()) || !options.modified_in_window(path, &metadata) {
+            return Err(LineCountError::Unsupported {
+                path: path.to_path_buf(),
+            });
+        }
+    }
+    Ok(file)
+}
+
+fn count_open_file(
+    path: &Path,
+    mut file: File,
+    options: &CountOptions,
+    buffer: &mut [u8],
+) -> Result<FileStats, LineCountError> {
+    if let Some(inner) = filter::gzip_inner(path) {
+        let mut decoder = MultiGzDecoder::new(file);
+        let counts = count::count_stream(&mut decoder, &inner, buffer, options)
+            .map_err(|e| LineCountError::io(path, e))?;
+        return Ok(FileStats::from_counts(path, counts, options));
+    }
+
+    let mapped = if options.mmap {
+        count::count_mapped(&file, path, options)
+    } else {
+        None
+    };
+
+    let counts = match mapped {
+        Some(counts) => counts,
+        None => count::count_stream(&mut file, path, buffer, options)
+            .map_err(|e| LineCountError::io(path, e))?,
+    };
+
+    Ok(FileStats::from_counts(path, counts, options))
+}
+
+/// Runs `count` on a thread of its own and gives up on `path` if it
+/// doesn't finish within `timeout`. A blocking read can't be interrupted,
+/// so the thread is left to finish or hang on its own.
+///
+/// The `open_files` slot is held here rather than by the thread, so a
+/// file that's given up on no longer counts against the limit.
+fn with_timeout<T: Send + 'static>(
+    path: &Path,
+    options: &CountOptions,
+    timeout: Duration,
+    count: fn(&Path, &CountOptions) -> Result<T, LineCountError>,
+) -> Result<T, LineCountError> {
+    let _slot = open_file_slot(options);
+    let mut options = options.clone();
+    options.file_timeout = None;
+    options.open_files = None;
+    let owned = path.to_path_buf();
+    let (tx, rx) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let _ = tx.send(count(&owned, &options));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(LineCountError::Timeout {
+            path: path.to_path_buf(),
+            timeout,
+        }),
+        // The sender is only dropped unsent if `count` panicked.
+        Err(mpsc::RecvTimeoutError::Disconnected) => match handle.join() {
+            Err(panic) => std::panic::resume_unwind(panic),
+            Ok(()) => unreachable!("counting {} returned nothing", path.display()),
+        },
+    }
+}
+
+/// Files up to this size are read whole by the I/O threads of a staged walk
+/// and handed to the CPU threads. Bigger ones are counted by the I/O thread
+/// itself, so at most a few of these buffers are in memory at once.
+const STAGED_MAX_FILE: u64 = 16 * 1024 * 1024;
+
+/// What the I/O stage produced for one file.
+enum Loaded {
+    /// The contents, and the stamp to cache their count under.
+    Data(Vec<u8>, Option<cache::Stamp>),
+    Counted(Box<FileStats>),
+}
+
+fn load_file(
+    path: &Path,
+    options: &CountOptions,
+    buffer: &mut [u8],
+) -> Result<Loaded, LineCountError> {
+    if let Some(timeout) = options.file_timeout {
+        return with_timeout(path, options, timeout, |path, options| {
+            load_file(path, options, &mut vec![0u8; options.buffer_size])
+        });
+    }
+    let _slot = open_file_slot(options);
+    let mut file = open_counted(path, options)?;
+    let stamp = cache_stamp(&file, options);
+    if let Some(stats) = cached(path, stamp, options) {
+        return Ok(Loaded::Counted(Box::new(stats)));
+    }
+    let len = file
+        .metadata()
+        .map_err(|e| LineCountError::io(path, e))?
+        .len();
+    if len > STAGED_MAX_FILE {
+        let stats = count_open_file(path, file, options, buffer)?;
+        remember(stamp, &stats, options);
+        return Ok(Loaded::Counted(Box::new(stats)));
+    }
+    let mut data = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut data)
+        .map_err(|e| LineCountError::io(path, e))?;
+    Ok(Loaded::Data(data, stamp))
+}
+
+/// Counts a file that [`load_file`] read into memory.
+fn count_loaded(
+    path: &Path,
+    data: &[u8],
+    stamp: Option<cache::Stamp>,
+    options: &CountOptions,
+    buffer: &mut [u8],
+) -> Result<FileStats, LineCountError> {
+    let counts = match filter::gzip_inner(path) {
+        Some(inner) => count::count_stream(&mut MultiGzDecoder::new(data), &inner, buffer, options)
+            .map_err(|e| LineCountError::io(path, e))?,
+        None => count::count_slice(data, path, options),
+    };
+    let stats = FileStats::from_counts(path, counts, options);
+    remember(stamp, &stats, options);
+    Ok(stats)
+}
+
+/// Counts the lines of any reader, such as stdin.
+///
+/// No extension filtering is applied since there is no path to check. The
+/// returned stats have `-` as their path.
+pub fn count_reader<R: Read>(
+    reader: &mut R,
+    options: &CountOptions,
+) -> Result<FileStats, LineCountError> {
+    let mut buffer = vec![0u8; options.buffer_size];
+    count_reader_with(reader, options, &mut buffer)
+}
+
+fn count_reader_with<R: Read>(
+    reader: &mut R,
+    options: &CountOptions,
+    buffer: &mut [u8],
+) -> Result<FileStats, LineCountError> {
+    let path = Path::new("-");
+    let counts = count::count_stream(reader, path, buffer, options).map_err(|source| {
+        LineCountError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    })?;
+    Ok(FileStats::from_counts(path, counts, options))
+}
+
+/// Walks a directory and yields the files that [`count_directory`] would
+/// count, after the walk settings and every file filter have been applied,
+/// plus an error for each directory that couldn't be listed.
+pub fn select_files<'a>(
+    path: &Path,
+    options: &'a CountOptions,
+) -> impl Iterator<Item = Result<PathBuf, LineCountError>> + 'a {
+    let mut inodes = InodeSet::new(options);
+    walk_candidates(path, options).filter(move |item| match item {
+        Ok(p) => match selection(p, options) {
+            Selection::Count(m) => !inodes.is_duplicate(p, &m),
+            _ => false,
+        },
+        Err(_) => true,
+    })
+}
+
+/// Walks a directory and lazily counts each file [`select_files`] yields,
+/// so the caller can compute their own aggregates without collecting every
+/// result first.
+///
+/// Files are counted one at a time on the calling thread as the iterator
+/// is advanced, in `options.walk_order`: by name with `options.sort_walk`,
+/// and otherwise the same on every run for an unchanged tree. Use
+/// [`count_directory`] to count on `options.jobs` threads instead; there
+/// the order isn't fixed.
+pub fn walk_and_count<'a>(
+    root: &Path,
+    options: &'a CountOptions,
+) -> impl Iterator<Item = Result<FileStats, LineCountError>> + 'a {
+    let mut buffer = vec![0u8; options.buffer_size];
+    select_files(root, options)
+        .take_while(|_| !capped(options))
+        .map(move |item| {
+            let stats = count_file_with(&item?, options, &mut buffer)?;
+            spend(options, &stats);
+            Ok(stats)
+        })
+}
+
+fn walk_candidates<'a>(
+    path: &Path,
+    options: &'a CountOptions,
+) -> Box<dyn Iterator<Item = walk::WalkItem> + 'a> {
+    if options.recursive
+        || options.max_depth.is_some()
+        || !options.recursive_paths.is_empty()
+        || options.empty_dirs
+    {
+        Box::new(walk::walk_recursive(path, options))
+    } else {
+        walk::walk_shallow(path, options)
+    }
+}
+
+enum Selection {
+    Count(Metadata),
+    /// A regular file rejected by the file filters.
+    Filtered,
+    /// A symlink to a regular file, without `follow_symlinks`.
+    Symlink,
+    /// Not a regular file (and not a symlink to one).
+    Other,
+}
+
+/// Decides what to do with a path found while walking.
+fn selection(path: &Path, options: &CountOptions) -> Selection {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(m) if m.file_type().is_symlink() => match fs::metadata(path) {
+            Ok(m) if m.is_file() && !options.follow_symlinks => {
+                verbose!(options, 1, "skip {}: symlink", path.display());
+                return Selection::Symlink;
+            }
+            target => target,
+        },
+        m => m,
+    };
+    match metadata {
+        Ok(m) if m.is_file() => {
+            if !options.should_count(path) {
+                verbose!(
+                    options,
+                    1,
+                    "skip {}: not a counted file type",
+                    path.display()
+                );
+                Selection::Filtered
+            } else if !options.size_in_range(m.len()) {
+                verbose!(
+                    options,
+                    1,
+                    "skip {}: size {} out of range",
+                    path.display(),
+                    m.len()
+                );
+                Selection::Filtered
+            } else if !options.modified_in_window(path, &m) {
+                verbose!(options, 1, "skip {}: not modified recently", path.display());
+                Selection::Filtered
+            } else {
+                Selection::Count(m)
+            }
+        }
+        _ => Selection::Other,
+    }
+}
+
+/// Remembers the files seen so far by device and inode number, so
+/// hardlinks are only counted once, and checks `CountOptions::counted`.
+struct InodeSet<'a> {
+    enabled: bool,
+    verbose: u8,
+    seen: HashSet<(u64, u64)>,
+    counted: Option<&'a CountedFiles>,
+}
+
+impl<'a> InodeSet<'a> {
+    fn new(options: &'a CountOptions) -> Self {
+        InodeSet {
+            enabled: options.dedup_inodes,
+            verbose: options.verbose,
+            seen: HashSet::new(),
+            counted: options.counted.as_deref(),
+        }
+    }
+
+    fn is_duplicate(&mut self, path: &Path, metadata: &Metadata) -> bool {
+        let duplicate = self.enabled && file_id(metadata).is_some_and(|id| !self.seen.insert(id));
+        if duplicate {
+            verbose!(
+                self,
+                1,
+                "skip {}: hardlink to a counted file",
+                path.display()
+            );
+            return true;
+        }
+        if self.counted.is_some_and(|counted| !counted.insert(path)) {
+            verbose!(self, 1, "skip {}: already counted", path.display());
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Running totals shared between worker threads.
+///
+/// The counters are lock-free so workers finishing many small files don't
+/// contend on a lock. `Relaxed` is enough for them: each is a plain sum
+/// that nothing reads while it's being updated, and `into_stats` only runs
+/// once the thread scope has joined every worker, which already orders all
+/// of their increments before it. Only `longest` needs a lock, since it
+/// compares and replaces a whole value; it's only taken with
+/// `CountOptions::longest`. Per-extension totals don't need sharing, as they're
+/// summed by the caller's `on_file`, which runs on a single thread.
+#[derive(Default)]
+struct SharedTotals {
+    lines: AtomicU64,
+    words: AtomicU64,
+    chars: AtomicU64,
+    tokens: AtomicU64,
+    bytes: AtomicU64,
+    filtered: AtomicU64,
+    binary: AtomicU64,
+    duplicates: AtomicU64,
+    symlinks: AtomicU64,
+    errors: AtomicU64,
+    permission_denied: AtomicU64,
+    timed_out: AtomicU64,
+    truncated: AtomicU64,
+    blank: AtomicU64,
+    comment: AtomicU64,
+    code: AtomicU64,
+    lf: AtomicU64,
+    crlf: AtomicU64,
+    jsonl_valid: AtomicU64,
+    jsonl_invalid: AtomicU64,
+    longest: Mutex<Option<LongestLine>>,
+    line_hashes: Mutex<HashSet<u64>>,
+}
+
+impl SharedTotals {
+    fn add(&self, stats: &FileStats) {
+        self.lines.fetch_add(stats.lines, Ordering::Relaxed);
+        self.truncated.fetch_add(stats.truncated, Ordering::Relaxed);
+        self.binary
+            .fetch_add(stats.skipped.binary, Ordering::Relaxed);
+        self.words
+            .fetch_add(stats.words.unwrap_or(0), Ordering::Relaxed);
+        self.chars
+            .fetch_add(stats.chars.unwrap_or(0), Ordering::Relaxed);
+        self.tokens
+            .fetch_add(stats.tokens.unwrap_or(0), Ordering::Relaxed);
+        self.bytes
+            .fetch_add(stats.bytes.unwrap_or(0), Ordering::Relaxed);
+        if let Some(classes) = stats.classes {
+            self.blank.fetch_add(classes.blank, Ordering::Relaxed);
+            self.comment.fetch_add(classes.comment, Ordering::Relaxed);
+            self.code.fetch_add(classes.code, Ordering::Relaxed);
+        }
+        if let Some(endings) = stats.endings {
+            self.lf.fetch_add(endings.lf, Ordering::Relaxed);
+            self.crlf.fetch_add(endings.crlf, Ordering::Relaxed);
+        }
+        if let Some(records) = &stats.jsonl {
+            self.jsonl_valid.fetch_add(records.valid, Ordering::Relaxed);
+            self.jsonl_invalid
+                .fetch_add(records.invalid, Ordering::Relaxed);
+        }
+        if let Some(other) = &stats.longest {
+            let mut longest = self.longest.lock().unwrap();
+            if other.beats(longest.as_ref()) {
+                *longest = Some(other.clone());
+            }
+        }
+    }
+
+    fn add_filtered(&self) {
+        self.filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add_duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add_symlink(&self) {
+        self.symlinks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add_error(&self, error: &LineCountError) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        if error.is_permission_denied() {
+            self.permission_denied.fetch_add(1, Ordering::Relaxed);
+        }
+        if error.is_timeout() {
+            self.timed_out.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn into_stats(self, path: &Path, options: &CountOptions) -> FileStats {
+        let line_hashes = self.line_hashes.into_inner().unwrap();
+        FileStats {
+            path: path.to_path_buf(),
+            lines: self.lines.into_inner(),
+            words: options.words.then(|| self.words.into_inner()),
+            chars: options.chars.then(|| self.chars.into_inner()),
+            tokens: options.tokens.then(|| self.tokens.into_inner()),
+            bytes: options.bytes.then(|| self.bytes.into_inner()),
+            classes: options.classify.then(|| LineClasses {
+                blank: self.blank.into_inner(),
+                comment: self.comment.into_inner(),
+                code: self.code.into_inner(),
+            }),
+            longest: self.longest.into_inner().unwrap(),
+            endings: options.check_endings.then(|| Endings {
+                lf: self.lf.into_inner(),
+                crlf: self.crlf.into_inner(),
+            }),
+            unique_lines: options.dedup_lines.then_some(line_hashes.len() as u64),
+            line_hashes: options.dedup_lines.then_some(line_hashes),
+            jsonl: options.jsonl_validate.then(|| JsonlRecords {
+                valid: self.jsonl_valid.into_inner(),
+                invalid: self.jsonl_invalid.into_inner(),
+                invalid_lines: Vec::new(),
+            }),
+            skipped: Skipped {
+                filtered: self.filtered.into_inner(),
+                binary: self.binary.into_inner(),
+                duplicates: self.duplicates.into_inner(),
+                symlinks: self.symlinks.into_inner(),
+            },
+            errors: self.errors.into_inner(),
+            permission_denied: self.permission_denied.into_inner(),
+            timed_out: self.timed_out.into_inner(),
+            truncated: self.truncated.into_inner(),
+            empty_dirs: options.empty_dirs.then(Vec::new),
+        }
+    }
+}
+
+type FileResult = Result<FileStats, LineCountError>;
+
+/// The results for the walk item at an index: one for a file or a walk
+/// error, and one per counted member for an archive.
+type Indexed = (usize, Vec<FileResult>);
+
+/// The callback of [`count_tree`], run on the thread that counted a file.
+type OnCounted<'a> = Option<&'a (dyn Fn(&FileStats) + Sync)>;
+
+/// Spawns `options.jobs` threads that each read and count whole files.
+fn spawn_workers<'scope>(
+    s: &'scope thread::Scope<'scope, '_>,
+    options: &'scope CountOptions,
+    totals: &'scope SharedTotals,
+    path_rx: &'scope Mutex<mpsc::Receiver<(usize, PathBuf)>>,
+    result_tx: &mpsc::Sender<Indexed>,
+    on_counted: OnCounted<'scope>,
+) {
+    for _ in 0..options.jobs.max(1) {
+        let result_tx = result_tx.clone();
+        s.spawn(move || {
+            // Trees of many small files are dominated by per-file overhead,
+            // so each worker allocates its read buffer once and reuses it
+            // for every file it counts. The counting loops never look past
+            // the bytes returned by the current read, so leftovers from the
+            // previous file are harmless.
+            let mut buffer = vec![0u8; options.buffer_size];
+            loop {
+                let (index, p) = match path_rx.lock().unwrap().recv() {
+                    Ok(item) => item,
+                    Err(_) => break,
+                };
+
+                let reported = if options.archives && is_archive(&p) {
+                    let sink = (&result_tx, index, on_counted);
+                    report_archive(&p, options, &mut buffer, totals, sink)
+                } else {
+                    let result = count_file_with(&p, options, &mut buffer);
+                    report(options, totals, (&result_tx, index, on_counted), result)
+                };
+                if !reported {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Spawns I/O threads that read files into memory and CPU threads that
+/// count them, connected by a bounded channel so reading can't run
+/// arbitrarily far ahead of counting.
+fn spawn_staged<'scope>(
+    s: &'scope thread::Scope<'scope, '_>,
+    options: &'scope CountOptions,
+    totals: &'scope SharedTotals,
+    path_rx: &'scope Mutex<mpsc::Receiver<(usize, PathBuf)>>,
+    result_tx: &mpsc::Sender<Indexed>,
+    on_counted: OnCounted<'scope>,
+) {
+    let io_threads = options.io_threads.unwrap_or(options.jobs).max(1);
+    let cpu_threads = options.cpu_threads.unwrap_or(options.jobs).max(1);
+    let (data_tx, data_rx) =
+        mpsc::sync_channel::<(usize, PathBuf, Vec<u8>, Option<cache::Stamp>)>(cpu_threads * 2);
+
+    for _ in 0..io_threads {
+        let data_tx = data_tx.clone();
+        let result_tx = result_tx.clone();
+        s.spawn(move || {
+            let mut buffer = vec![0u8; options.buffer_size];
+            loop {
+                let (index, p) = match path_rx.lock().unwrap().recv() {
+                    Ok(item) => item,
+                    Err(_) => break,
+                };
+
+                // Archives are read as they're counted, member by member.
+                let sink = (&result_tx, index, on_counted);
+                if options.archives && is_archive(&p) {
+                    if !report_archive(&p, options, &mut buffer, totals, sink) {
+                        break;
+                    }
+                    continue;
+                }
+                let sent = match load_file(&p, options, &mut buffer) {
+                    Ok(Loaded::Data(data, stamp)) => data_tx.send((index, p, data, stamp)).is_ok(),
+                    Ok(Loaded::Counted(stats)) => report(options, totals, sink, Ok(*stats)),
+                    Err(e) => report(options, totals, sink, Err(e)),
+                };
+                if !sent {
+                    break;
+                }
+            }
+        });
+    }
+    // The CPU stage ends once every I/O thread has dropped its sender.
+    drop(data_tx);
+
+    let data_rx = Arc::new(Mutex::new(data_rx));
+    for _ in 0..cpu_threads {
+        let data_rx = Arc::clone(&data_rx);
+        let result_tx = result_tx.clone();
+        s.spawn(move || {
+            let mut buffer = vec![0u8; options.buffer_size];
+            loop {
+                let (index, p, data, stamp) = match data_rx.lock().unwrap().recv() {
+                    Ok(item) => item,
+                    Err(_) => break,
+                };
+
+                let result = count_loaded(&p, &data, stamp, options, &mut buffer);
+                if !report(options, totals, (&result_tx, index, on_counted), result) {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Where a worker's results go: the results channel, tagged with the walk
+/// index of the item they belong to, and the callback of [`count_tree`].
+type Sink<'a> = (&'a mpsc::Sender<Indexed>, usize, OnCounted<'a>);
+
+/// Adds `result` to `totals`, shows it to `on_counted` and passes it on to
+/// the calling thread. Returns false once nobody is listening anymore, or
+/// the total cap has been reached.
+fn report(
+    options: &CountOptions,
+    totals: &SharedTotals,
+    sink: Sink,
+    mut result: FileResult,
+) -> bool {
+    let (result_tx, index, on_counted) = sink;
+    tally(options, totals, on_counted, &mut result);
+    result_tx.send((index, vec![result])).is_ok() && !capped(options)
+}
+
+/// The bookkeeping [`report`] does for each result before sending it.
+fn tally(
+    options: &CountOptions,
+    totals: &SharedTotals,
+    on_counted: OnCounted,
+    result: &mut FileResult,
+) {
+    match result {
+        Ok(stats) => {
+            totals.add(stats);
+            spend(options, stats);
+            // Each file's hashes only matter for the total, which keeps
+            // its own union of them.
+            if let Some(hashes) = stats.line_hashes.take() {
+                totals.line_hashes.lock().unwrap().extend(hashes);
+            }
+            if let Some(on_counted) = on_counted {
+                on_counted(stats);
+            }
+        }
+        Err(e) => totals.add_error(e),
+    }
+}
+
+/// Counts an archive found while walking and reports its members together,
+/// each like a file of its own. Members past the total cap are left out.
+/// Returns false when [`report`] would.
+fn report_archive(
+    path: &Path,
+    options: &CountOptions,
+    buffer: &mut [u8],
+    totals: &SharedTotals,
+    sink: Sink,
+) -> bool {
+    let (result_tx, index, on_counted) = sink;
+    let results = match archive_members(path, options, buffer) {
+        Ok((members, filtered)) => {
+            totals.filtered.fetch_add(filtered, Ordering::Relaxed);
+            let mut results = Vec::with_capacity(members.len());
+            for member in members {
+                let mut result = Ok(member);
+                tally(options, totals, on_counted, &mut result);
+                results.push(result);
+                if capped(options) {
+                    break;
+                }
+            }
+            results
+        }
+        Err(e) => {
+            let mut result = Err(e);
+            tally(options, totals, on_counted, &mut result);
+            vec![result]
+        }
+    };
+    result_tx.send((index, results)).is_ok() && !capped(options)
+}
+
+/// Counts every file in a directory and returns the combined stats.
+///
+/// Files are counted concurrently on `options.jobs` worker threads while the
+/// directory is still being walked. `on_file` is called on the calling
+/// thread with the stats of each file in walk order, however many jobs
+/// there are: a file counted early waits for the files walked before it.
+/// Files and directories inside `path` that can't be read are passed to
+/// `on_error` the same way, skipped, and tallied in the returned `errors`. Files left out by the filters are tallied in
+/// `skipped`.
+///
+/// Fails without walking if `path` itself doesn't exist or isn't a
+/// directory.
+pub fn count_directory<F, E>(
+    path: &Path,
+    options: &CountOptions,
+    mut on_file: F,
+    mut on_error: E,
+) -> Result<FileStats, LineCountError>
+where
+    F: FnMut(&FileStats),
+    E: FnMut(&LineCountError),
+{
+    count_tree_with(path, options, None, &mut on_file, &mut on_error)
+}
+
+/// Counts every file in a directory like [`count_directory`], but calls
+/// `on_file` on the worker thread that counted each file, as soon as it's
+/// done. With `options.jobs` above 1 it runs on several threads at once,
+/// so it must be `Send + Sync` and keep any state of its own behind atomics
+/// or locks; in exchange nothing waits on the calling thread. Files that
+/// couldn't be read are only tallied in the returned `errors`.
+///
+/// ```no_run
+/// use std::path::Path;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use linecount::{count_tree, CountOptions};
+///
+/// let options = CountOptions { recursive: true, ..CountOptions::default() };
+/// let lines = AtomicU64::new(0);
+/// let total = count_tree(Path::new("src"), &options, |stats| {
+///     let sum = lines.fetch_add(stats.lines, Ordering::Relaxed) + stats.lines;
+///     eprint!("\r{} lines so far", sum);
+/// })?;
+/// eprintln!();
+/// assert_eq!(total.lines, lines.into_inner());
+/// # Ok::<(), linecount::LineCountError>(())
+/// ```
+pub fn count_tree<F>(
+    root: &Path,
+    options: &CountOptions,
+    on_file: F,
+) -> Result<FileStats, LineCountError>
+where
+    F: Fn(&FileStats) + Send + Sync,
+{
+    count_tree_with(root, options, Some(&on_file), &mut |_| {}, &mut |_| {})
+}
+
+fn count_tree_with(
+    path: &Path,
+    options: &CountOptions,
+    on_counted: OnCounted,
+    on_file: &mut dyn FnMut(&FileStats),
+    on_error: &mut dyn FnMut(&LineCountError),
+) -> Result<FileStats, LineCountError> {
+    let metadata = path.metadata().map_err(|e| LineCountError::io(path, e))?;
+    if !metadata.is_dir() {
+        return Err(LineCountError::Unsupported {
+            path: path.to_path_buf(),
+        });
+    }
+
+    let totals = SharedTotals::default();
+    let (path_tx, path_rx) = mpsc::channel::<(usize, PathBuf)>();
+    let path_rx = Mutex::new(path_rx);
+    let (result_tx, result_rx) = mpsc::channel::<Indexed>();
+
+    let empty_dirs = thread::scope(|s| {
+        let totals = &totals;
+        let walk_tx = result_tx.clone();
+        let walker = s.spawn(move || {
+            let mut inodes = InodeSet::new(options);
+            // Only walked directories are yielded as `Other`.
+            let mut dirs = Vec::new();
+            // Numbers the files sent to the workers and the walk errors.
+            let mut index = 0;
+            for item in walk_candidates(path, options) {
+                if capped(options) {
+                    break;
+                }
+                match item {
+                    Ok(p) => match selection(&p, options) {
+                        Selection::Count(m) if inodes.is_duplicate(&p, &m) => {
+                            totals.add_duplicate()
+                        }
+                        Selection::Count(_) => {
+                            if path_tx.send((index, p)).is_err() {
+                                break;
+                            }
+                            index += 1;
+                        }
+                        Selection::Filtered => totals.add_filtered(),
+                        Selection::Symlink => totals.add_symlink(),
+                        Selection::Other if options.empty_dirs && p.is_dir() => dirs.push(p),
+                        Selection::Other => {}
+                    },
+                    Err(e) => {
+                        totals.add_error(&e);
+                        if walk_tx.send((index, vec![Err(e)])).is_err() {
+                            break;
+                        }
+                        index += 1;
+                    }
+                }
+            }
+            dirs
+        });
+
+        if options.io_threads.is_some() || options.cpu_threads.is_some() {
+            spawn_staged(s, options, totals, &path_rx, &result_tx, on_counted);
+        } else {
+            spawn_workers(s, options, totals, &path_rx, &result_tx, on_counted);
+        }
+
+        // Only the walker's and workers' clones should keep the results
+        // channel open.
+        drop(result_tx);
+        let mut nonempty = HashSet::new();
+        let mut deliver = |results: Vec<FileResult>| {
+            for result in results {
+                match result {
+                    Ok(stats) => {
+                        if options.empty_dirs && stats.lines > 0 {
+                            mark_nonempty(&mut nonempty, path, &stats.path);
+                        }
+                        on_file(&stats);
+                    }
+                    Err(e) => on_error(&e),
+                }
+            }
+        };
+        // Results that arrived ahead of an earlier walk item.
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (index, results) in result_rx {
+            pending.insert(index, results);
+            while let Some(results) = pending.remove(&next) {
+                deliver(results);
+                next += 1;
+            }
+        }
+        // Items the cap stopped before they were counted leave gaps.
+        pending.into_values().for_each(&mut deliver);
+
+        let mut dirs = walker.join().unwrap();
+        dirs.retain(|d| !nonempty.contains(d));
+        dirs.sort();
+        dirs
+    });
+
+    let mut stats = totals.into_stats(path, options);
+    if options.empty_dirs {
+        stats.empty_dirs = Some(empty_dirs);
+    }
+    Ok(stats)
+}
+
+/// Adds the directories from `file`'s parent up to `root` to `nonempty`.
+fn mark_nonempty(nonempty: &mut HashSet<PathBuf>, root: &Path, file: &Path) {
+    for dir in file.ancestors().skip(1) {
+        // Once a directory is in, so are all of its ancestors.
+        if !nonempty.insert(dir.to_path_buf()) || dir == root {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    #[cfg(unix)]
+    fn fifo(dir: &TestDir, name: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        let status = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        path
+    }
+
+    /// A few directories of files with different line counts.
+    fn tree() -> TestDir {
+        let dir = TestDir::new();
+        for i in 0..40 {
+            let lines = "line\n".repeat(i * 7 + 1);
+            dir.file(&format!("d{}/e{}/f{}.rs", i % 3, i % 5, i), lines);
+        }
+        dir.file("top.txt", "a\nb\nc");
+        dir
+    }
+
+    fn total(path: &Path, options: &CountOptions) -> FileStats {
+        count_directory(path, options, |_| {}, |e| panic!("{}", e)).unwrap()
+    }
+
+    /// The files `select_files` picks in `dir`, relative to it and sorted.
+    fn selected(dir: &TestDir, options: &CountOptions) -> Vec<String> {
+        let mut files: Vec<String> = select_files(dir.path(), options)
+            .map(|p| {
+                let p = p.unwrap();
+                p.strip_prefix(dir.path()).unwrap().display().to_string()
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_only_followed_when_asked() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TestDir::new();
+        let target = TestDir::new();
+        dir.file("real.rs", "1\n");
+        let file = target.file("file.rs", "1\n2\n");
+        target.file("sub/inner.rs", "1\n2\n3\n");
+        symlink(&file, dir.path().join("file_link.rs")).unwrap();
+        symlink(target.path().join("sub"), dir.path().join("dir_link")).unwrap();
+
+        let skip = CountOptions {
+            recursive: true,
+            ..CountOptions::default()
+        };
+        let follow = CountOptions {
+            follow_symlinks: true,
+            ..skip.clone()
+        };
+        // A symlinked file, and a symlinked directory, without following.
+        assert_eq!(selected(&dir, &skip), ["real.rs"]);
+        let stats = total(dir.path(), &skip);
+        assert_eq!((stats.lines, stats.skipped.symlinks), (1, 1));
+        // Both of them again, followed.
+        assert_eq!(
+            selected(&dir, &follow),
+            ["dir_link/inner.rs", "file_link.rs", "real.rs"]
+        );
+        let stats = total(dir.path(), &follow);
+        assert_eq!((stats.lines, stats.skipped.symlinks), (6, 0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dedup_inodes_counts_hardlinks_once() {
+        let dir = TestDir::new();
+        let file = dir.file("a.rs", "1\n2\n");
+        fs::hard_link(&file, dir.path().join("b.rs")).unwrap();
+        dir.file("c.rs", "1\n");
+        let every_path = CountOptions::default();
+        assert_eq!(total(dir.path(), &every_path).lines, 5);
+        let dedup = CountOptions {
+            dedup_inodes: true,
+            ..CountOptions::default()
+        };
+        let stats = total(dir.path(), &dedup);
+        assert_eq!((stats.lines, stats.skipped.duplicates), (3, 1));
+        assert_eq!(selected(&dir, &dedup), ["a.rs", "c.rs"]);
+    }
+
+    #[test]
+    fn a_file_inside_a_counted_directory_is_counted_once() {
+        let dir = TestDir::new();
+        let file = dir.file("a.rs", "1\n2\n");
+        dir.file("b.rs", "1\n");
+        let options = CountOptions {
+            counted: Some(Arc::new(CountedFiles::new())),
+            ..CountOptions::default()
+        };
+        assert_eq!(total(dir.path(), &options).lines, 3);
+        let again = count_file(&file, &options).unwrap();
+        assert_eq!((again.lines, again.skipped.duplicates), (0, 1));
+
+        // The other way around, and through a different spelling.
+        let options = CountOptions {
+            counted: Some(Arc::new(CountedFiles::new())),
+            ..CountOptions::default()
+        };
+        assert_eq!(count_file(&file, &options).unwrap().lines, 2);
+        let stats = total(&dir.path().join("."), &options);
+        assert_eq!((stats.lines, stats.skipped.duplicates), (1, 1));
+    }
+
+    #[test]
+    fn min_size_includes_files_of_exactly_that_size() {
+        let dir = TestDir::new();
+        let exact = dir.file("exact.txt", "1234\n6789\n");
+        let small = dir.file("small.txt", "1234\n678\n");
+        let options = CountOptions {
+            min_size: Some(10),
+            ..CountOptions::default()
+        };
+        let stats = total(dir.path(), &options);
+        assert_eq!((stats.lines, stats.skipped.filtered), (2, 1));
+        assert_eq!(count_file(&exact, &options).unwrap().lines, 2);
+        assert!(matches!(
+            count_file(&small, &options),
+            Err(LineCountError::Unsupported { .. })
+        ));
+        let options = CountOptions {
+            max_size: Some(9),
+            ..CountOptions::default()
+        };
+        assert_eq!(selected(&dir, &options), ["small.txt"]);
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn gzip_files_count_their_decompressed_lines() {
+        let dir = TestDir::new();
+        let log = dir.file("app.log.gz", gzip(b"one\ntwo\nthree\n"));
+        let options = CountOptions {
+            bytes: true,
+            ..CountOptions::default()
+        };
+        let stats = count_file(&log, &options).unwrap();
+        assert_eq!((stats.lines, stats.bytes), (3, Some(14)));
+        // Concatenated streams are read to the end.
+        let both = [gzip(b"a\nb\n"), gzip(b"c\n")].concat();
+        let both = dir.file("both.txt.gz", both);
+        assert_eq!(count_file(&both, &options).unwrap().lines, 3);
+        // The extension filter looks at the decompressed name.
+        dir.file("image.png.gz", gzip(b"x\n"));
+        assert_eq!(selected(&dir, &options), ["app.log.gz", "both.txt.gz"]);
+    }
+
+    #[test]
+    fn the_job_count_does_not_change_the_totals() {
+        let dir = tree();
+        let options = |jobs| CountOptions {
+            recursive: true,
+            words: true,
+            chars: true,
+            jobs,
+            ..CountOptions::default()
+        };
+        let one = total(dir.path(), &options(1));
+        assert_eq!(one.lines, (0..40).map(|i| i * 7 + 1).sum::<u64>() + 3);
+        for _ in 0..5 {
+            let eight = total(dir.path(), &options(8));
+            assert_eq!(
+                (one.lines, one.words, one.chars),
+                (eight.lines, eight.words, eight.chars)
+            );
+        }
+    }
+
+    #[test]
+    fn totals_go_past_i32_and_u32_max() {
+        let options = CountOptions {
+            words: true,
+            ..CountOptions::default()
+        };
+        let mut file = FileStats::new(Path::new("big.txt"), &options);
+        file.lines = u64::from(u32::MAX);
+        file.words = Some(u64::from(u32::MAX));
+        let mut total = FileStats::new(Path::new(""), &options);
+        for _ in 0..3 {
+            total.add(&file);
+        }
+        assert!(total.lines > i32::MAX as u64);
+        assert_eq!(total.lines, 3 * u64::from(u32::MAX));
+        assert_eq!(total.words, Some(3 * u64::from(u32::MAX)));
+    }
+
+    /// Yields `chunk` over and over, `times` times.
+    struct Repeat {
+        chunk: Vec<u8>,
+        times: u64,
+    }
+
+    impl Read for Repeat {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.times == 0 {
+                return Ok(0);
+            }
+            // Reads always line up with whole chunks.
+            let n = self.chunk.len().min(buf.len());
+            assert_eq!(n, self.chunk.len());
+            buf[..n].copy_from_slice(&self.chunk);
+            self.times -= 1;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn counted_stream_goes_past_i32_max() {
+        const CHUNK: u64 = 1 << 20;
+        const TIMES: u64 = 2100;
+        let options = CountOptions {
+            bytes: true,
+            buffer_size: CHUNK as usize,
+            ..CountOptions::default()
+        };
+        // Mostly empty lines, so the line count gets past i32::MAX too.
+        let mut chunk = b"abc\n".to_vec();
+        chunk.resize(CHUNK as usize, b'\n');
+        let mut reader = Repeat {
+            chunk,
+            times: TIMES,
+        };
+        let stats = count_reader(&mut reader, &options).unwrap();
+        assert!(stats.lines > i32::MAX as u64);
+        assert_eq!(stats.lines, (CHUNK - 3) * TIMES);
+        assert_eq!(stats.bytes, Some(CHUNK * TIMES));
+    }
+
+    #[test]
+    fn shared_totals_add_up_exactly_across_threads() {
+        const THREADS: u64 = 16;
+        const FILES: u64 = 10_000;
+        let options = CountOptions {
+            words: true,
+            ..CountOptions::default()
+        };
+        let totals = SharedTotals::default();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let (totals, options) = (&totals, &options);
+                s.spawn(move || {
+                    let mut stats = FileStats::new(Path::new("a.txt"), options);
+                    for i in 0..FILES {
+                        stats.lines = t + i;
+                        stats.words = Some(1);
+                        totals.add(&stats);
+                        totals.add_filtered();
+                    }
+                });
+            }
+        });
+        let stats = totals.into_stats(Path::new(""), &options);
+        let per_thread = FILES * (FILES - 1) / 2;
+        let expected = THREADS * per_thread + FILES * THREADS * (THREADS - 1) / 2;
+        assert_eq!(stats.lines, expected);
+        assert_eq!(stats.words, Some(THREADS * FILES));
+        assert_eq!(stats.skipped.filtered, THREADS * FILES);
+    }
+
+    #[test]
+    fn mmap_counts_like_buffered_reads() {
+        let dir = TestDir::new();
+        let buffered = CountOptions {
+            words: true,
+            chars: true,
+            buffer_size: 3,
+            ..CountOptions::default()
+        };
+        let mapped = CountOptions {
+            mmap: true,
+            ..buffered.clone()
+        };
+        for contents in ["one two\nthree\n", "one two\nthree", "", "\n"] {
+            let path = dir.file("a.txt", contents);
+            let a = count_file(&path, &buffered).unwrap();
+            let b = count_file(&path, &mapped).unwrap();
+            assert_eq!((a.lines, a.words, a.chars), (b.lines, b.words, b.chars));
+        }
+        let path = dir.file("a.txt", "one two\nthree");
+        assert_eq!(count_file(&path, &mapped).unwrap().lines, 2);
+    }
+
+    /// Opening a FIFO nobody writes to blocks, like a hung network mount.
+    #[cfg(unix)]
+    #[test]
+    fn timed_out_files_give_back_their_open_file_slot() {
+        let dir = TestDir::new();
+        let file = dir.file("a.txt", "one\ntwo\n");
+        let options = CountOptions {
+            file_timeout: Some(Duration::from_millis(50)),
+            open_files: Some(Arc::new(OpenFileLimit::new(1))),
+            ..CountOptions::default()
+        };
+        for name in ["hung1.txt", "hung2.txt", "hung3.txt"] {
+            let error = count_file(&fifo(&dir, name), &options).unwrap_err();
+            assert!(error.is_timeout(), "{}", error);
+        }
+        assert_eq!(count_file(&file, &options).unwrap().lines, 2);
+    }
+
+    #[test]
+    fn a_limit_of_one_open_file_keeps_the_totals() {
+        let dir = tree();
+        let unlimited = CountOptions {
+            recursive: true,
+            open_files: None,
+            ..CountOptions::default()
+        };
+        let expected = total(dir.path(), &unlimited).lines;
+        for staged in [None, Some(4)] {
+            let limit = Arc::new(OpenFileLimit::new(1));
+            let options = CountOptions {
+                jobs: 8,
+                io_threads: staged,
+                open_files: Some(Arc::clone(&limit)),
+                ..unlimited.clone()
+            };
+            assert_eq!(total(dir.path(), &options).lines, expected);
+            assert_eq!(limit.peak(), 1);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_limit_of_one_open_file_survives_timeouts() {
+        let dir = tree();
+        let hung = fifo(&dir, "hung.txt");
+        let limit = Arc::new(OpenFileLimit::new(1));
+        let options = CountOptions {
+            recursive: true,
+            jobs: 8,
+            file_timeout: Some(Duration::from_millis(50)),
+            open_files: Some(Arc::clone(&limit)),
+            ..CountOptions::default()
+        };
+        assert!(count_file(&hung, &options).unwrap_err().is_timeout());
+        let unlimited = CountOptions {
+            open_files: None,
+            ..options.clone()
+        };
+        assert_eq!(
+            total(dir.path(), &options).lines,
+            total(dir.path(), &unlimited).lines
+        );
+        assert_eq!(limit.peak(), 1);
+    }
+}
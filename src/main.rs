@@ -1,207 +1,1731 @@
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::{File, read_dir};
-use std::io::Read;
-use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
 
-use bytecount;
+use linecount::{
+    CountOptions, CountedFiles, EmptyLines, Encoding, FileCache, FileStats, LineCap,
+    LineCountError, LineEnding, OpenFileLimit, Regex, WalkOrder, combine_extensions, count_archive,
+    count_directory, count_file, count_reader, default_config_path, default_jobs, expand_glob,
+    is_archive, is_glob, load_comment_syntax, parse_extensions, parse_extensions_exact,
+    select_files,
+};
 
-const FILE_EXT_LIST: [&str; 41] = [
-    "txt", "text", "md", "markdown", "log",
-    "rs", "py", "js", "ts", "java", "c", "cpp", "h", "hpp",
-    "go", "rb", "php", "swift", "kt", "scala", "r",
-    "html", "htm", "css", "scss", "sass", "less",
-    "xml", "svg", "json", "yaml", "yml", "toml", "ini",
-    "csv", "tsv", "sql", "sh", "bash", "conf", "config",
-];
+mod baseline;
+mod bench;
+mod completions;
+mod git;
+mod output;
+mod progress;
+mod template;
+#[cfg(test)]
+#[path = "testdir.rs"]
+mod testdir;
+mod watch;
+
+use baseline::{Baseline, Change};
+use output::{Breakdowns, Comparison, DiffTotals, DirTotal, Failure, FileSummary, Flagged, Format};
+use progress::Progress;
+use template::{Context, Field, Template};
+
+/// Exit status when some file or directory couldn't be read.
+const EXIT_ERROR: i32 = 1;
+/// Exit status when a path given on the command line doesn't exist.
+const EXIT_NOT_FOUND: i32 = 2;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+    if args.len() < 2 || args.iter().skip(1).any(|a| a == "--help" || a == "-h") {
         show_help();
         return;
     }
+    if args.iter().skip(1).any(|a| a == "--version" || a == "-V") {
+        let version = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        failed_write(writeln!(io::stdout().lock(), "{}", version));
+        return;
+    }
+    if let Some(shell) = args
+        .iter()
+        .find_map(|a| a.strip_prefix("--generate-completions="))
+    {
+        match completions::script(shell) {
+            Some(script) => print!("{}", script),
+            None => {
+                eprintln!("Unknown shell '{}'. Use bash, zsh or fish.", shell);
+                process::exit(EXIT_ERROR);
+            }
+        }
+        return;
+    }
 
-    let mut buffer_size = 8 * 1024;
-    let mut skip_empty = false;
-    let mut recursive = false;
-
-    let target = &args[1];
+    let mut options = CountOptions::default();
+    let mut report = Report::default();
+    let mut list = false;
+    let mut bench = false;
+    let mut compare = false;
+    let mut watch = false;
+    let mut format_given = false;
+    let mut targets: Vec<&str> = Vec::new();
+    let mut ext_list: Option<String> = None;
+    let mut add_ext: Vec<String> = Vec::new();
+    let mut remove_ext: Vec<String> = Vec::new();
+    let mut text_ext: Vec<String> = Vec::new();
+    let mut binary_ext: Vec<String> = Vec::new();
+    let mut files_from: Option<&str> = None;
+    let mut config: Option<PathBuf> = None;
+    let mut baseline: Option<PathBuf> = None;
+    let mut git_base: Option<&str> = None;
+    let mut cache: Option<PathBuf> = None;
 
     // Parse flags
-    for arg in args.iter().skip(2) {
+    for arg in args.iter().skip(1) {
         if let Some(size) = arg.strip_prefix("--buffer-size=") {
-            buffer_size = parse_buffer_size(size);
+            options.buffer_size = parse_buffer_size(size);
         } else if arg == "--skip-empty" {
-            skip_empty = true;
+            options.skip_empty = true;
+        } else if let Some(name) = arg.strip_prefix("--empty-definition=") {
+            options.empty_lines = parse_empty_definition(name);
         } else if arg == "--recursive" {
-            recursive = true;
+            options.recursive = true;
+        } else if let Some(list) = arg.strip_prefix("--recursive-paths=") {
+            options.recursive_paths.extend(
+                list.split(',')
+                    .map(|p| p.trim().trim_matches('/').to_string())
+                    .filter(|p| !p.is_empty()),
+            );
+        } else if let Some(depth) = arg.strip_prefix("--max-depth=") {
+            options.max_depth = parse_max_depth(depth);
+        } else if arg == "--verbose" || arg == "-v" {
+            options.verbose = options.verbose.saturating_add(1);
+        } else if arg == "-vv" {
+            options.verbose = options.verbose.saturating_add(2);
+        } else if arg == "--hidden" {
+            options.hidden = true;
+        } else if arg == "--count-vcs" {
+            options.count_vcs = true;
+        } else if let Some(s) = arg.strip_prefix("--sort-walk=") {
+            options.sort_walk = parse_sort_walk(s);
+        } else if let Some(order) = arg.strip_prefix("--walk-order=") {
+            options.walk_order = parse_walk_order(order);
+        } else if let Some(pattern) = arg.strip_prefix("--exclude=") {
+            options.exclude.push(pattern.to_string());
+        } else if arg == "--follow-symlinks" {
+            options.follow_symlinks = true;
+        } else if arg == "--gitignore" {
+            options.gitignore = true;
+        } else if arg == "--no-ignore-files" {
+            options.ignore_files = false;
+        } else if let Some(n) = arg.strip_prefix("--jobs=") {
+            options.jobs = parse_jobs(n);
+        } else if let Some(n) = arg.strip_prefix("--io-threads=") {
+            options.io_threads = parse_threads(n);
+        } else if let Some(n) = arg.strip_prefix("--cpu-threads=") {
+            options.cpu_threads = parse_threads(n);
+        } else if let Some(list) = arg.strip_prefix("--ext=") {
+            ext_list = Some(list.to_string());
+        } else if let Some(list) = arg
+            .strip_prefix("--add-ext=")
+            .or_else(|| arg.strip_prefix("--include-ext="))
+        {
+            add_ext.push(list.to_string());
+        } else if arg == "--case-sensitive-ext" {
+            options.case_sensitive_ext = true;
+        } else if let Some(list) = arg.strip_prefix("--exclude-ext=") {
+            remove_ext.push(list.to_string());
+        } else if let Some(list) = arg.strip_prefix("--text-ext=") {
+            text_ext.push(list.to_string());
+        } else if let Some(list) = arg.strip_prefix("--binary-ext=") {
+            binary_ext.push(list.to_string());
+        } else if let Some(list) = arg.strip_prefix("--name=") {
+            options
+                .names
+                .extend(list.split(',').filter(|n| !n.is_empty()).map(String::from));
+        } else if let Some(size) = arg.strip_prefix("--min-size=") {
+            options.min_size = parse_size(size);
+        } else if let Some(size) = arg.strip_prefix("--max-size=") {
+            options.max_size = parse_size(size);
+        } else if let Some(window) = arg.strip_prefix("--since=") {
+            options.since = parse_since(window);
+        } else if let Some(ms) = arg.strip_prefix("--file-timeout=") {
+            options.file_timeout = parse_file_timeout(ms);
+        } else if arg == "--dedup-inodes" {
+            options.dedup_inodes = true;
+        } else if arg == "--archives" {
+            options.archives = true;
+        } else if arg == "--count-all" {
+            options.count_all = true;
+        } else if arg == "--skip-binary" {
+            options.skip_binary = true;
+        } else if arg == "--mmap" {
+            options.mmap = true;
+        } else if let Some(ending) = arg.strip_prefix("--line-ending=") {
+            options.line_ending = parse_line_ending(ending);
+        } else if let Some(d) = arg.strip_prefix("--delimiter=") {
+            if let Some(byte) = parse_delimiter(d) {
+                options.line_ending = LineEnding::Delimiter(byte);
+            }
+        } else if let Some(list) = arg.strip_prefix("--break-on=") {
+            options.extra_breaks.extend(parse_breaks(list));
+        } else if arg == "--words" {
+            options.words = true;
+        } else if arg == "--chars" {
+            options.chars = true;
+        } else if arg == "--tokens" {
+            options.tokens = true;
+        } else if arg == "--bytes" {
+            options.bytes = true;
+        } else if let Some(name) = arg.strip_prefix("--encoding=") {
+            options.encoding = parse_encoding(name);
+        } else if arg == "--dedup-lines" {
+            options.dedup_lines = true;
+        } else if arg == "--longest" {
+            options.longest = true;
+        } else if arg == "--sql-statements" {
+            options.sql_statements = true;
+        } else if arg == "--jsonl-validate" {
+            options.jsonl_validate = true;
+        } else if let Some(n) = arg.strip_prefix("--total-cap=") {
+            options.total_cap = parse_total_cap(n).map(|n| Arc::new(LineCap::new(n)));
+        } else if let Some(n) = arg.strip_prefix("--max-open-files=") {
+            if let Some(n) = parse_max_open_files(n) {
+                options.open_files = Some(Arc::new(OpenFileLimit::new(n)));
+            }
+        } else if let Some(n) = arg.strip_prefix("--max-lines=") {
+            options.max_lines = parse_max_lines(n);
+        } else if let Some(re) = arg.strip_prefix("--match=") {
+            options.matching = Some(parse_pattern(re));
+        } else if let Some(re) = arg.strip_prefix("--no-match=") {
+            options.not_matching = Some(parse_pattern(re));
+        } else if arg == "--show-empty-dirs" {
+            options.empty_dirs = true;
+        } else if arg == "--check-endings" {
+            options.check_endings = true;
+        } else if let Some(path) = arg.strip_prefix("--baseline=") {
+            baseline = Some(PathBuf::from(path));
+        } else if arg == "--git-changed" {
+            git_base = Some("HEAD");
+        } else if let Some(base) = arg.strip_prefix("--git-changed=") {
+            git_base = Some(base);
+        } else if let Some(path) = arg.strip_prefix("--cache=") {
+            cache = Some(PathBuf::from(path));
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config = Some(PathBuf::from(path));
+        } else if arg == "--classify" {
+            options.classify = true;
+        } else if arg == "--per-file" {
+            report.per_file = true;
+        } else if let Some(key) = arg.strip_prefix("--sort=") {
+            report.sort = parse_sort(key);
+        } else if let Some(n) = arg.strip_prefix("--top=") {
+            report.top = parse_top(n);
+        } else if arg == "--subtotals" {
+            report.subtotals = true;
+        } else if arg == "--by-ext" {
+            report.by_ext = true;
+        } else if arg == "--by-dir" {
+            report.by_dir = true;
+        } else if arg == "--summary-only" {
+            report.summary_only = true;
+        } else if arg == "--quiet" || arg == "-q" {
+            report.quiet = true;
+        } else if arg == "--relative" {
+            report.relative = true;
+        } else if arg == "--human" {
+            report.human = true;
+        } else if let Some(list) = arg.strip_prefix("--files-from=") {
+            files_from = Some(list);
+        } else if arg == "--progress" {
+            report.progress = true;
+        } else if arg == "--watch" {
+            watch = true;
+        } else if arg == "--compare" {
+            compare = true;
+        } else if arg == "--bench" {
+            bench = true;
+        } else if arg == "--list" {
+            list = true;
+        } else if arg == "--csv-total" {
+            report.csv_total = true;
+        } else if let Some(t) = arg.strip_prefix("--template=") {
+            report.template = Some(parse_template(t, Context::File));
+        } else if let Some(t) = arg.strip_prefix("--summary-template=") {
+            report.summary_template = Some(parse_template(t, Context::Summary));
+        } else if let Some(f) = arg.strip_prefix("--format=") {
+            report.format = output::parse_format(f);
+            format_given = true;
+        } else if let Some(path) = arg.strip_prefix("--output=") {
+            report.output = Some(PathBuf::from(path));
+        } else if arg == "-" || !arg.starts_with('-') {
+            targets.push(arg);
+            continue;
+        } else {
+            // Unknown flags are ignored.
+            continue;
         }
+        debug_assert!(
+            completions::is_known(arg),
+            "{} is missing from completions::FLAGS",
+            arg
+        );
     }
 
-    let start = std::time::Instant::now();
+    if targets.is_empty() && files_from.is_none() {
+        if report.quiet {
+            process::exit(EXIT_ERROR);
+        }
+        show_help();
+        return;
+    }
 
-    let path = Path::new(target);
+    if !format_given && let Some(path) = &report.output {
+        report.format = output::format_for_path(path);
+    }
+    // CSV and ndjson output are made of per-file records, and a file
+    // template is a way of printing them.
+    if matches!(report.format, Format::Csv | Format::Ndjson) || report.template.is_some() {
+        report.per_file = true;
+    }
+    // Whatever a template shows gets counted.
+    for template in [&report.template, &report.summary_template]
+        .into_iter()
+        .flatten()
+    {
+        options.words |= template.uses(Field::Words);
+        options.chars |= template.uses(Field::Chars);
+        options.bytes |= template.uses(Field::Bytes);
+    }
+    // Quiet mode prints the total and nothing else, and summary-only mode
+    // the summary without any listing, even of the records of CSV and
+    // ndjson output.
+    if report.quiet || report.summary_only {
+        report.per_file = false;
+        report.subtotals = false;
+        report.by_ext = false;
+        report.by_dir = false;
+    }
+    if report.summary_only && report.format == Format::Csv {
+        report.csv_total = true;
+    }
+    // --top needs a ranking, so it implies sorting by size.
+    if report.top.is_some() && report.sort.is_none() {
+        report.sort = Some(SortKey::Lines);
+    }
+
+    // A missing default config is fine; a missing --config file is not.
+    let config = config.or_else(|| default_config_path().filter(|p| p.exists()));
+    if let Some(path) = config {
+        match load_comment_syntax(&path) {
+            Ok(syntax) => options.comment_syntax = syntax,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+
+    if let Some(path) = baseline {
+        match baseline::load(&path) {
+            Ok(baseline) => report.baseline = Some(baseline),
+            Err(e) => {
+                eprintln!("Cannot read baseline {}: {}", path.display(), e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
 
-    let total = if path.is_dir() {
-        process_directory(path, buffer_size, skip_empty, recursive)
+    if let Some(path) = cache {
+        if !FileCache::supports(&options) {
+            eprintln!("--cache only stores line counts, so it isn't used with the other counts.");
+        } else {
+            match FileCache::load(&path, &options) {
+                Ok(cache) => options.cache = Some(Arc::new(cache)),
+                Err(e) => {
+                    eprintln!("Cannot read cache {}: {}", path.display(), e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+    }
+
+    let listed = match files_from.map(read_file_list).transpose() {
+        Ok(listed) => listed.unwrap_or_default(),
+        Err(e) => {
+            eprintln!(
+                "Cannot read file list {}: {}",
+                files_from.unwrap_or_default(),
+                e
+            );
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    // The lists are parsed once all flags are known, since
+    // --case-sensitive-ext may come after them.
+    let parse = if options.case_sensitive_ext {
+        parse_extensions_exact
     } else {
-        process_file(path, buffer_size, skip_empty)
+        parse_extensions
+    };
+    if let Some(list) = ext_list {
+        options.extensions = parse(&list);
+    }
+    let mut add_ext: Vec<String> = add_ext.iter().flat_map(|list| parse(list)).collect();
+    // The files --jsonl-validate is about aren't in the default list.
+    if options.jsonl_validate {
+        add_ext.extend(["jsonl", "ndjson"].map(String::from));
+    }
+    let remove_ext: Vec<String> = remove_ext.iter().flat_map(|list| parse(list)).collect();
+    // Exclusions apply last, so they also win over --ext and --include-ext.
+    options.extensions = combine_extensions(options.extensions, &add_ext, &remove_ext);
+    options.text_extensions = text_ext.iter().flat_map(|list| parse(list)).collect();
+    options.binary_extensions = binary_ext.iter().flat_map(|list| parse(list)).collect();
+
+    // Globs are expanded here unless they name an existing path, so quoted
+    // patterns work too. Their matches and the paths from --files-from are
+    // files, used as given without walking.
+    let mut missing_target = false;
+    let mut all_targets: Vec<(String, bool)> = Vec::new();
+    for target in &targets {
+        if is_glob(target) && !Path::new(target).exists() {
+            let matches = expand_glob(target, options.hidden);
+            if matches.is_empty() {
+                eprintln!("No files match {}", target);
+                missing_target = true;
+            }
+            all_targets.extend(
+                matches
+                    .into_iter()
+                    .map(|p| (p.to_string_lossy().into_owned(), false)),
+            );
+        } else {
+            all_targets.push((target.to_string(), true));
+        }
+    }
+    all_targets.extend(listed.into_iter().map(|t| (t, false)));
+
+    // Missing paths are reported before any output, and when nothing is
+    // left there's no total worth printing.
+    all_targets.retain(|(target, _)| {
+        let exists = target == "-" || Path::new(target).exists();
+        if !exists {
+            let path = PathBuf::from(target);
+            eprintln!("{}", LineCountError::NotFound { path });
+            missing_target = true;
+        }
+        exists
+    });
+    if all_targets.is_empty() && missing_target {
+        process::exit(EXIT_NOT_FOUND);
+    }
+
+    // Each target is replaced by its changed files, which are then counted
+    // like --files-from entries.
+    if let Some(base) = git_base {
+        let mut changed_paths = HashSet::new();
+        let mut changed_targets = Vec::new();
+        for (target, _) in &all_targets {
+            if target == "-" {
+                eprintln!("--git-changed can't be used with stdin.");
+                process::exit(EXIT_ERROR);
+            }
+            match git::changed(Path::new(target), base) {
+                Ok(changed) => {
+                    changed_paths.extend(changed.deleted.iter().map(|p| output::path_text(p)));
+                    for path in changed.files {
+                        changed_paths.insert(output::path_text(&path));
+                        changed_targets.push((path.to_string_lossy().into_owned(), false));
+                    }
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Cannot list files changed since {} in {}: {}",
+                        base, target, e
+                    );
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        all_targets = changed_targets;
+        // Files outside the diff would otherwise show as removed.
+        if let Some(baseline) = &mut report.baseline {
+            baseline.retain_paths(&changed_paths);
+        }
+    }
+
+    if list {
+        let options = dedup_targets(&all_targets, &options);
+        let mut errors = 0;
+        for (target, walk) in &all_targets {
+            match list_target(target, *walk, &options) {
+                Ok(n) => errors += n,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    match e {
+                        LineCountError::NotFound { .. } => missing_target = true,
+                        _ => errors += 1,
+                    }
+                }
+            }
+        }
+        if missing_target {
+            process::exit(EXIT_NOT_FOUND);
+        }
+        if errors > 0 {
+            process::exit(EXIT_ERROR);
+        }
+        return;
+    }
+
+    if bench {
+        if all_targets.iter().any(|(t, _)| t == "-") {
+            eprintln!("--bench can't be used with stdin.");
+            process::exit(EXIT_ERROR);
+        }
+        // Cached counts would skip the reading being timed.
+        options.cache = None;
+        let results = bench::bench(&options, |options| {
+            for (target, walk) in &all_targets {
+                let path = Path::new(target);
+                // Only the timing matters, and errors would repeat on every run.
+                let _ = if *walk && path.is_dir() {
+                    count_directory(path, options, |_| {}, |_| {})
+                } else {
+                    count_file(path, options)
+                };
+            }
+        });
+        failed_write(bench::print(
+            &mut io::stdout().lock(),
+            &results,
+            report.human,
+        ));
+        return;
+    }
+
+    if compare {
+        if missing_target {
+            process::exit(EXIT_NOT_FOUND);
+        }
+        if all_targets.len() != 2 || all_targets.iter().any(|(t, _)| t == "-") {
+            eprintln!("--compare needs exactly two paths, neither of them stdin.");
+            process::exit(EXIT_ERROR);
+        }
+        let outcome = run_compare(&all_targets, &options, &report);
+        if missing_target || outcome.missing_target {
+            process::exit(EXIT_NOT_FOUND);
+        }
+        if outcome.errors > 0 {
+            process::exit(EXIT_ERROR);
+        }
+        return;
+    }
+
+    if watch {
+        if all_targets.iter().any(|(t, _)| t == "-") {
+            eprintln!("--watch can't be used with stdin.");
+            process::exit(EXIT_ERROR);
+        }
+        let paths: Vec<PathBuf> = all_targets.iter().map(|(t, _)| PathBuf::from(t)).collect();
+        let clear = report.output.is_none() && io::stdout().is_terminal();
+        // The cache is rewritten after every count, which mustn't count as
+        // a change.
+        let ignored = match &options.cache {
+            Some(cache) => absolute(cache.path())
+                .into_iter()
+                .flat_map(|path| {
+                    let mut temp = path.clone().into_os_string();
+                    temp.push(".tmp");
+                    [path, PathBuf::from(temp)]
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let result = watch::watch(&paths, ignored, || {
+            if clear {
+                print!("\x1b[2J\x1b[H");
+            }
+            run(&all_targets, &options, &report);
+            save_cache(&options);
+        });
+        if let Err(e) = result {
+            eprintln!("Cannot watch for changes: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+        return;
+    }
+
+    let outcome = run(&all_targets, &options, &report);
+    save_cache(&options);
+    if missing_target || outcome.missing_target {
+        process::exit(EXIT_NOT_FOUND);
+    }
+    if outcome.errors > 0 {
+        process::exit(EXIT_ERROR);
+    }
+}
+
+/// `path` with its directory resolved, as file watchers report it. The
+/// file itself needn't exist yet.
+fn absolute(path: &Path) -> Option<PathBuf> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
     };
+    Some(fs::canonicalize(dir).ok()?.join(path.file_name()?))
+}
 
-    println!("Total lines: {}", total);
-    println!("Time taken: {:?}", start.elapsed());
+/// Writes back `--cache`. Failing to is only worth a warning, since the
+/// counts themselves are fine.
+fn save_cache(options: &CountOptions) {
+    if let Some(cache) = &options.cache
+        && let Err(e) = cache.save()
+    {
+        eprintln!("Cannot write cache: {}", e);
+    }
 }
 
-fn show_help() {
-    println!("Usage: linecount <path> [OPTIONS]\n");
-    println!("Options:");
-    println!("  --buffer-size=<KB>   Set buffer size (default: 8 KB)");
-    println!("  --skip-empty         Skip empty lines");
-    println!("  --recursive          Process directories recursively");
-    println!("  --help, -h           Show help");
+/// How the counts are printed.
+#[derive(Debug, Default)]
+struct Report {
+    format: Format,
+    per_file: bool,
+    sort: Option<SortKey>,
+    top: Option<usize>,
+    subtotals: bool,
+    by_ext: bool,
+    /// Break the total down by each target's first-level directories.
+    by_dir: bool,
+    human: bool,
+    quiet: bool,
+    /// Print the summary and the file count, but no listing.
+    summary_only: bool,
+    /// Replaces the per-file lines of text output.
+    template: Option<Template>,
+    /// Replaces the summary of text output.
+    summary_template: Option<Template>,
+    csv_total: bool,
+    progress: bool,
+    /// Write the report here instead of stdout.
+    output: Option<PathBuf>,
+    /// Report the changes against these counts instead of the counts.
+    baseline: Option<Baseline>,
+    /// Print paths relative to their target.
+    relative: bool,
 }
 
-fn parse_buffer_size(s: &str) -> usize {
-    match s.parse::<usize>() {
-        Ok(kb) => kb * 1024,
-        Err(_) => {
-            eprintln!("Invalid buffer size. Using 8 KB.");
-            8 * 1024
+impl Report {
+    /// Prints one per-file line of text or ndjson output.
+    fn print_file(&self, out: &mut dyn Write, stats: &FileStats) -> io::Result<()> {
+        match self.format {
+            Format::Ndjson => output::print_ndjson_file(out, stats),
+            _ => match &self.template {
+                Some(template) => template.write_file(out, stats, self.human),
+                None => output::print_text_file(out, stats, self.human),
+            },
         }
     }
-}
 
-fn is_valid_ext(path: &Path) -> bool {
-    match path.extension().and_then(|e| e.to_str()) {
-        Some(ext) => {
-            let ext_lower = ext.to_ascii_lowercase();
-            FILE_EXT_LIST.iter().any(|allowed| allowed == &ext_lower)
+    /// Whether the file count and averages are needed.
+    fn wants_summary(&self) -> bool {
+        self.per_file
+            || self.summary_only
+            || self
+                .summary_template
+                .as_ref()
+                .is_some_and(|t| t.uses(Field::Files))
+    }
+
+    fn breakdowns<'a>(&self, results: &'a Results) -> Breakdowns<'a> {
+        Breakdowns {
+            targets: self.subtotals.then_some(&results.targets[..]),
+            by_ext: self.by_ext.then_some(&results.by_ext[..]),
+            by_dir: self.by_dir.then_some(&results.by_dir[..]),
         }
-        None => false,
     }
-}
 
-fn process_file(path: &Path, buffer_size: usize, skip_empty: bool) -> i32 {
-    if !is_valid_ext(path) {
-        return 0;
+    /// Sorting, JSON and CSV need every file before the first one can be
+    /// printed.
+    fn collect_files(&self) -> bool {
+        let streams = matches!(self.format, Format::Text | Format::Ndjson);
+        self.baseline.is_some() || (self.per_file && (!streams || self.sort.is_some()))
     }
+}
 
-    let mut file = match File::open(path) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("Cannot open {}: {}", path.display(), e);
-            return 0;
+/// What a run ran into, for the exit status.
+struct Outcome {
+    errors: u64,
+    missing_target: bool,
+}
+
+/// Counts every target and prints the report.
+fn run(targets: &[(String, bool)], options: &CountOptions, report: &Report) -> Outcome {
+    let start = std::time::Instant::now();
+    let mut options = dedup_targets(targets, options);
+    // Each run, such as each one of --watch, gets the whole cap.
+    options.total_cap = options
+        .total_cap
+        .as_ref()
+        .map(|cap| Arc::new(LineCap::new(cap.limit())));
+    let options = &options;
+
+    let Some(mut out) = open_output(report) else {
+        return Outcome {
+            errors: 1,
+            missing_target: false,
+        };
+    };
+    // The first write error stops all further output.
+    let mut written = Ok(());
+
+    let mut files: Vec<FileStats> = Vec::new();
+    let mut file_lines: Vec<u64> = Vec::new();
+    let mut flagged = Flagged::default();
+    let mut ext_totals: HashMap<String, u64> = HashMap::new();
+    let collect_files = report.collect_files();
+    let mut progress = report.progress.then(|| Progress::new(targets, options));
+    let mut on_file = |stats: &FileStats| {
+        if let Some(progress) = &mut progress {
+            progress.update(stats);
+        }
+        if report.by_ext {
+            *ext_totals.entry(extension_key(&stats.path)).or_default() += stats.lines;
+        }
+        // Binary files were not counted, so they stay out of the averages.
+        if report.wants_summary() && stats.skipped.binary == 0 {
+            file_lines.push(stats.lines);
+        }
+        if stats.endings.is_some_and(|e| e.is_mixed()) {
+            flagged.mixed_endings.push(stats.clone());
+        }
+        if stats.jsonl.as_ref().is_some_and(|r| r.invalid > 0) {
+            flagged.invalid_jsonl.push(stats.clone());
+        }
+        if collect_files {
+            files.push(stats.clone());
+        } else if report.per_file && written.is_ok() {
+            written = report.print_file(&mut out, stats);
         }
     };
 
-    if skip_empty {
-        count_nonempty_lines(&mut file, buffer_size)
+    let mut failures: Vec<Failure> = Vec::new();
+    let mut dir_totals: HashMap<PathBuf, (u64, HashMap<String, u64>)> = HashMap::new();
+    let mut target_totals: Vec<FileStats> = Vec::new();
+    let mut total = FileStats::new(Path::new(""), options);
+    let mut missing_target = false;
+    let labels = if report.relative {
+        relative_labels(targets)
     } else {
-        count_newlines_fast(&mut file, buffer_size)
+        Vec::new()
+    };
+    for (i, (target, walk)) in targets.iter().enumerate() {
+        if capped(options).is_some() {
+            break;
+        }
+        let relative = labels.get(i).and_then(|label| Relative::new(target, label));
+        let mut on_target_file = |stats: &FileStats| {
+            if report.by_dir {
+                let dir = top_dir(Path::new(target), &stats.path);
+                let dir = match &relative {
+                    Some(relative) => relative.path(&dir),
+                    None => dir,
+                };
+                let (lines, by_ext) = dir_totals.entry(dir).or_default();
+                *lines += stats.lines;
+                if report.by_ext {
+                    *by_ext.entry(extension_key(&stats.path)).or_default() += stats.lines;
+                }
+            }
+            match &relative {
+                Some(relative) => {
+                    let mut stats = stats.clone();
+                    stats.path = relative.path(&stats.path);
+                    on_file(&stats);
+                }
+                None => on_file(stats),
+            }
+        };
+        let mut on_target_error = |e: &LineCountError| {
+            let path = match &relative {
+                Some(relative) => relative.path(e.path()),
+                None => e.path().to_path_buf(),
+            };
+            failures.push(Failure {
+                path,
+                reason: e.reason(),
+            });
+        };
+        let result = count_target(
+            target,
+            *walk,
+            options,
+            &mut on_target_file,
+            &mut on_target_error,
+        );
+        let mut stats = match result {
+            Ok(stats) => stats,
+            // Files outside the filters contribute nothing.
+            Err(LineCountError::Unsupported { path }) => {
+                let mut stats = FileStats::new(&path, options);
+                stats.skipped.filtered = 1;
+                stats
+            }
+            Err(e @ LineCountError::NotFound { .. }) => {
+                eprintln!("{}", e);
+                on_target_error(&e);
+                missing_target = true;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                on_target_error(&e);
+                let mut stats = FileStats::new(e.path(), options);
+                stats.errors = 1;
+                stats.permission_denied = e.is_permission_denied() as u64;
+                stats.timed_out = e.is_timeout() as u64;
+                stats
+            }
+        };
+        if let Some(relative) = &relative {
+            relative.contents(&mut stats);
+        }
+        total.add(&stats);
+        target_totals.push(stats);
+    }
+
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    let ext_totals = sort_ext_totals(ext_totals);
+    let mut dir_totals: Vec<DirTotal> = dir_totals
+        .into_iter()
+        .map(|(path, (lines, by_ext))| DirTotal {
+            path,
+            lines,
+            by_ext: report.by_ext.then(|| sort_ext_totals(by_ext)),
+        })
+        .collect();
+    dir_totals.sort_by(|a, b| b.lines.cmp(&a.lines).then_with(|| a.path.cmp(&b.path)));
+    let changes = report
+        .baseline
+        .as_ref()
+        .map(|baseline| baseline::diff(baseline, &files));
+    if let Some(key) = report.sort {
+        sort_files(&mut files, key);
+    }
+    if let Some(n) = report.top {
+        files.truncate(n);
+    }
+
+    failures.sort_by(|a, b| a.path.cmp(&b.path));
+    flagged.mixed_endings.sort_by(|a, b| a.path.cmp(&b.path));
+    flagged.invalid_jsonl.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let results = Results {
+        total,
+        elapsed: start.elapsed(),
+        files,
+        summary: FileSummary::new(file_lines),
+        targets: target_totals,
+        by_ext: ext_totals,
+        by_dir: dir_totals,
+        flagged,
+        failures,
+        capped: capped(options),
+        changes,
+    };
+    let written = written
+        .and_then(|_| print_report(&mut out, report, &results))
+        .and_then(|_| out.flush());
+    Outcome {
+        errors: results.total.errors + failed_write(written),
+        missing_target,
     }
 }
 
-fn count_newlines_fast(file: &mut File, buffer_size: usize) -> i32 {
-    let mut buffer = vec![0u8; buffer_size];
-    let mut total = 0;
+/// The limit of the total cap in `options`, if it has been reached.
+fn capped(options: &CountOptions) -> Option<u64> {
+    let cap = options.total_cap.as_ref()?;
+    cap.is_reached().then(|| cap.limit())
+}
 
-    loop {
-        let n = match file.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(n) => n,
+/// With several targets, a file that two of them cover, such as `src` and
+/// `src/main.rs`, is only counted for the first.
+fn dedup_targets(targets: &[(String, bool)], options: &CountOptions) -> CountOptions {
+    let mut options = options.clone();
+    if targets.len() > 1 {
+        options.counted = Some(Arc::new(CountedFiles::new()));
+    }
+    options
+}
+
+/// Opens `--output`, or stdout without it. Failing to open the file is
+/// reported here.
+fn open_output(report: &Report) -> Option<Box<dyn Write>> {
+    match &report.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(Box::new(BufWriter::new(file))),
             Err(e) => {
-                eprintln!("Read error: {}", e);
-                break;
+                eprintln!("Cannot write {}: {}", path.display(), e);
+                None
             }
-        };
-
-        total += bytecount::count(&buffer[..n], b'\n') as i32;
+        },
+        None => Some(Box::new(io::stdout().lock())),
     }
+}
 
-    total
+/// Reports a failed write of the output, returning 1 if it counts as an
+/// error.
+fn failed_write(written: io::Result<()>) -> u64 {
+    match written {
+        Ok(()) => 0,
+        // A closed pipe just means the reader has seen enough.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => 1,
+        Err(e) => {
+            eprintln!("Cannot write output: {}", e);
+            1
+        }
+    }
 }
 
-fn count_nonempty_lines(file: &mut File, buffer_size: usize) -> i32 {
-    let mut buffer = vec![0u8; buffer_size];
-    let mut total = 0;
-    let mut has_data = false;
+/// Counts both targets of `--compare` and prints them side by side.
+fn run_compare(targets: &[(String, bool)], options: &CountOptions, report: &Report) -> Outcome {
+    let start = std::time::Instant::now();
+    let Some(mut out) = open_output(report) else {
+        return Outcome {
+            errors: 1,
+            missing_target: false,
+        };
+    };
 
-    loop {
-        let n = match file.read(&mut buffer) {
-            Ok(0) => {
-                if has_data {
-                    total += 1;
-                }
-                break;
+    let mut errors = 0;
+    let mut missing_target = false;
+    let mut sides = Vec::new();
+    for (target, walk) in targets {
+        let mut by_ext: HashMap<String, u64> = HashMap::new();
+        let mut on_file = |stats: &FileStats| {
+            *by_ext.entry(extension_key(&stats.path)).or_default() += stats.lines;
+        };
+        let lines = match count_target(target, *walk, options, &mut on_file, &mut |_| {}) {
+            Ok(stats) => {
+                errors += stats.errors;
+                stats.lines
             }
-            Ok(n) => n,
+            Err(LineCountError::Unsupported { .. }) => 0,
             Err(e) => {
-                eprintln!("Read error: {}", e);
-                break;
+                eprintln!("{}", e);
+                match e {
+                    LineCountError::NotFound { .. } => missing_target = true,
+                    _ => errors += 1,
+                }
+                0
             }
         };
+        sides.push(output::Side {
+            path: target.clone(),
+            lines,
+            by_ext,
+        });
+    }
 
-        for &b in &buffer[..n] {
-            match b {
-                b'\n' => {
-                    if has_data {
-                        total += 1;
-                    }
-                    has_data = false;
+    let comparison = Comparison::new(sides.remove(0), sides.remove(0));
+    let elapsed = start.elapsed();
+    let written = match report.format {
+        Format::Text => output::print_text_comparison(&mut out, &comparison, report.human)
+            .and_then(|_| output::print_text_time(&mut out, elapsed, report.human)),
+        Format::Json => writeln!(out, "{}", output::json_comparison(&comparison, elapsed)),
+        Format::Ndjson => output::print_ndjson_comparison(&mut out, &comparison, elapsed),
+        Format::Csv => output::print_csv_comparison(&mut out, &comparison),
+    }
+    .and_then(|_| out.flush());
+    Outcome {
+        errors: errors + failed_write(written),
+        missing_target,
+    }
+}
+
+/// Everything gathered by [`run`] for the final report.
+struct Results {
+    total: FileStats,
+    elapsed: Duration,
+    /// Sorted and truncated per `--sort` and `--top`; only filled when the
+    /// report needs all files at once.
+    files: Vec<FileStats>,
+    summary: FileSummary,
+    targets: Vec<FileStats>,
+    by_ext: Vec<(String, u64)>,
+    by_dir: Vec<DirTotal>,
+    flagged: Flagged,
+    /// The files and directories that couldn't be counted, by path.
+    failures: Vec<Failure>,
+    /// The `--total-cap`, if counting stopped at it.
+    capped: Option<u64>,
+    /// Set with `--baseline`.
+    changes: Option<Vec<Change>>,
+}
+
+fn print_report(out: &mut dyn Write, report: &Report, results: &Results) -> io::Result<()> {
+    let total = &results.total;
+    if !report.quiet
+        && let (Some(baseline), Some(changes)) = (&report.baseline, &results.changes)
+    {
+        return print_diff(out, report, baseline, changes, results);
+    }
+    match report.format {
+        _ if report.quiet => writeln!(out, "{}", total.lines),
+        Format::Text => {
+            if report.collect_files() {
+                for f in &results.files {
+                    report.print_file(out, f)?;
+                }
+            }
+            if report.per_file {
+                output::print_text_failures(out, &results.failures)?;
+            }
+            // A summary template stands in for both summary lines.
+            if (report.per_file || report.summary_only) && report.summary_template.is_none() {
+                output::print_text_file_summary(out, &results.summary, report.human)?;
+            }
+            if report.subtotals {
+                output::print_text_subtotals(out, &results.targets, report.human)?;
+            }
+            if report.by_ext {
+                output::print_text_by_ext(out, &results.by_ext, total.lines, report.human)?;
+            }
+            if report.by_dir {
+                output::print_text_by_dir(out, &results.by_dir, total.lines, report.human)?;
+            }
+            match &report.summary_template {
+                Some(template) => template.write_summary(
+                    out,
+                    total,
+                    results.summary.files,
+                    results.elapsed,
+                    report.human,
+                )?,
+                None => output::print_text_summary(out, total, results.elapsed, report.human)?,
+            }
+            if total.endings.is_some() {
+                output::print_text_mixed_endings(out, &results.flagged.mixed_endings)?;
+            }
+            if let Some(records) = &total.jsonl {
+                output::print_text_jsonl(
+                    out,
+                    records,
+                    &results.flagged.invalid_jsonl,
+                    report.human,
+                )?;
+            }
+            if let Some(dirs) = &total.empty_dirs {
+                output::print_text_empty_dirs(out, dirs)?;
+            }
+            if let Some(cap) = results.capped {
+                output::print_text_capped(out, cap, report.human)?;
+            }
+            Ok(())
+        }
+        Format::Json => {
+            let json = output::json_report(
+                total,
+                results.elapsed,
+                report.per_file.then_some(&results.files[..]),
+                (report.per_file || report.summary_only).then_some(&results.summary),
+                &report.breakdowns(results),
+                &results.flagged,
+                results.capped,
+            );
+            writeln!(out, "{}", json)
+        }
+        Format::Ndjson => {
+            if report.collect_files() {
+                for f in &results.files {
+                    report.print_file(out, f)?;
                 }
-                b'\r' | b' ' | b'\t' => {}
-                _ => has_data = true,
             }
+            let json = output::json_report(
+                total,
+                results.elapsed,
+                None,
+                Some(&results.summary),
+                &report.breakdowns(results),
+                &results.flagged,
+                results.capped,
+            );
+            output::print_ndjson_summary(out, &json)
+        }
+        Format::Csv => output::print_csv(out, &results.files, total, report.csv_total),
+    }
+}
+
+fn print_diff(
+    out: &mut dyn Write,
+    report: &Report,
+    baseline: &Baseline,
+    changes: &[Change],
+    results: &Results,
+) -> io::Result<()> {
+    let totals = DiffTotals::new(baseline, &results.total, changes);
+    match report.format {
+        Format::Text => {
+            output::print_text_diff(out, changes, &totals, report.human)?;
+            output::print_text_time(out, results.elapsed, report.human)
         }
+        Format::Json => writeln!(
+            out,
+            "{}",
+            output::json_diff(changes, &totals, results.elapsed)
+        ),
+        Format::Ndjson => output::print_ndjson_diff(out, changes, &totals, results.elapsed),
+        Format::Csv => output::print_csv_diff(out, changes),
     }
+}
 
-    total
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    /// Most lines first, ties broken by path.
+    Lines,
+    Path,
 }
 
-fn process_directory(
-    path: &Path,
-    buffer: usize,
-    skip_empty: bool,
-    recursive: bool,
-) -> i32 {
-    let mut total = 0;
+fn sort_files(files: &mut [FileStats], key: SortKey) {
+    match key {
+        SortKey::Lines => {
+            files.sort_by(|a, b| b.lines.cmp(&a.lines).then_with(|| a.path.cmp(&b.path)))
+        }
+        SortKey::Path => files.sort_by(|a, b| a.path.cmp(&b.path)),
+    }
+}
 
-    let walker: Box<dyn Iterator<Item = PathBuf>> = if recursive {
-        Box::new(walk_recursive(path))
-    } else {
-        Box::new(walk_shallow(path))
-    };
+/// The first-level directory of `root` that `file` is in, or `root` itself
+/// for the files directly inside it and for a target that is a file.
+fn top_dir(root: &Path, file: &Path) -> PathBuf {
+    let rest = file.strip_prefix(root).unwrap_or(Path::new(""));
+    let mut components = rest.components();
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => root.join(first),
+        _ => root.to_path_buf(),
+    }
+}
 
-    for p in walker {
-        if p.is_file() {
-            total += process_file(&p, buffer, skip_empty);
+/// Lowercase extension of `path`, or an empty string if it has none.
+fn extension_key(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Sorts by descending line count, breaking ties alphabetically.
+fn sort_ext_totals(totals: HashMap<String, u64>) -> Vec<(String, u64)> {
+    let mut totals: Vec<(String, u64)> = totals.into_iter().collect();
+    totals.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    totals
+}
+
+/// Rewrites the paths found under one target for `--relative`.
+struct Relative<'a> {
+    target: &'a Path,
+    /// What's kept of `target`, see [`relative_labels`].
+    label: &'a Path,
+}
+
+impl<'a> Relative<'a> {
+    /// Stdin has no path to shorten, so it gets `None`.
+    fn new(target: &'a str, label: &'a Path) -> Option<Self> {
+        (target != "-").then(|| Relative {
+            target: Path::new(target),
+            label,
+        })
+    }
+
+    fn path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(self.target) {
+            Ok(rest) if rest.as_os_str().is_empty() && self.label.as_os_str().is_empty() => {
+                PathBuf::from(".")
+            }
+            Ok(rest) if rest.as_os_str().is_empty() => self.label.to_path_buf(),
+            Ok(rest) => self.label.join(rest),
+            Err(_) => path.to_path_buf(),
         }
     }
 
-    total
+    /// Rewrites the paths inside a target's totals, leaving the target's
+    /// own path alone for `--subtotals`.
+    fn contents(&self, stats: &mut FileStats) {
+        if let Some(longest) = &mut stats.longest {
+            longest.path = self.path(&longest.path);
+        }
+        if let Some(dirs) = &mut stats.empty_dirs {
+            for dir in dirs.iter_mut() {
+                *dir = self.path(dir);
+            }
+        }
+    }
+}
+
+/// The trailing components of each target that `--relative` keeps in front
+/// of the paths below it: none for a single directory, otherwise as few as
+/// tell the targets apart. A file target always keeps its name.
+fn relative_labels(targets: &[(String, bool)]) -> Vec<PathBuf> {
+    let parts: Vec<Vec<Component>> = targets
+        .iter()
+        .map(|(t, _)| {
+            Path::new(t)
+                .components()
+                .filter(|c| *c != Component::CurDir)
+                .collect()
+        })
+        .collect();
+    let suffix = |parts: &[Component], n: usize| -> PathBuf {
+        parts[parts.len().saturating_sub(n)..].iter().collect()
+    };
+    let longest = parts.iter().map(Vec::len).max().unwrap_or(0);
+    let n = (0..=longest)
+        .find(|&n| {
+            let labels: HashSet<PathBuf> = parts.iter().map(|p| suffix(p, n)).collect();
+            labels.len() == parts.len()
+        })
+        .unwrap_or(longest);
+    targets
+        .iter()
+        .zip(&parts)
+        .map(|((t, _), parts)| {
+            let n = if Path::new(t).is_dir() { n } else { n.max(1) };
+            suffix(parts, n)
+        })
+        .collect()
+}
+
+/// Counts one command-line target, walking it if it's a directory and `walk`
+/// is set. A file named directly (`walk` set) is counted whatever its
+/// extension, like `wc` would. Errors inside a directory are reported on
+/// stderr as they happen, and passed to `on_error`; the returned error is
+/// about `target` itself.
+fn count_target(
+    target: &str,
+    walk: bool,
+    options: &CountOptions,
+    on_file: &mut dyn FnMut(&FileStats),
+    on_error: &mut dyn FnMut(&LineCountError),
+) -> Result<FileStats, LineCountError> {
+    let path = Path::new(target);
+
+    let stats = if target == "-" {
+        count_reader(&mut io::stdin().lock(), options)?
+    } else if walk && path.is_dir() {
+        return count_directory(path, options, on_file, |e| {
+            eprintln!("{}", e);
+            on_error(e);
+        });
+    } else if options.archives && is_archive(path) {
+        return count_archive(path, options, on_file);
+    } else if walk {
+        count_file(path, &named_file(options))?
+    } else {
+        count_file(path, options)?
+    };
+    // A file another target already covered isn't listed twice.
+    if stats.skipped.duplicates == 0 {
+        on_file(&stats);
+    }
+    Ok(stats)
 }
 
-fn walk_shallow(path: &Path) -> impl Iterator<Item = PathBuf> {
-    read_dir(path)
-        .unwrap()
-        .filter_map(|e| e.ok().map(|d| d.path()))
+/// Options for a file named on the command line, which skips the extension
+/// and name filters but not the size and time ones.
+fn named_file(options: &CountOptions) -> CountOptions {
+    CountOptions {
+        count_all: true,
+        ..options.clone()
+    }
 }
 
-fn walk_recursive(root: &Path) -> impl Iterator<Item = PathBuf> {
-    let mut stack = vec![root.to_path_buf()];
+/// Prints the files `count_target` would count, one per line, and returns
+/// how many directories inside `target` couldn't be listed.
+fn list_target(target: &str, walk: bool, options: &CountOptions) -> Result<u64, LineCountError> {
+    let path = Path::new(target);
 
-    std::iter::from_fn(move || {
-        while let Some(path) = stack.pop() {
-            if path.is_dir() {
-                if let Ok(entries) = read_dir(&path) {
-                    for entry in entries.flatten() {
-                        stack.push(entry.path());
+    if target == "-" {
+        println!("-");
+        return Ok(0);
+    }
+    if walk && path.is_dir() {
+        let mut errors = 0;
+        let mut out = io::stdout().lock();
+        for item in select_files(path, options) {
+            match item {
+                Ok(p) => {
+                    // A closed pipe ends the listing.
+                    if output::write_path_line(&mut out, "", &p, "").is_err() {
+                        break;
                     }
                 }
-                continue;
+                Err(e) => {
+                    eprintln!("{}", e);
+                    errors += 1;
+                }
             }
-            return Some(path);
         }
-        None
+        return Ok(errors);
+    }
+
+    let metadata = path.metadata().map_err(|e| LineCountError::io(path, e))?;
+    if (walk || options.should_count(path))
+        && options.size_in_range(metadata.len())
+        && options.modified_in_window(path, &metadata)
+        && options.counted.as_ref().is_none_or(|c| c.insert(path))
+    {
+        let _ = output::write_path_line(&mut io::stdout().lock(), "", path, "");
+    }
+    Ok(0)
+}
+
+/// Reads newline-separated paths from `source`, or from stdin if it's `-`.
+/// Blank lines are ignored.
+fn read_file_list(source: &str) -> io::Result<Vec<String>> {
+    let content = if source == "-" {
+        let mut content = String::new();
+        io::stdin().lock().read_to_string(&mut content)?;
+        content
+    } else {
+        fs::read_to_string(source)?
+    };
+
+    Ok(content
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Prints the usage text. A closed stdout ends it quietly, as it does the
+/// report.
+fn show_help() {
+    failed_write(io::stdout().lock().write_all(HELP.as_bytes()));
+}
+
+/// The usage text of `--help`.
+const HELP: &str = "\
+Usage: linecount <path>... [OPTIONS]
+
+Use - as the path to read from standard input.
+
+Options:
+  --buffer-size=<SIZE> Set buffer size, 1B to 1G (default: 8K; bare numbers are KB)
+  --skip-empty         Skip empty lines
+  --empty-definition=<DEF>
+                       What --skip-empty skips: whitespace (default) or strict
+  --recursive          Process directories recursively
+  --max-depth=<N>      Recurse at most N levels (0 = direct files only)
+  --recursive-paths=<LIST>
+                       Without --recursive, still recurse into these subdirectories
+  --hidden             Include hidden files and directories
+  --count-vcs          Also walk .git, .hg, .svn and .bzr directories
+  --sort-walk=off      Walk directories in file system order, not by name
+  --walk-order=<ORDER> Walk subdirectories depth-first (dfs, default) or bfs
+  --exclude=<GLOB>     Skip matching files and directories (repeatable)
+  --follow-symlinks    Count symlinked files and descend into symlinked directories
+  --gitignore          Skip paths matched by .gitignore files
+  --no-ignore-files    Don't read .linecountignore or the global ignore file
+  --jobs=<N>           Count N files in parallel (default: number of CPUs)
+  --io-threads=<N>     Read files on N threads, separate from counting
+  --cpu-threads=<N>    Count files on N threads, separate from reading
+  --max-open-files=<N> Keep at most N files open at once
+  --ext=<LIST>         Only count these comma-separated extensions
+  --add-ext=<LIST>     Count these extensions in addition to the defaults
+  --include-ext=<LIST> Same as --add-ext
+  --exclude-ext=<LIST> Don't count these extensions
+  --text-ext=<LIST>    Always count these extensions as text
+  --binary-ext=<LIST>  Never count these extensions
+  --case-sensitive-ext Only count extensions matching in case
+  --name=<LIST>        Also count files with these exact names
+  --min-size=<SIZE>    Skip files smaller than SIZE bytes (K, M, G suffixes)
+  --max-size=<SIZE>    Skip files larger than SIZE bytes
+  --since=<AGE>        Only count files modified within AGE (e.g. 24h, 7d)
+  --file-timeout=<MS>  Give up on a file that takes longer than MS milliseconds
+  --dedup-inodes       Count hardlinked files only once (Unix)
+  --count-all          Count every file regardless of extension
+  --archives           Count the files inside tar, tar.gz and zip archives
+  --skip-binary        Don't count files that look binary
+  --mmap               Read files through a memory map
+  --line-ending=<END>  Line terminator: lf (default), crlf or auto
+  --delimiter=<CHAR>   Count records ended by CHAR instead of lines (e.g. \\0)
+  --break-on=<LIST>    Also end lines at form feeds (ff) and vertical tabs (vt)
+  --words              Also count whitespace-delimited words
+  --encoding=<ENC>     Input encoding: utf8 (default), utf16le, utf16be or latin1
+  --chars              Also count characters
+  --tokens             Also estimate language model tokens (a heuristic)
+  --bytes              Also count the bytes read
+  --dedup-lines        Also count distinct lines; needs about 16 bytes of memory
+                       per distinct line across all files
+  --longest            Report the longest line and where it is
+  --jsonl-validate     Check that each line of .jsonl and .ndjson files is JSON
+  --sql-statements     Count statements instead of lines in .sql files
+  --max-lines=<N>      Stop reading a file after N line breaks; the rest of it
+                       isn't counted and the file is marked truncated
+  --total-cap=<N>      Stop the whole run once about N lines have been counted
+  --match=<REGEX>      Only count lines matching REGEX; slower, as every line
+                       has to be looked at
+  --no-match=<REGEX>   Only count lines not matching REGEX
+  --show-empty-dirs    List the directories in which no lines were counted
+  --check-endings      Report files that mix LF and CRLF line endings
+  --classify           Also count blank, comment and code lines
+  --config=<FILE>      Read comment syntax for --classify from a TOML file
+  --verbose, -v        Explain on stderr why paths are skipped; -vv also shows
+                       how many bytes and reads each file took
+  --progress           Show files counted so far and the time left on stderr
+  --watch              Count again whenever a file under the targets changes
+  --bench              Time counting the targets at several buffer sizes
+  --list               Print the files that would be counted, without counting
+  --per-file           Print the line count of each file
+  --sort=<KEY>         Sort --per-file output by lines (descending) or path
+  --top=<N>            Only show the N largest files with --per-file
+  --subtotals          Print the line count of each path argument
+  --by-ext             Print a breakdown of lines per extension
+  --by-dir             Print a breakdown of lines per first-level directory
+  --summary-only       Print only the summary, overriding listings
+  --quiet, -q          Print only the total line count
+  --relative           Print paths relative to the target they were found under
+  --human              Use thousands separators and short durations in text output
+  --files-from=<FILE>  Also count the files listed in FILE (- for stdin)
+  --format=<FORMAT>    Output format: text (default), json, ndjson or csv
+  --template=<TPL>     Print each file as TPL, e.g. '{path}: {lines} lines'
+  --summary-template=<TPL>
+                       Print the summary as TPL, e.g. '{files} files, {lines} lines'
+  --cache=<FILE>       Reuse the line counts of files unchanged since the last run
+  --git-changed[=REF]  Count only the files changed since REF (default HEAD)
+  --compare            Count two paths and compare them by extension
+  --baseline=<FILE>    Print the changes since a --format=json --per-file report
+  --output=<FILE>      Write the report to FILE; the format follows its extension
+  --csv-total          End CSV output with a TOTAL row
+  --help, -h           Show help
+  --version, -V        Show the version
+";
+
+/// Largest accepted `--buffer-size`, in bytes (1 GB).
+const MAX_BUFFER: u64 = 1024 * 1024 * 1024;
+
+/// A bare number is in KB, as it was before suffixes were accepted.
+fn parse_buffer_size(s: &str) -> usize {
+    match size_in_bytes(s, 'K') {
+        Some(n) if (1..=MAX_BUFFER).contains(&n) => n as usize,
+        _ => {
+            eprintln!(
+                "Invalid buffer size '{}' (must be 1B to 1G). Using 8 KB.",
+                s
+            );
+            8 * 1024
+        }
+    }
+}
+
+/// Parses a byte count with an optional `B`, `K`, `M` or `G` suffix
+/// (powers of 1024).
+fn parse_size(s: &str) -> Option<u64> {
+    let size = size_in_bytes(s, 'B');
+    if size.is_none() {
+        eprintln!("Invalid size '{}'. Ignoring the limit.", s);
+    }
+    size
+}
+
+/// Parses a number with an optional unit suffix, using `default_unit`
+/// without one.
+fn size_in_bytes(s: &str, default_unit: char) -> Option<u64> {
+    let (digits, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c.to_ascii_uppercase()),
+        _ => (s, default_unit),
+    };
+    let multiplier = match unit {
+        'B' => 1,
+        'K' => 1024,
+        'M' => 1024 * 1024,
+        'G' => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .map(|n| n.saturating_mul(multiplier))
+}
+
+/// Parses an age such as `90s`, `30m`, `24h`, `7d` or `2w`.
+fn parse_since(s: &str) -> Option<Duration> {
+    let (digits, unit) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+        Some((i, 'w')) => (&s[..i], 7 * 24 * 60 * 60),
+        _ => (s, 0),
+    };
+    match digits.parse::<u64>() {
+        Ok(n) if unit > 0 => Some(Duration::from_secs(n.saturating_mul(unit))),
+        _ => {
+            eprintln!("Invalid age '{}'. Counting files of any age.", s);
+            None
+        }
+    }
+}
+
+fn parse_max_depth(s: &str) -> Option<usize> {
+    match s.parse::<usize>() {
+        Ok(depth) => Some(depth),
+        Err(_) => {
+            eprintln!("Invalid max depth. Recursing without a limit.");
+            None
+        }
+    }
+}
+
+fn parse_file_timeout(s: &str) -> Option<Duration> {
+    match s.parse::<u64>() {
+        Ok(ms) if ms > 0 => Some(Duration::from_millis(ms)),
+        _ => {
+            eprintln!("Invalid file timeout. Waiting for every file.");
+            None
+        }
+    }
+}
+
+fn parse_max_open_files(s: &str) -> Option<usize> {
+    match s.parse::<usize>() {
+        Ok(n) if n > 0 => Some(n),
+        _ => {
+            eprintln!("Invalid open file limit. Using the default.");
+            None
+        }
+    }
+}
+
+fn parse_total_cap(s: &str) -> Option<u64> {
+    match s.parse::<u64>() {
+        Ok(n) if n > 0 => Some(n),
+        _ => {
+            eprintln!("Invalid total cap. Counting without a cap.");
+            None
+        }
+    }
+}
+
+fn parse_max_lines(s: &str) -> Option<u64> {
+    match s.parse::<u64>() {
+        Ok(n) => Some(n),
+        Err(_) => {
+            eprintln!("Invalid max lines. Counting whole files.");
+            None
+        }
+    }
+}
+
+/// Compiles a --match pattern. A bad pattern is fatal, since counting every
+/// line instead would look like a valid answer.
+fn parse_pattern(s: &str) -> Regex {
+    match Regex::new(s) {
+        Ok(re) => re,
+        Err(e) => {
+            eprintln!("Invalid pattern '{}': {}", s, e);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+fn parse_sort(s: &str) -> Option<SortKey> {
+    match s {
+        "lines" => Some(SortKey::Lines),
+        "path" => Some(SortKey::Path),
+        _ => {
+            eprintln!("Unknown sort key '{}'. Not sorting.", s);
+            None
+        }
+    }
+}
+
+/// Parses a `--template` or `--summary-template`, exiting on a mistake
+/// rather than printing it wrong for every file.
+fn parse_template(s: &str, context: Context) -> Template {
+    Template::parse(s, context).unwrap_or_else(|e| {
+        eprintln!("Invalid template '{}': {}", s, e);
+        process::exit(EXIT_ERROR);
     })
-}
\ No newline at end of file
+}
+
+fn parse_sort_walk(s: &str) -> bool {
+    match s {
+        "on" => true,
+        "off" => false,
+        _ => {
+            eprintln!("Unknown --sort-walk value '{}'. Use on or off.", s);
+            true
+        }
+    }
+}
+
+fn parse_walk_order(s: &str) -> WalkOrder {
+    match s {
+        "dfs" => WalkOrder::DepthFirst,
+        "bfs" => WalkOrder::BreadthFirst,
+        _ => {
+            eprintln!("Unknown walk order '{}'. Using dfs.", s);
+            WalkOrder::DepthFirst
+        }
+    }
+}
+
+fn parse_top(s: &str) -> Option<usize> {
+    match s.parse::<usize>() {
+        Ok(n) => Some(n),
+        Err(_) => {
+            eprintln!("Invalid top count. Showing all files.");
+            None
+        }
+    }
+}
+
+fn parse_line_ending(s: &str) -> LineEnding {
+    match s {
+        "lf" => LineEnding::Lf,
+        "crlf" => LineEnding::Crlf,
+        "auto" => LineEnding::Auto,
+        _ => {
+            eprintln!("Unknown line ending '{}'. Using lf.", s);
+            LineEnding::Lf
+        }
+    }
+}
+
+fn parse_empty_definition(s: &str) -> EmptyLines {
+    match s {
+        "whitespace" => EmptyLines::Whitespace,
+        "strict" => EmptyLines::Strict,
+        _ => {
+            eprintln!("Unknown empty line definition '{}'. Using whitespace.", s);
+            EmptyLines::Whitespace
+        }
+    }
+}
+
+fn parse_encoding(s: &str) -> Encoding {
+    match s.to_ascii_lowercase().replace('-', "").as_str() {
+        "utf8" => Encoding::Utf8,
+        "utf16le" => Encoding::Utf16Le,
+        "utf16be" => Encoding::Utf16Be,
+        "latin1" => Encoding::Latin1,
+        _ => {
+            eprintln!("Unknown encoding '{}'. Using utf8.", s);
+            Encoding::Utf8
+        }
+    }
+}
+
+/// Parses a single ASCII character or one of the escapes `\0`, `\t`, `\n`,
+/// `\r` and `\\`.
+fn parse_delimiter(s: &str) -> Option<u8> {
+    let byte = match s {
+        "\\0" => Some(0),
+        "\\t" => Some(b'\t'),
+        "\\n" => Some(b'\n'),
+        "\\r" => Some(b'\r'),
+        "\\\\" => Some(b'\\'),
+        _ if s.len() == 1 && s.is_ascii() => Some(s.as_bytes()[0]),
+        _ => None,
+    };
+    if byte.is_none() {
+        eprintln!("Invalid delimiter '{}'. Using the line ending.", s);
+    }
+    byte
+}
+
+/// Parses a comma-separated list of `ff` (form feed) and `vt` (vertical
+/// tab), skipping anything else.
+fn parse_breaks(s: &str) -> Vec<u8> {
+    s.split(',')
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| match name {
+            "ff" => Some(0x0c),
+            "vt" => Some(0x0b),
+            _ => {
+                eprintln!("Unknown line break '{}'. Use ff or vt.", name);
+                None
+            }
+        })
+        .collect()
+}
+
+fn parse_jobs(s: &str) -> usize {
+    match s.parse::<usize>() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            let jobs = default_jobs();
+            eprintln!("Invalid job count. Using {}.", jobs);
+            jobs
+        }
+    }
+}
+
+/// Parses `--io-threads` and `--cpu-threads`; an invalid count falls back to
+/// `--jobs`.
+fn parse_threads(s: &str) -> Option<usize> {
+    match s.parse::<usize>() {
+        Ok(n) if n > 0 => Some(n),
+        _ => {
+            eprintln!("Invalid thread count. Using the --jobs count.");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_sizes_out_of_range_fall_back_to_8k() {
+        assert_eq!(parse_buffer_size("0"), 8 * 1024);
+        assert_eq!(parse_buffer_size("0B"), 8 * 1024);
+        assert_eq!(parse_buffer_size("2G"), 8 * 1024);
+        // Saturates instead of wrapping around to a small size.
+        assert_eq!(parse_buffer_size("17179869184G"), 8 * 1024);
+        assert_eq!(parse_buffer_size("99999999999999999999K"), 8 * 1024);
+        assert_eq!(parse_buffer_size("-1"), 8 * 1024);
+        assert_eq!(parse_buffer_size("1X"), 8 * 1024);
+    }
+
+    #[test]
+    fn buffer_sizes_take_a_unit() {
+        assert_eq!(parse_buffer_size("1B"), 1);
+        assert_eq!(parse_buffer_size("65536B"), 65536);
+        assert_eq!(parse_buffer_size("512"), 512 * 1024);
+        assert_eq!(parse_buffer_size("1m"), 1024 * 1024);
+        assert_eq!(parse_buffer_size("1G"), 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn sizes_take_every_suffix() {
+        let cases = [
+            ("0", Some(0)),
+            ("100", Some(100)),
+            ("100B", Some(100)),
+            ("100b", Some(100)),
+            ("10K", Some(10 * 1024)),
+            ("10k", Some(10 * 1024)),
+            ("5M", Some(5 * 1024 * 1024)),
+            ("2G", Some(2 * 1024 * 1024 * 1024)),
+            ("", None),
+            ("K", None),
+            ("1.5M", None),
+            ("10T", None),
+            ("-1", None),
+        ];
+        for (s, expected) in cases {
+            assert_eq!(parse_size(s), expected, "{}", s);
+        }
+        // The same suffixes, but a bare number is in KB.
+        for (s, kb) in [("1", 1), ("64", 64), ("64K", 64), ("1M", 1024)] {
+            assert_eq!(parse_buffer_size(s), kb * 1024, "{}", s);
+        }
+    }
+}
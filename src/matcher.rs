@@ -0,0 +1,59 @@
+use regex::bytes::Regex;
+
+use crate::CountOptions;
+use crate::count::{self, EmptyLines, LineBuffer};
+
+/// Counts the lines that match `CountOptions::matching` and don't match
+/// `CountOptions::not_matching`.
+///
+/// Each line has to be buffered and searched, which is much slower than
+/// counting terminators, so this only runs when a pattern is given.
+pub(crate) struct LineMatcher<'a> {
+    filter: Filter<'a>,
+    lines: LineBuffer,
+    count: u64,
+}
+
+struct Filter<'a> {
+    matching: Option<&'a Regex>,
+    not_matching: Option<&'a Regex>,
+    skip_empty: Option<EmptyLines>,
+}
+
+impl Filter<'_> {
+    fn accepts(&self, line: &[u8]) -> bool {
+        !self
+            .skip_empty
+            .is_some_and(|empty| count::is_blank(line, empty))
+            && self.matching.is_none_or(|re| re.is_match(line))
+            && !self.not_matching.is_some_and(|re| re.is_match(line))
+    }
+}
+
+impl<'a> LineMatcher<'a> {
+    pub(crate) fn new(options: &'a CountOptions) -> Self {
+        LineMatcher {
+            filter: Filter {
+                matching: options.matching.as_ref(),
+                not_matching: options.not_matching.as_ref(),
+                skip_empty: options.skip_empty.then_some(options.empty_lines),
+            },
+            lines: LineBuffer::new(options),
+            count: 0,
+        }
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        let (filter, count) = (&self.filter, &mut self.count);
+        self.lines
+            .feed(chunk, |line| *count += filter.accepts(line) as u64);
+    }
+
+    /// Returns the number of matching lines.
+    pub(crate) fn finish(self) -> u64 {
+        let (filter, mut count) = (self.filter, self.count);
+        self.lines
+            .finish(|line| count += filter.accepts(line) as u64);
+        count
+    }
+}
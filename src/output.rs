@@ -0,0 +1,1231 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use linecount::{Endings, FileStats, JsonlRecords, LineClasses, LongestLine};
+
+use crate::baseline::{Baseline, Change};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Csv,
+    /// One JSON object per line and file, then a summary object.
+    Ndjson,
+}
+
+pub fn parse_format(s: &str) -> Format {
+    match s {
+        "text" => Format::Text,
+        "json" => Format::Json,
+        "csv" => Format::Csv,
+        "ndjson" => Format::Ndjson,
+        _ => {
+            eprintln!("Unknown format '{}'. Using text.", s);
+            Format::Text
+        }
+    }
+}
+
+/// Picks the format matching the extension of an output file: `.json`,
+/// `.ndjson`, `.jsonl` or `.csv`, and text for anything else.
+pub fn format_for_path(path: &Path) -> Format {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(e) if e.eq_ignore_ascii_case("json") => Format::Json,
+        Some(e) if e.eq_ignore_ascii_case("ndjson") || e.eq_ignore_ascii_case("jsonl") => {
+            Format::Ndjson
+        }
+        Some(e) if e.eq_ignore_ascii_case("csv") => Format::Csv,
+        _ => Format::Text,
+    }
+}
+
+/// Prints `<lines>[\t<words>][\t<chars>][\t<tokens>][\t<bytes>][\t<unique>][\t<blank>\t<comment>\t<code>][\t<longest>][\t<endings>]\t<path>`.
+pub fn print_text_file(out: &mut dyn Write, stats: &FileStats, human: bool) -> io::Result<()> {
+    let mut line = count(stats.lines, human);
+    for n in [
+        stats.words,
+        stats.chars,
+        stats.tokens,
+        stats.bytes,
+        stats.unique_lines,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        line.push_str(&format!("\t{}", count(n, human)));
+    }
+    if let Some(c) = stats.classes {
+        for n in [c.blank, c.comment, c.code] {
+            line.push_str(&format!("\t{}", count(n, human)));
+        }
+    }
+    if let Some(l) = &stats.longest {
+        line.push_str(&format!("\t{}", count(l.length, human)));
+    }
+    if let Some(e) = &stats.endings {
+        line.push_str(&format!("\t{}", e.kind()));
+    }
+    let marker = if stats.truncated > 0 {
+        " (truncated)"
+    } else {
+        ""
+    };
+    write_path_line(out, &format!("{}\t", line), &stats.path, marker)
+}
+
+/// Line-count statistics over the files of a `--per-file` listing.
+pub struct FileSummary {
+    pub files: u64,
+    pub total: u64,
+    pub mean: f64,
+    pub median: f64,
+}
+
+impl FileSummary {
+    pub fn new(mut lines: Vec<u64>) -> Self {
+        lines.sort_unstable();
+        let files = lines.len() as u64;
+        let total: u64 = lines.iter().sum();
+        let mid = lines.len() / 2;
+        let (mean, median) = match lines.len() {
+            0 => (0.0, 0.0),
+            n if n % 2 == 0 => (
+                total as f64 / n as f64,
+                (lines[mid - 1] + lines[mid]) as f64 / 2.0,
+            ),
+            n => (total as f64 / n as f64, lines[mid] as f64),
+        };
+        FileSummary {
+            files,
+            total,
+            mean,
+            median,
+        }
+    }
+}
+
+pub fn print_text_file_summary(
+    out: &mut dyn Write,
+    summary: &FileSummary,
+    human: bool,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "Files: {}, total: {}, mean: {:.1}, median: {}",
+        count(summary.files, human),
+        count(summary.total, human),
+        summary.mean,
+        summary.median
+    )
+}
+
+pub fn print_text_subtotals(
+    out: &mut dyn Write,
+    targets: &[FileStats],
+    human: bool,
+) -> io::Result<()> {
+    for stats in targets {
+        let lines = format!(": {}", count(stats.lines, human));
+        write_path_line(out, "Subtotal ", &stats.path, &lines)?;
+    }
+    Ok(())
+}
+
+/// Prints `<ext>: <lines> (<share of total>%)` for each extension.
+pub fn print_text_by_ext(
+    out: &mut dyn Write,
+    totals: &[(String, u64)],
+    total: u64,
+    human: bool,
+) -> io::Result<()> {
+    for (ext, lines) in totals {
+        writeln!(
+            out,
+            "{}: {} ({:.1}%)",
+            ext_label(ext),
+            count(*lines, human),
+            percent(*lines, total)
+        )?;
+    }
+    Ok(())
+}
+
+/// Lines under one first-level directory of a target, for `--by-dir`.
+#[derive(Debug, Clone)]
+pub struct DirTotal {
+    pub path: PathBuf,
+    pub lines: u64,
+    /// The directory's own per-extension totals, sorted like `--by-ext`'s,
+    /// when both are asked for.
+    pub by_ext: Option<Vec<(String, u64)>>,
+}
+
+/// Prints each directory's share of the total, followed by its
+/// extensions' shares of the directory if they were collected.
+pub fn print_text_by_dir(
+    out: &mut dyn Write,
+    dirs: &[DirTotal],
+    total: u64,
+    human: bool,
+) -> io::Result<()> {
+    for dir in dirs {
+        let lines = format!(
+            ": {} ({:.1}%)",
+            count(dir.lines, human),
+            percent(dir.lines, total)
+        );
+        write_path_line(out, "", &dir.path, &lines)?;
+        for (ext, lines) in dir.by_ext.iter().flatten() {
+            writeln!(
+                out,
+                "  {}: {} ({:.1}%)",
+                ext_label(ext),
+                count(*lines, human),
+                percent(*lines, dir.lines)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// `part` as a percentage of `total`, or 0 if `total` is 0.
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+fn ext_label(ext: &str) -> &str {
+    if ext.is_empty() { "(none)" } else { ext }
+}
+
+pub fn print_text_summary(
+    out: &mut dyn Write,
+    total: &FileStats,
+    elapsed: Duration,
+    human: bool,
+) -> io::Result<()> {
+    writeln!(out, "Total lines: {}", count(total.lines, human))?;
+    if let Some(words) = total.words {
+        writeln!(out, "Total words: {}", count(words, human))?;
+    }
+    if let Some(chars) = total.chars {
+        writeln!(out, "Total chars: {}", count(chars, human))?;
+    }
+    if let Some(tokens) = total.tokens {
+        writeln!(out, "Total tokens (estimate): {}", count(tokens, human))?;
+    }
+    if let Some(bytes) = total.bytes {
+        writeln!(out, "Total bytes: {}", count(bytes, human))?;
+    }
+    if let Some(unique) = total.unique_lines {
+        writeln!(out, "Unique lines: {}", count(unique, human))?;
+    }
+    if let Some(c) = total.classes {
+        writeln!(out, "Blank lines: {}", count(c.blank, human))?;
+        writeln!(out, "Comment lines: {}", count(c.comment, human))?;
+        writeln!(out, "Code lines: {}", count(c.code, human))?;
+    }
+    if let Some(l) = &total.longest {
+        let length = format!("Longest line: {} (", count(l.length, human));
+        write_path_line(out, &length, &l.path, &format!(":{})", l.line))?;
+    }
+    let skipped = &total.skipped;
+    if skipped.filtered + skipped.binary + skipped.duplicates + skipped.symlinks > 0 {
+        let mut line = format!(
+            "Skipped files: {} filtered, {} binary",
+            count(skipped.filtered, human),
+            count(skipped.binary, human)
+        );
+        if skipped.duplicates > 0 {
+            line.push_str(&format!(", {} duplicate", count(skipped.duplicates, human)));
+        }
+        if skipped.symlinks > 0 {
+            line.push_str(&format!(", {} symlinked", count(skipped.symlinks, human)));
+        }
+        writeln!(out, "{}", line)?;
+    }
+    if total.truncated > 0 {
+        writeln!(out, "Truncated files: {}", count(total.truncated, human))?;
+    }
+    if total.errors > 0 {
+        let mut line = format!(
+            "Failed: {} ({} permission denied",
+            count(total.errors, human),
+            count(total.permission_denied, human)
+        );
+        if total.timed_out > 0 {
+            line.push_str(&format!(", {} timed out", count(total.timed_out, human)));
+        }
+        writeln!(out, "{})", line)?;
+    }
+    print_text_time(out, elapsed, human)?;
+    if let Some(rate) = Throughput::new(total, elapsed) {
+        let mut line = format!("Throughput: {} lines/s", count(rate.lines, human));
+        if let Some(mb) = rate.megabytes {
+            line.push_str(&format!(", {:.1} MB/s", mb));
+        }
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// How fast a run went, in lines and, with `--bytes`, MB (1024 × 1024
+/// bytes) per second.
+struct Throughput {
+    lines: u64,
+    megabytes: Option<f64>,
+}
+
+impl Throughput {
+    /// `None` if no time was measured at all.
+    fn new(total: &FileStats, elapsed: Duration) -> Option<Throughput> {
+        let secs = elapsed.as_secs_f64();
+        (secs > 0.0).then(|| Throughput {
+            lines: (total.lines as f64 / secs).round() as u64,
+            megabytes: total
+                .bytes
+                .map(|bytes| bytes as f64 / (1024.0 * 1024.0) / secs),
+        })
+    }
+}
+
+pub fn print_text_time(out: &mut dyn Write, elapsed: Duration, human: bool) -> io::Result<()> {
+    writeln!(out, "Time taken: {}", duration(elapsed, human))
+}
+
+/// Formats a duration for text output, shortened with `human`.
+pub fn duration(d: Duration, human: bool) -> String {
+    if human {
+        human_duration(d)
+    } else {
+        format!("{:?}", d)
+    }
+}
+
+/// Lists the files of `--check-endings` that mix both kinds of line ending.
+pub fn print_text_mixed_endings(out: &mut dyn Write, mixed: &[FileStats]) -> io::Result<()> {
+    if mixed.is_empty() {
+        return writeln!(out, "Mixed line endings: none");
+    }
+    let noun = if mixed.len() == 1 { "file" } else { "files" };
+    writeln!(out, "Mixed line endings: {} {}", mixed.len(), noun)?;
+    for stats in mixed {
+        let e = stats.endings.unwrap_or_default();
+        let counts = format!(" ({} LF, {} CRLF)", e.lf, e.crlf);
+        write_path_line(out, "  ", &stats.path, &counts)?;
+    }
+    Ok(())
+}
+
+/// The ways of splitting up the total that were asked for.
+#[derive(Default)]
+pub struct Breakdowns<'a> {
+    /// Each target's own total, for `--subtotals`.
+    pub targets: Option<&'a [FileStats]>,
+    pub by_ext: Option<&'a [(String, u64)]>,
+    pub by_dir: Option<&'a [DirTotal]>,
+}
+
+/// Files singled out by `--check-endings` and `--jsonl-validate`, each
+/// list sorted by path.
+#[derive(Default)]
+pub struct Flagged {
+    /// Files mixing `\n` and `\r\n`.
+    pub mixed_endings: Vec<FileStats>,
+    /// Files with invalid JSON records.
+    pub invalid_jsonl: Vec<FileStats>,
+}
+
+/// Prints the record counts of `--jsonl-validate`, then the files with
+/// invalid records and the first of their invalid line numbers.
+pub fn print_text_jsonl(
+    out: &mut dyn Write,
+    total: &JsonlRecords,
+    invalid: &[FileStats],
+    human: bool,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "JSON records: {} valid, {} invalid",
+        count(total.valid, human),
+        count(total.invalid, human)
+    )?;
+    for stats in invalid {
+        let Some(records) = &stats.jsonl else {
+            continue;
+        };
+        let lines: Vec<String> = records.invalid_lines.iter().map(u64::to_string).collect();
+        let more = records.invalid - records.invalid_lines.len() as u64;
+        let mut suffix = format!(": line {}", lines.join(", "));
+        if more > 0 {
+            suffix.push_str(&format!(" and {} more", count(more, human)));
+        }
+        write_path_line(out, "  ", &stats.path, &suffix)?;
+    }
+    Ok(())
+}
+
+/// A file or directory that couldn't be counted.
+pub struct Failure {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Lists what couldn't be counted under `--per-file`, as
+/// `ERROR\t<path>\t<reason>`.
+pub fn print_text_failures(out: &mut dyn Write, failures: &[Failure]) -> io::Result<()> {
+    for failure in failures {
+        write_path_line(
+            out,
+            "ERROR\t",
+            &failure.path,
+            &format!("\t{}", failure.reason),
+        )?;
+    }
+    Ok(())
+}
+
+/// Says that the run stopped at `--total-cap`, so the counts are short.
+pub fn print_text_capped(out: &mut dyn Write, cap: u64, human: bool) -> io::Result<()> {
+    writeln!(
+        out,
+        "Stopped at the total cap of {} lines; the counts are incomplete.",
+        count(cap, human)
+    )
+}
+
+/// Lists the directories of `--show-empty-dirs`.
+pub fn print_text_empty_dirs(out: &mut dyn Write, dirs: &[PathBuf]) -> io::Result<()> {
+    if dirs.is_empty() {
+        return writeln!(out, "Empty directories: none");
+    }
+    writeln!(out, "Empty directories: {}", dirs.len())?;
+    for dir in dirs {
+        write_path_line(out, "  ", dir, "")?;
+    }
+    Ok(())
+}
+
+/// Writes `before`, `path`, `after` and a line break.
+///
+/// Text output keeps paths byte for byte, so a file name that isn't valid
+/// UTF-8 comes out exactly as it is on disk, like `ls` and `find` print it.
+pub fn write_path_line(
+    out: &mut dyn Write,
+    before: &str,
+    path: &Path,
+    after: &str,
+) -> io::Result<()> {
+    out.write_all(before.as_bytes())?;
+    out.write_all(&path_bytes(path))?;
+    out.write_all(after.as_bytes())?;
+    out.write_all(b"\n")
+}
+
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().into()
+}
+
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    path.to_string_lossy().into_owned().into_bytes().into()
+}
+
+/// `path` as a string for JSON and CSV, which must be valid UTF-8. Bytes
+/// that aren't part of a valid UTF-8 sequence are written as `\xHH`, so
+/// no two file names come out looking the same as long as none of them
+/// contains a literal `\x`.
+pub fn path_text(path: &Path) -> String {
+    let bytes = path_bytes(path);
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        for b in chunk.invalid() {
+            text.push_str(&format!("\\x{:02X}", b));
+        }
+    }
+    text
+}
+
+/// Formats `n`, with thousands separators if `human` is set.
+pub fn count(n: u64, human: bool) -> String {
+    let digits = n.to_string();
+    if !human {
+        return digits;
+    }
+
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Formats `d` at a precision that suits its magnitude: `850µs`, `340ms`,
+/// `1.23s`, `2m 5s`.
+pub fn human_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else if secs >= 1 {
+        format!("{:.2}s", d.as_secs_f64())
+    } else if d.as_millis() >= 1 {
+        format!("{}ms", d.as_millis())
+    } else {
+        format!("{}µs", d.as_micros())
+    }
+}
+
+/// Builds the JSON object of the whole report.
+pub fn json_report(
+    total: &FileStats,
+    elapsed: Duration,
+    files: Option<&[FileStats]>,
+    summary: Option<&FileSummary>,
+    breakdowns: &Breakdowns,
+    flagged: &Flagged,
+    capped: Option<u64>,
+) -> String {
+    let mut json = format!("{{\"total\":{}", total.lines);
+    if let Some(words) = total.words {
+        json.push_str(&format!(",\"words\":{}", words));
+    }
+    if let Some(chars) = total.chars {
+        json.push_str(&format!(",\"chars\":{}", chars));
+    }
+    if let Some(tokens) = total.tokens {
+        json.push_str(&format!(",\"tokens\":{}", tokens));
+    }
+    if let Some(bytes) = total.bytes {
+        json.push_str(&format!(",\"bytes\":{}", bytes));
+    }
+    if let Some(unique) = total.unique_lines {
+        json.push_str(&format!(",\"unique_lines\":{}", unique));
+    }
+    if let Some(c) = total.classes {
+        json.push_str(&json_classes(&c));
+    }
+    if let Some(l) = &total.longest {
+        json.push_str(&format!(
+            ",\"longest\":{{\"path\":{},\"line\":{},\"length\":{}}}",
+            json_string(&path_text(&l.path)),
+            l.line,
+            l.length
+        ));
+    }
+    if let Some(e) = &total.endings {
+        json.push_str(&format!(
+            ",\"endings\":{{\"lf\":{},\"crlf\":{}}}",
+            e.lf, e.crlf
+        ));
+        json.push_str(",\"mixed_endings\":[");
+        for (i, stats) in flagged.mixed_endings.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str(&json_string(&path_text(&stats.path)));
+        }
+        json.push(']');
+    }
+    if let Some(records) = &total.jsonl {
+        json.push_str(&json_jsonl(records, false));
+        let files: Vec<String> = flagged
+            .invalid_jsonl
+            .iter()
+            .map(|stats| {
+                let lines = stats.jsonl.as_ref().map(|r| &r.invalid_lines[..]);
+                format!(
+                    "{{\"path\":{},\"lines\":{}}}",
+                    json_string(&path_text(&stats.path)),
+                    json_numbers(lines.unwrap_or_default())
+                )
+            })
+            .collect();
+        json.push_str(&format!(",\"invalid_records\":[{}]", files.join(",")));
+    }
+    if let Some(dirs) = &total.empty_dirs {
+        json.push_str(",\"empty_dirs\":[");
+        for (i, dir) in dirs.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str(&json_string(&path_text(dir)));
+        }
+        json.push(']');
+    }
+    json.push_str(&format!(
+        ",\"skipped\":{{\"filtered\":{},\"binary\":{},\"duplicates\":{},\"symlinks\":{}}}",
+        total.skipped.filtered,
+        total.skipped.binary,
+        total.skipped.duplicates,
+        total.skipped.symlinks
+    ));
+    if total.truncated > 0 {
+        json.push_str(&format!(",\"truncated\":{}", total.truncated));
+    }
+    if let Some(cap) = capped {
+        json.push_str(&format!(",\"capped\":{}", cap));
+    }
+    json.push_str(&format!(
+        ",\"failed\":{{\"total\":{},\"permission_denied\":{},\"timed_out\":{}}}",
+        total.errors, total.permission_denied, total.timed_out
+    ));
+    json.push_str(&format!(
+        ",\"elapsed_ms\":{:.3}",
+        elapsed.as_secs_f64() * 1000.0
+    ));
+    if let Some(rate) = Throughput::new(total, elapsed) {
+        json.push_str(&format!(",\"lines_per_sec\":{}", rate.lines));
+        if let Some(mb) = rate.megabytes {
+            json.push_str(&format!(",\"mb_per_sec\":{:.1}", mb));
+        }
+    }
+
+    if let Some(files) = files {
+        json.push_str(",\"files\":");
+        json.push_str(&json_stats_array(files));
+    }
+    if let Some(summary) = summary {
+        json.push_str(&format!(
+            ",\"summary\":{{\"files\":{},\"total\":{},\"mean\":{:.3},\"median\":{}}}",
+            summary.files, summary.total, summary.mean, summary.median
+        ));
+    }
+
+    if let Some(targets) = breakdowns.targets {
+        json.push_str(",\"targets\":");
+        json.push_str(&json_stats_array(targets));
+    }
+
+    if let Some(by_ext) = breakdowns.by_ext {
+        json.push_str(",\"by_ext\":");
+        json.push_str(&json_by_ext(by_ext, total.lines));
+    }
+
+    if let Some(dirs) = breakdowns.by_dir {
+        let dirs: Vec<String> = dirs
+            .iter()
+            .map(|dir| {
+                let mut json = format!(
+                    "{{\"path\":{},\"lines\":{},\"percent\":{:.1}",
+                    json_string(&path_text(&dir.path)),
+                    dir.lines,
+                    percent(dir.lines, total.lines)
+                );
+                if let Some(by_ext) = &dir.by_ext {
+                    json.push_str(",\"by_ext\":");
+                    json.push_str(&json_by_ext(by_ext, dir.lines));
+                }
+                json.push('}');
+                json
+            })
+            .collect();
+        json.push_str(&format!(",\"by_dir\":[{}]", dirs.join(",")));
+    }
+
+    json.push('}');
+    json
+}
+
+/// A JSON array of per-extension totals and their shares of `total`.
+fn json_by_ext(by_ext: &[(String, u64)], total: u64) -> String {
+    let exts: Vec<String> = by_ext
+        .iter()
+        .map(|(ext, lines)| {
+            format!(
+                "{{\"ext\":{},\"lines\":{},\"percent\":{:.1}}}",
+                json_string(ext),
+                lines,
+                percent(*lines, total)
+            )
+        })
+        .collect();
+    format!("[{}]", exts.join(","))
+}
+
+/// Prints one line of `--format=ndjson` output for a file.
+pub fn print_ndjson_file(out: &mut dyn Write, stats: &FileStats) -> io::Result<()> {
+    writeln!(out, "{}", json_stats(stats))
+}
+
+/// Prints a [`json_report`] object as the last line of `--format=ndjson`
+/// output, tagged with `"type":"summary"` so it stands out from the files.
+pub fn print_ndjson_summary(out: &mut dyn Write, report: &str) -> io::Result<()> {
+    writeln!(out, "{{\"type\":\"summary\",{}", &report[1..])
+}
+
+/// Prints a `path,lines[,words][,chars][,tokens][,bytes][,unique][,blank,comment,code][,longest]` table
+/// with one row per file, followed by a `TOTAL` row if `with_total` is set.
+/// The optional columns are those present in `total`.
+pub fn print_csv(
+    out: &mut dyn Write,
+    files: &[FileStats],
+    total: &FileStats,
+    with_total: bool,
+) -> io::Result<()> {
+    let mut header = String::from("path,lines");
+    if total.words.is_some() {
+        header.push_str(",words");
+    }
+    if total.chars.is_some() {
+        header.push_str(",chars");
+    }
+    if total.tokens.is_some() {
+        header.push_str(",tokens");
+    }
+    if total.bytes.is_some() {
+        header.push_str(",bytes");
+    }
+    if total.unique_lines.is_some() {
+        header.push_str(",unique");
+    }
+    if total.classes.is_some() {
+        header.push_str(",blank,comment,code");
+    }
+    let longest = total.longest.is_some();
+    if longest {
+        header.push_str(",longest");
+    }
+    if total.endings.is_some() {
+        header.push_str(",endings");
+    }
+    writeln!(out, "{}", header)?;
+
+    for stats in files {
+        let label = csv_field(&path_text(&stats.path));
+        writeln!(out, "{}", csv_row(&label, stats, longest))?;
+    }
+    if with_total {
+        writeln!(out, "{}", csv_row("TOTAL", total, longest))?;
+    }
+    Ok(())
+}
+
+/// Files without lines have no longest line, so with `longest` set their
+/// column is left empty to keep the rows aligned.
+fn csv_row(label: &str, stats: &FileStats, longest: bool) -> String {
+    let mut row = format!("{},{}", label, stats.lines);
+    for n in [
+        stats.words,
+        stats.chars,
+        stats.tokens,
+        stats.bytes,
+        stats.unique_lines,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        row.push_str(&format!(",{}", n));
+    }
+    if let Some(c) = stats.classes {
+        row.push_str(&format!(",{},{},{}", c.blank, c.comment, c.code));
+    }
+    if longest {
+        row.push(',');
+        if let Some(l) = &stats.longest {
+            row.push_str(&l.length.to_string());
+        }
+    }
+    if let Some(e) = &stats.endings {
+        row.push_str(&format!(",{}", e.kind()));
+    }
+    row
+}
+
+/// Totals of a comparison against a baseline.
+pub struct DiffTotals {
+    pub before: u64,
+    pub after: u64,
+    /// Sum of the growth of the files that grew.
+    pub added: u64,
+    /// Sum of the shrinkage of the files that shrank.
+    pub removed: u64,
+}
+
+impl DiffTotals {
+    pub fn new(baseline: &Baseline, total: &FileStats, changes: &[Change]) -> Self {
+        let delta = |sign: i64| -> u64 {
+            changes
+                .iter()
+                .map(|c| c.delta() * sign)
+                .filter(|&d| d > 0)
+                .sum::<i64>() as u64
+        };
+        DiffTotals {
+            before: baseline.total,
+            after: total.lines,
+            added: delta(1),
+            removed: delta(-1),
+        }
+    }
+}
+
+/// Prints `<delta>\t<before>\t<after>\t<path>` for each changed file, with
+/// `-` for the side a file is missing from, followed by the totals.
+pub fn print_text_diff(
+    out: &mut dyn Write,
+    changes: &[Change],
+    totals: &DiffTotals,
+    human: bool,
+) -> io::Result<()> {
+    let side = |n: Option<u64>| n.map_or_else(|| "-".to_string(), |n| count(n, human));
+    for c in changes {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            signed(c.delta(), human),
+            side(c.before),
+            side(c.after),
+            c.path
+        )?;
+    }
+    writeln!(out, "Added lines: {}", count(totals.added, human))?;
+    writeln!(out, "Removed lines: {}", count(totals.removed, human))?;
+    writeln!(
+        out,
+        "Total lines: {} (was {}, {})",
+        count(totals.after, human),
+        count(totals.before, human),
+        signed(totals.after as i64 - totals.before as i64, human)
+    )
+}
+
+/// Builds the JSON object of a comparison against a baseline.
+pub fn json_diff(changes: &[Change], totals: &DiffTotals, elapsed: Duration) -> String {
+    let files: Vec<String> = changes.iter().map(json_change).collect();
+    format!(
+        "{{{},\"files\":[{}]}}",
+        json_diff_totals(totals, elapsed),
+        files.join(",")
+    )
+}
+
+/// Prints one changed file per line, then the totals tagged with
+/// `"type":"summary"`.
+pub fn print_ndjson_diff(
+    out: &mut dyn Write,
+    changes: &[Change],
+    totals: &DiffTotals,
+    elapsed: Duration,
+) -> io::Result<()> {
+    for c in changes {
+        writeln!(out, "{}", json_change(c))?;
+    }
+    writeln!(
+        out,
+        "{{\"type\":\"summary\",{}}}",
+        json_diff_totals(totals, elapsed)
+    )
+}
+
+/// The fields shared by both JSON forms of a comparison, without braces.
+fn json_diff_totals(totals: &DiffTotals, elapsed: Duration) -> String {
+    format!(
+        "\"total\":{},\"baseline_total\":{},\"delta\":{},\"added\":{},\"removed\":{},\"elapsed_ms\":{:.3}",
+        totals.after,
+        totals.before,
+        totals.after as i64 - totals.before as i64,
+        totals.added,
+        totals.removed,
+        elapsed.as_secs_f64() * 1000.0
+    )
+}
+
+/// Prints a `path,before,after,delta` table of the changed files.
+pub fn print_csv_diff(out: &mut dyn Write, changes: &[Change]) -> io::Result<()> {
+    writeln!(out, "path,before,after,delta")?;
+    for c in changes {
+        let side = |n: Option<u64>| n.map_or_else(String::new, |n| n.to_string());
+        writeln!(
+            out,
+            "{},{},{},{}",
+            csv_field(&c.path),
+            side(c.before),
+            side(c.after),
+            c.delta()
+        )?;
+    }
+    Ok(())
+}
+
+fn json_change(c: &Change) -> String {
+    let side = |n: Option<u64>| n.map_or_else(|| "null".to_string(), |n| n.to_string());
+    format!(
+        "{{\"path\":{},\"before\":{},\"after\":{},\"delta\":{}}}",
+        json_string(&c.path),
+        side(c.before),
+        side(c.after),
+        c.delta()
+    )
+}
+
+/// What one side of `--compare` counted.
+pub struct Side {
+    pub path: String,
+    pub lines: u64,
+    /// Lines by lowercase extension.
+    pub by_ext: HashMap<String, u64>,
+}
+
+/// Two counts side by side, for `--compare`.
+pub struct Comparison {
+    pub left: Side,
+    pub right: Side,
+    /// Lines by extension on either side, largest first.
+    pub by_ext: Vec<(String, u64, u64)>,
+}
+
+impl Comparison {
+    pub fn new(left: Side, right: Side) -> Self {
+        let mut by_ext: Vec<(String, u64, u64)> = left
+            .by_ext
+            .keys()
+            .chain(
+                right
+                    .by_ext
+                    .keys()
+                    .filter(|ext| !left.by_ext.contains_key(*ext)),
+            )
+            .map(|ext| {
+                let side = |s: &Side| s.by_ext.get(ext).copied().unwrap_or(0);
+                (ext.clone(), side(&left), side(&right))
+            })
+            .collect();
+        by_ext.sort_by(|a, b| (b.1 + b.2).cmp(&(a.1 + a.2)).then_with(|| a.0.cmp(&b.0)));
+        Comparison {
+            left,
+            right,
+            by_ext,
+        }
+    }
+
+    fn delta(&self) -> i64 {
+        self.right.lines as i64 - self.left.lines as i64
+    }
+}
+
+/// Prints a tab-separated `<ext>\t<left>\t<right>\t<delta>` table headed
+/// by the two paths, ending with the totals.
+pub fn print_text_comparison(out: &mut dyn Write, c: &Comparison, human: bool) -> io::Result<()> {
+    writeln!(out, "\t{}\t{}\tdelta", c.left.path, c.right.path)?;
+    for (ext, left, right) in &c.by_ext {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            ext_label(ext),
+            count(*left, human),
+            count(*right, human),
+            signed(*right as i64 - *left as i64, human)
+        )?;
+    }
+    writeln!(
+        out,
+        "Total\t{}\t{}\t{}",
+        count(c.left.lines, human),
+        count(c.right.lines, human),
+        signed(c.delta(), human)
+    )
+}
+
+/// Builds the JSON object of a `--compare` run.
+pub fn json_comparison(c: &Comparison, elapsed: Duration) -> String {
+    let by_ext: Vec<String> = c.by_ext.iter().map(json_ext_comparison).collect();
+    format!(
+        "{{{},\"by_ext\":[{}]}}",
+        json_comparison_totals(c, elapsed),
+        by_ext.join(",")
+    )
+}
+
+/// Prints one extension per line, then the totals tagged with
+/// `"type":"summary"`.
+pub fn print_ndjson_comparison(
+    out: &mut dyn Write,
+    c: &Comparison,
+    elapsed: Duration,
+) -> io::Result<()> {
+    for ext in &c.by_ext {
+        writeln!(out, "{}", json_ext_comparison(ext))?;
+    }
+    writeln!(
+        out,
+        "{{\"type\":\"summary\",{}}}",
+        json_comparison_totals(c, elapsed)
+    )
+}
+
+fn json_comparison_totals(c: &Comparison, elapsed: Duration) -> String {
+    let side = |s: &Side| {
+        format!(
+            "{{\"path\":{},\"total\":{}}}",
+            json_string(&s.path),
+            s.lines
+        )
+    };
+    format!(
+        "\"left\":{},\"right\":{},\"delta\":{},\"elapsed_ms\":{:.3}",
+        side(&c.left),
+        side(&c.right),
+        c.delta(),
+        elapsed.as_secs_f64() * 1000.0
+    )
+}
+
+fn json_ext_comparison((ext, left, right): &(String, u64, u64)) -> String {
+    format!(
+        "{{\"ext\":{},\"left\":{},\"right\":{},\"delta\":{}}}",
+        json_string(ext),
+        left,
+        right,
+        *right as i64 - *left as i64
+    )
+}
+
+/// Prints an `ext,left,right,delta` table with a `TOTAL` row.
+pub fn print_csv_comparison(out: &mut dyn Write, c: &Comparison) -> io::Result<()> {
+    writeln!(out, "ext,left,right,delta")?;
+    for (ext, left, right) in &c.by_ext {
+        writeln!(
+            out,
+            "{},{},{},{}",
+            csv_field(ext),
+            left,
+            right,
+            *right as i64 - *left as i64
+        )?;
+    }
+    writeln!(
+        out,
+        "TOTAL,{},{},{}",
+        c.left.lines,
+        c.right.lines,
+        c.delta()
+    )
+}
+
+/// Formats `n` with an explicit sign, e.g. `+12` or `-3`.
+fn signed(n: i64, human: bool) -> String {
+    let sign = if n < 0 { '-' } else { '+' };
+    format!("{}{}", sign, count(n.unsigned_abs(), human))
+}
+
+/// Quotes `s` per RFC 4180 if it contains a comma, quote or line break.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn json_stats_array(list: &[FileStats]) -> String {
+    let mut out = String::from("[");
+    for (i, stats) in list.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&json_stats(stats));
+    }
+    out.push(']');
+    out
+}
+
+fn json_stats(stats: &FileStats) -> String {
+    let mut out = format!(
+        "{{\"path\":{},\"lines\":{}",
+        json_string(&path_text(&stats.path)),
+        stats.lines
+    );
+    if let Some(words) = stats.words {
+        out.push_str(&format!(",\"words\":{}", words));
+    }
+    if let Some(chars) = stats.chars {
+        out.push_str(&format!(",\"chars\":{}", chars));
+    }
+    if let Some(tokens) = stats.tokens {
+        out.push_str(&format!(",\"tokens\":{}", tokens));
+    }
+    if let Some(bytes) = stats.bytes {
+        out.push_str(&format!(",\"bytes\":{}", bytes));
+    }
+    if let Some(unique) = stats.unique_lines {
+        out.push_str(&format!(",\"unique_lines\":{}", unique));
+    }
+    if let Some(c) = stats.classes {
+        out.push_str(&json_classes(&c));
+    }
+    if let Some(l) = &stats.longest {
+        out.push_str(&json_longest(l));
+    }
+    if let Some(e) = &stats.endings {
+        out.push_str(&json_endings(e));
+    }
+    if let Some(records) = &stats.jsonl {
+        out.push_str(&json_jsonl(records, true));
+    }
+    if stats.truncated > 0 {
+        out.push_str(",\"truncated\":true");
+    }
+    out.push('}');
+    out
+}
+
+fn json_jsonl(records: &JsonlRecords, with_lines: bool) -> String {
+    let mut json = format!(
+        ",\"jsonl\":{{\"valid\":{},\"invalid\":{}",
+        records.valid, records.invalid
+    );
+    if with_lines {
+        json.push_str(&format!(
+            ",\"invalid_lines\":{}",
+            json_numbers(&records.invalid_lines)
+        ));
+    }
+    json.push('}');
+    json
+}
+
+fn json_numbers(numbers: &[u64]) -> String {
+    let numbers: Vec<String> = numbers.iter().map(u64::to_string).collect();
+    format!("[{}]", numbers.join(","))
+}
+
+fn json_longest(l: &LongestLine) -> String {
+    format!(
+        ",\"longest\":{{\"line\":{},\"length\":{}}}",
+        l.line, l.length
+    )
+}
+
+fn json_endings(e: &Endings) -> String {
+    format!(
+        ",\"endings\":{{\"kind\":\"{}\",\"lf\":{},\"crlf\":{}}}",
+        e.kind(),
+        e.lf,
+        e.crlf
+    )
+}
+
+fn json_classes(c: &LineClasses) -> String {
+    format!(
+        ",\"blank\":{},\"comment\":{},\"code\":{}",
+        c.blank, c.comment, c.code
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_file_names_come_out_byte_for_byte() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let raw = b"src/caf\xE9.rs";
+        let path = Path::new(OsStr::from_bytes(raw));
+        assert_eq!(&path_bytes(path)[..], raw);
+        let mut out = Vec::new();
+        write_path_line(&mut out, "3\t", path, "").unwrap();
+        assert_eq!(out, b"3\tsrc/caf\xE9.rs\n");
+        // JSON and CSV get valid UTF-8 instead.
+        assert_eq!(path_text(path), "src/caf\\xE9.rs");
+        assert_eq!(path_text(Path::new("caf\u{e9}.rs")), "caf\u{e9}.rs");
+    }
+
+    fn file(path: &str, lines: u64) -> FileStats {
+        let mut stats = FileStats::new(Path::new(path), &crate::CountOptions::default());
+        stats.lines = lines;
+        stats
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("src/main.rs"), "src/main.rs");
+        assert_eq!(csv_field("a,b.rs"), "\"a,b.rs\"");
+        assert_eq!(csv_field("say \"hi\".rs"), "\"say \"\"hi\"\".rs\"");
+        assert_eq!(csv_field("two\nlines.rs"), "\"two\nlines.rs\"");
+        assert_eq!(csv_field("cr\r.rs"), "\"cr\r.rs\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn csv_rows_keep_awkward_paths_in_one_field() {
+        let files = [file("a,b.rs", 1), file("\"q\".rs", 2), file("x\r\ny.rs", 3)];
+        let total = file("", 6);
+        let mut out = Vec::new();
+        print_csv(&mut out, &files, &total, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "path,lines\n\"a,b.rs\",1\n\"\"\"q\"\".rs\",2\n\"x\r\ny.rs\",3\nTOTAL,6\n"
+        );
+    }
+
+    #[test]
+    fn json_strings_escape_quotes_backslashes_and_control_characters() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(json_string("C:\\src"), "\"C:\\\\src\"");
+        assert_eq!(json_string("a\nb\rc\td"), "\"a\\nb\\rc\\td\"");
+        assert_eq!(
+            json_string("\u{0}\u{1b}\u{1f}"),
+            "\"\\u0000\\u001b\\u001f\""
+        );
+        // Only control characters are escaped; the rest stays as it is.
+        assert_eq!(json_string("\u{7f}é\u{1F600}"), "\"\u{7f}é\u{1F600}\"");
+    }
+
+    #[test]
+    fn json_strings_parse_back_to_the_original() {
+        let awkward: String = (0u8..0x80)
+            .map(char::from)
+            .chain("é\u{2028}".chars())
+            .collect();
+        let parsed: serde_json::Value = serde_json::from_str(&json_string(&awkward)).unwrap();
+        assert_eq!(parsed, awkward);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn json_stats_of_a_non_utf8_path_are_valid_json() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut stats = file("", 3);
+        stats.path = PathBuf::from(OsStr::from_bytes(b"a\"\xFF\n.rs"));
+        let parsed: serde_json::Value = serde_json::from_str(&json_stats(&stats)).unwrap();
+        assert_eq!(parsed["path"], "a\"\\xFF\n.rs");
+        assert_eq!(parsed["lines"], 3);
+    }
+}
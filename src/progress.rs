@@ -0,0 +1,138 @@
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use linecount::{CountOptions, FileStats, select_files};
+
+/// Running status on stderr while a count is in progress.
+///
+/// On a terminal the status is redrawn in place several times a second;
+/// when stderr is redirected a plain line is written every few seconds
+/// instead, so logs don't fill up with carriage returns.
+///
+/// The targets are walked a second time on a thread of their own to find
+/// how many files there are, and once that's known the status shows how
+/// far along the count is and how long the rest should take, going by the
+/// pace so far.
+pub struct Progress {
+    tty: bool,
+    interval: Duration,
+    start: Instant,
+    last: Instant,
+    files: u64,
+    lines: u64,
+    shown: bool,
+    /// Set once the walk for the file count is done.
+    expected: Arc<OnceLock<u64>>,
+    /// Tells that walk to give up once it's no longer needed.
+    stop: Arc<AtomicBool>,
+}
+
+impl Progress {
+    pub fn new(targets: &[(String, bool)], options: &CountOptions) -> Self {
+        let expected = Arc::new(OnceLock::new());
+        let stop = Arc::new(AtomicBool::new(false));
+        // The walk only lists files; it mustn't claim them for a count
+        // across targets or repeat the --verbose messages.
+        let options = CountOptions {
+            counted: None,
+            cache: None,
+            verbose: 0,
+            ..options.clone()
+        };
+        let targets = targets.to_vec();
+        let (found, stopped) = (Arc::clone(&expected), Arc::clone(&stop));
+        thread::spawn(move || {
+            let mut files = 0;
+            for (target, walk) in &targets {
+                let path = Path::new(target);
+                if !(*walk && path.is_dir()) {
+                    files += 1;
+                    continue;
+                }
+                for item in select_files(path, &options) {
+                    if stopped.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    files += item.is_ok() as u64;
+                }
+            }
+            let _ = found.set(files);
+        });
+
+        let tty = io::stderr().is_terminal();
+        let now = Instant::now();
+        Progress {
+            tty,
+            interval: if tty {
+                Duration::from_millis(100)
+            } else {
+                Duration::from_secs(2)
+            },
+            start: now,
+            last: now,
+            files: 0,
+            lines: 0,
+            shown: false,
+            expected,
+            stop,
+        }
+    }
+
+    pub fn update(&mut self, stats: &FileStats) {
+        self.files += 1;
+        self.lines += stats.lines;
+        if self.last.elapsed() >= self.interval {
+            self.last = Instant::now();
+            self.show();
+        }
+    }
+
+    fn show(&mut self) {
+        // Archive members can take the count past the files found.
+        let status = match self.expected.get() {
+            Some(&expected) if self.files <= expected && expected > 0 => {
+                let rest = self
+                    .start
+                    .elapsed()
+                    .mul_f64((expected - self.files) as f64 / self.files as f64);
+                format!(
+                    "{}% ({}/{} files) ~{} remaining, {} lines",
+                    self.files * 100 / expected,
+                    self.files,
+                    expected,
+                    eta(rest),
+                    self.lines
+                )
+            }
+            _ => format!("{} files, {} lines", self.files, self.lines),
+        };
+        if self.tty {
+            eprint!("\r{}\x1b[K", status);
+        } else {
+            eprintln!("{}", status);
+        }
+        self.shown = true;
+    }
+
+    /// Clears the status line so the report starts on a clean line.
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if self.tty && self.shown {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+/// Whole seconds, rounded up so a count never claims to take no time.
+fn eta(d: Duration) -> String {
+    let secs = d.as_secs() + (d.subsec_nanos() > 0) as u64;
+    if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
@@ -0,0 +1,175 @@
+use std::path::Path;
+
+/// Returns true if `path` has a `.sql` extension.
+pub(crate) fn is_sql(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("sql"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Code,
+    /// Inside `'...'` or `"..."`; holds the closing quote.
+    Quoted(u8),
+    LineComment,
+    BlockComment,
+}
+
+/// Counts `;`-terminated SQL statements, ignoring semicolons inside string
+/// literals, quoted identifiers and comments.
+///
+/// Input can be fed in arbitrary chunks: a `-` or `/` (or a `*` inside a
+/// block comment) at the end of a chunk is remembered until the next byte
+/// shows whether it starts or ends a comment.
+pub(crate) struct SqlCounter {
+    state: State,
+    /// The previous byte, if it may be the first half of a two-byte marker.
+    pending: Option<u8>,
+    /// Whether the current statement has anything besides whitespace and
+    /// comments.
+    has_content: bool,
+    statements: u64,
+}
+
+impl SqlCounter {
+    pub(crate) fn new() -> Self {
+        SqlCounter {
+            state: State::Code,
+            pending: None,
+            has_content: false,
+            statements: 0,
+        }
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            self.step(b);
+        }
+    }
+
+    fn step(&mut self, b: u8) {
+        let pending = self.pending.take();
+        match self.state {
+            State::Code => match (pending, b) {
+                (Some(b'-'), b'-') => self.state = State::LineComment,
+                (Some(b'/'), b'*') => self.state = State::BlockComment,
+                _ => {
+                    // A lone `-` or `/` was an operator after all.
+                    if pending.is_some() {
+                        self.has_content = true;
+                    }
+                    self.code_byte(b);
+                }
+            },
+            State::Quoted(quote) => {
+                if b == quote {
+                    self.state = State::Code;
+                }
+            }
+            State::LineComment => {
+                if b == b'\n' {
+                    self.state = State::Code;
+                }
+            }
+            State::BlockComment => {
+                if pending == Some(b'*') && b == b'/' {
+                    self.state = State::Code;
+                } else if b == b'*' {
+                    self.pending = Some(b);
+                }
+            }
+        }
+    }
+
+    fn code_byte(&mut self, b: u8) {
+        match b {
+            b';' => {
+                if self.has_content {
+                    self.statements += 1;
+                }
+                self.has_content = false;
+            }
+            b'-' | b'/' => self.pending = Some(b),
+            b'\'' | b'"' => {
+                self.has_content = true;
+                self.state = State::Quoted(b);
+            }
+            _ if b.is_ascii_whitespace() => {}
+            _ => self.has_content = true,
+        }
+    }
+
+    /// Returns the number of statements, counting a final one that lacks
+    /// its `;`.
+    pub(crate) fn finish(self) -> u64 {
+        let open = self.has_content || (self.state == State::Code && self.pending.is_some());
+        self.statements + open as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Statements in `sql`, checked to be the same for every chunk size.
+    fn statements(sql: &str) -> u64 {
+        let counts: Vec<u64> = [1, 2, 3, sql.len().max(1)]
+            .iter()
+            .map(|&size| {
+                let mut counter = SqlCounter::new();
+                for chunk in sql.as_bytes().chunks(size) {
+                    counter.feed(chunk);
+                }
+                counter.finish()
+            })
+            .collect();
+        assert!(
+            counts.iter().all(|&n| n == counts[0]),
+            "{:?} for {:?}",
+            counts,
+            sql
+        );
+        counts[0]
+    }
+
+    #[test]
+    fn semicolons_in_quotes_are_not_statement_ends() {
+        assert_eq!(statements("SELECT 'a;b';\nSELECT 1;\n"), 2);
+        assert_eq!(statements("SELECT \"odd;name\" FROM t;"), 1);
+        assert_eq!(statements("SELECT '\";'; SELECT \"';\";"), 2);
+    }
+
+    #[test]
+    fn doubled_quotes_stay_inside_the_string() {
+        assert_eq!(statements("SELECT 'it''s; fine';\nSELECT 2;"), 2);
+        assert_eq!(statements("SELECT '';;"), 1);
+    }
+
+    #[test]
+    fn semicolons_in_comments_are_not_statement_ends() {
+        assert_eq!(statements("-- one; two;\nSELECT 1;"), 1);
+        assert_eq!(statements("SELECT 1 /* ; */ + 2;\n/* a;\n b; */"), 1);
+        assert_eq!(statements("/* **; */ SELECT 1;"), 1);
+    }
+
+    #[test]
+    fn lone_dash_and_slash_are_operators() {
+        // Split across chunks of every size, `- -` and `/ *` might look
+        // like the start of a comment.
+        assert_eq!(statements("SELECT 4 - 1; SELECT 4 / 2;"), 2);
+        assert_eq!(statements("SELECT 4-1;SELECT 4/2;"), 2);
+        assert_eq!(statements("-"), 1);
+        assert_eq!(statements("/"), 1);
+        assert_eq!(statements("SELECT 1 --x;\n;"), 1);
+        assert_eq!(statements("SELECT 1 /*x;*/;"), 1);
+    }
+
+    #[test]
+    fn a_final_statement_without_a_semicolon_counts() {
+        assert_eq!(statements("SELECT 1;\nSELECT 2"), 2);
+        assert_eq!(statements("SELECT 1;\n-- done\n"), 1);
+        assert_eq!(statements("SELECT 1; /* trailing */  \n"), 1);
+        assert_eq!(statements(""), 0);
+        assert_eq!(statements(";;\n"), 0);
+    }
+}
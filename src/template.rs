@@ -0,0 +1,260 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use linecount::FileStats;
+
+use crate::output::{count, human_duration, path_bytes};
+
+/// What a template is filled in with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    /// One file's stats, for `--template`.
+    File,
+    /// The totals, for `--summary-template`.
+    Summary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Path,
+    Lines,
+    Words,
+    Chars,
+    Bytes,
+    /// Files counted, not counting binary ones.
+    Files,
+    /// Time taken.
+    Time,
+}
+
+impl Field {
+    fn parse(name: &str, context: Context) -> Option<Field> {
+        let field = match name {
+            "lines" => Field::Lines,
+            "words" => Field::Words,
+            "chars" => Field::Chars,
+            "bytes" => Field::Bytes,
+            "path" if context == Context::File => Field::Path,
+            "files" if context == Context::Summary => Field::Files,
+            "time" if context == Context::Summary => Field::Time,
+            _ => return None,
+        };
+        Some(field)
+    }
+}
+
+#[derive(Debug)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// An output line with `{name}` placeholders, and `{{` and `}}` for
+/// literal braces.
+#[derive(Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str, context: Context) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(i) = rest.find(['{', '}']) {
+            text.push_str(&rest[..i]);
+            let brace = &rest[i..i + 1];
+            rest = &rest[i + 1..];
+            if let Some(after) = rest.strip_prefix(brace) {
+                text.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                return Err("unmatched '}' (write '}}' for a literal one)".to_string());
+            }
+            let Some(end) = rest.find('}') else {
+                return Err("unclosed '{' (write '{{' for a literal one)".to_string());
+            };
+            let name = &rest[..end];
+            let field = Field::parse(name, context)
+                .ok_or_else(|| format!("unknown placeholder {{{}}}", name))?;
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+            }
+            parts.push(Part::Field(field));
+            rest = &rest[end + 1..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Returns true if the template has a `field` placeholder.
+    pub fn uses(&self, field: Field) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Field(f) if *f == field))
+    }
+
+    /// Writes the template filled in with one file's stats.
+    pub fn write_file(
+        &self,
+        out: &mut dyn Write,
+        stats: &FileStats,
+        human: bool,
+    ) -> io::Result<()> {
+        self.write(out, stats, 0, Duration::ZERO, human)
+    }
+
+    /// Writes the template filled in with the totals of a run.
+    pub fn write_summary(
+        &self,
+        out: &mut dyn Write,
+        total: &FileStats,
+        files: u64,
+        elapsed: Duration,
+        human: bool,
+    ) -> io::Result<()> {
+        self.write(out, total, files, elapsed, human)
+    }
+
+    fn write(
+        &self,
+        out: &mut dyn Write,
+        stats: &FileStats,
+        files: u64,
+        elapsed: Duration,
+        human: bool,
+    ) -> io::Result<()> {
+        let mut line = Vec::new();
+        for part in &self.parts {
+            let field = match part {
+                Part::Text(text) => {
+                    line.extend_from_slice(text.as_bytes());
+                    continue;
+                }
+                Part::Field(field) => *field,
+            };
+            let value = match field {
+                Field::Path => {
+                    line.extend_from_slice(&path_bytes(&stats.path));
+                    continue;
+                }
+                Field::Time => {
+                    line.extend_from_slice(human_duration(elapsed).as_bytes());
+                    continue;
+                }
+                Field::Lines => stats.lines,
+                Field::Files => files,
+                // Counting is switched on for every placeholder in use.
+                Field::Words => stats.words.unwrap_or(0),
+                Field::Chars => stats.chars.unwrap_or(0),
+                Field::Bytes => stats.bytes.unwrap_or(0),
+            };
+            line.extend_from_slice(count(value, human).as_bytes());
+        }
+        line.push(b'\n');
+        out.write_all(&line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use linecount::CountOptions;
+
+    use super::*;
+
+    fn stats() -> FileStats {
+        let mut stats = FileStats::new(Path::new("src/main.rs"), &CountOptions::default());
+        stats.lines = 1234;
+        stats.words = Some(5);
+        stats.chars = Some(6);
+        stats.bytes = Some(7);
+        stats
+    }
+
+    fn file_line(template: &str, human: bool) -> String {
+        let template = Template::parse(template, Context::File).unwrap();
+        let mut out = Vec::new();
+        template.write_file(&mut out, &stats(), human).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn summary_line(template: &str, files: u64, elapsed: Duration) -> String {
+        let template = Template::parse(template, Context::Summary).unwrap();
+        let mut out = Vec::new();
+        template
+            .write_summary(&mut out, &stats(), files, elapsed, false)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn every_file_field_is_filled_in() {
+        assert_eq!(
+            file_line("{path}: {lines} {words} {chars} {bytes}", false),
+            "src/main.rs: 1234 5 6 7\n"
+        );
+        assert_eq!(file_line("{lines}", true), "1,234\n");
+        assert_eq!(file_line("no fields", false), "no fields\n");
+        assert_eq!(file_line("", false), "\n");
+    }
+
+    #[test]
+    fn every_summary_field_is_filled_in() {
+        let elapsed = Duration::from_millis(1500);
+        assert_eq!(
+            summary_line(
+                "{files} files, {lines} {words} {chars} {bytes} in {time}",
+                3,
+                elapsed
+            ),
+            format!("3 files, 1234 5 6 7 in {}\n", human_duration(elapsed))
+        );
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(file_line("{{lines}} = {lines}", false), "{lines} = 1234\n");
+        assert_eq!(file_line("}}{{", false), "}{\n");
+        assert_eq!(file_line("{{{lines}}}", false), "{1234}\n");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_refused() {
+        let error = |template, context| Template::parse(template, context).unwrap_err();
+        assert_eq!(error("{size}", Context::File), "unknown placeholder {size}");
+        assert_eq!(error("{}", Context::File), "unknown placeholder {}");
+        // Each field only exists where it has a value.
+        assert_eq!(
+            error("{files}", Context::File),
+            "unknown placeholder {files}"
+        );
+        assert_eq!(error("{time}", Context::File), "unknown placeholder {time}");
+        assert_eq!(
+            error("{path}", Context::Summary),
+            "unknown placeholder {path}"
+        );
+    }
+
+    #[test]
+    fn unmatched_braces_are_refused() {
+        let error = |template| Template::parse(template, Context::File).unwrap_err();
+        assert!(error("{lines").starts_with("unclosed '{'"));
+        assert!(error("lines {").starts_with("unclosed '{'"));
+        assert!(error("lines}").starts_with("unmatched '}'"));
+        assert!(error("{lines}}").starts_with("unmatched '}'"));
+    }
+
+    #[test]
+    fn uses_reports_the_placeholders_in_the_template() {
+        let template = Template::parse("{files} and {{lines}}", Context::Summary).unwrap();
+        assert!(template.uses(Field::Files));
+        assert!(!template.uses(Field::Lines));
+    }
+}
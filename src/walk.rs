@@ -0,0 +1,555 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, DirEntry, read_dir};
+use std::path::{Path, PathBuf};
+
+use crate::glob::{glob_match, to_slash};
+use crate::ignore::{self, IgnoreRules};
+use crate::{CountOptions, LineCountError, filter};
+
+/// Metadata directories of version control systems, skipped unless
+/// `CountOptions::count_vcs` is set.
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn", ".bzr"];
+
+/// The order in which a recursive walk visits directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalkOrder {
+    /// Each subdirectory is walked to the bottom before its next sibling.
+    #[default]
+    DepthFirst,
+    /// All of a level is walked before the level below it, so shallow
+    /// files come first.
+    BreadthFirst,
+}
+
+/// A discovered path, or a directory that couldn't be listed.
+pub(crate) type WalkItem = Result<PathBuf, LineCountError>;
+
+pub(crate) fn walk_shallow<'a>(
+    path: &Path,
+    options: &'a CountOptions,
+) -> Box<dyn Iterator<Item = WalkItem> + Send + 'a> {
+    let root = path.to_path_buf();
+    let rules = ignore::rules_for_dir(path, &ignore::root_rules(path, options), options);
+
+    match read_dir(path) {
+        Ok(entries) => Box::new(
+            sorted(entries.flatten().collect(), options)
+                .into_iter()
+                .map(|e| e.path())
+                .filter(move |p| !is_skipped(&root, p, &rules, options))
+                .map(Ok),
+        ),
+        Err(source) => Box::new(std::iter::once(Err(LineCountError::ReadDir {
+            path: path.to_path_buf(),
+            source,
+        }))),
+    }
+}
+
+/// Sorts directory entries by name with `CountOptions::sort_walk`.
+fn sorted(mut entries: Vec<DirEntry>, options: &CountOptions) -> Vec<DirEntry> {
+    if options.sort_walk {
+        entries.sort_by_key(|e| e.file_name());
+    }
+    entries
+}
+
+/// Returns true if a discovered entry should be left out of the walk,
+/// because it's version control metadata, hidden, excluded or ignored.
+fn is_skipped(root: &Path, path: &Path, rules: &IgnoreRules, options: &CountOptions) -> bool {
+    let reason = if !options.count_vcs && is_vcs_dir(path) {
+        "version control"
+    } else if !options.hidden && is_hidden(path) && !is_known_file(path, options) {
+        "hidden"
+    } else if is_excluded(root, path, options) {
+        "excluded"
+    } else if ignore::is_ignored(rules, path, path.is_dir()) {
+        "ignored"
+    } else {
+        return false;
+    };
+    verbose!(options, 1, "skip {}: {}", path.display(), reason);
+    true
+}
+
+fn is_vcs_dir(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|n| VCS_DIRS.iter().any(|d| n == *d))
+        && path.is_dir()
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+}
+
+/// Files in `CountOptions::names`, such as `.gitignore`, are walked even
+/// when they're hidden.
+fn is_known_file(path: &Path, options: &CountOptions) -> bool {
+    filter::is_known_name(path, &options.names) && !path.is_dir()
+}
+
+/// Returns true if `path` matches one of the `--exclude` patterns.
+///
+/// Patterns containing a `/` are matched against the path relative to the
+/// walk root, others against the file or directory name alone.
+fn is_excluded(root: &Path, path: &Path, options: &CountOptions) -> bool {
+    if options.exclude.is_empty() {
+        return false;
+    }
+
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let rel = to_slash(path.strip_prefix(root).unwrap_or(path));
+
+    options.exclude.iter().any(|pattern| {
+        if pattern.contains('/') {
+            glob_match(pattern.trim_matches('/'), &rel)
+        } else {
+            glob_match(pattern, &name)
+        }
+    })
+}
+
+/// How far the walk goes below a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reach {
+    /// Count everything below it.
+    Deep,
+    /// Count its direct files, and descend into the `recursive_paths`.
+    Shallow,
+    /// Only passed through on the way to a deeper `recursive_paths` entry;
+    /// its own files aren't counted.
+    Transit,
+}
+
+/// Walks `root` in `walk_order`, and in name order with `sort_walk`,
+/// yielding every path it finds, each directory (`root` included) once it
+/// has been listed, and an error for each directory that can't be listed.
+/// A directory's entries are yielded together either way; the order only
+/// decides whether a subdirectory's contents come before or after its
+/// later siblings.
+///
+/// Directories that are passed over, for being too deep or a symlink, are
+/// not yielded.
+///
+/// `root` is at depth 0; with `max_depth` set, directories deeper than it
+/// are not descended into, so `Some(0)` only yields root's direct files.
+/// Without `recursive` only root's direct files and the subdirectories named
+/// in `recursive_paths` are walked. Symlinked directories are skipped unless
+/// `follow_symlinks` is set, and each directory is visited at most once so
+/// symlink cycles terminate. Symlinked files are yielded either way, and
+/// left to the caller.
+pub(crate) fn walk_recursive<'a>(
+    root: &Path,
+    options: &'a CountOptions,
+) -> impl Iterator<Item = WalkItem> + 'a {
+    let root = root.to_path_buf();
+    let reach = if options.recursive || options.max_depth.is_some() {
+        Reach::Deep
+    } else {
+        Reach::Shallow
+    };
+    let mut queue = VecDeque::from([(root.clone(), 0, reach, ignore::root_rules(&root, options))]);
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+
+    std::iter::from_fn(move || {
+        while let Some((path, depth, reach, rules)) = queue.pop_front() {
+            if path.is_dir() {
+                if options.max_depth.is_some_and(|max| depth > max) {
+                    verbose!(
+                        options,
+                        1,
+                        "skip {}: deeper than --max-depth",
+                        path.display()
+                    );
+                    continue;
+                }
+                if let Ok(canonical) = fs::canonicalize(&path)
+                    && !visited.insert(canonical)
+                {
+                    verbose!(options, 1, "skip {}: already visited", path.display());
+                    continue;
+                }
+
+                let rules = ignore::rules_for_dir(&path, &rules, options);
+
+                let entries = match read_dir(&path) {
+                    Ok(entries) => entries,
+                    Err(source) => return Some(Err(LineCountError::ReadDir { path, source })),
+                };
+                let mut children = Vec::new();
+                for entry in sorted(entries.flatten().collect(), options) {
+                    let child = entry.path();
+                    let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+                    if is_symlink && !options.follow_symlinks && child.is_dir() {
+                        verbose!(options, 1, "skip {}: symlink", child.display());
+                        continue;
+                    }
+                    if is_skipped(&root, &child, &rules, options) {
+                        continue;
+                    }
+                    let child_reach = match reach {
+                        Reach::Deep => Reach::Deep,
+                        _ if child.is_dir() => match recursive_path(&root, &child, options) {
+                            Some(reach) => reach,
+                            None => {
+                                verbose!(options, 1, "skip {}: not recursing", child.display());
+                                continue;
+                            }
+                        },
+                        Reach::Shallow => Reach::Shallow,
+                        Reach::Transit => {
+                            verbose!(
+                                options,
+                                1,
+                                "skip {}: outside --recursive-paths",
+                                child.display()
+                            );
+                            continue;
+                        }
+                    };
+                    children.push((child, depth + 1, child_reach, rules.clone()));
+                }
+                match options.walk_order {
+                    // Pushed in reverse, so they're popped in order.
+                    WalkOrder::DepthFirst => {
+                        for child in children.into_iter().rev() {
+                            queue.push_front(child);
+                        }
+                    }
+                    WalkOrder::BreadthFirst => queue.extend(children),
+                }
+                return Some(Ok(path));
+            }
+            return Some(Ok(path));
+        }
+        None
+    })
+}
+
+/// Whether `dir` is one of the `recursive_paths` relative to `root`, or a
+/// directory leading to one.
+fn recursive_path(root: &Path, dir: &Path, options: &CountOptions) -> Option<Reach> {
+    let rel = to_slash(dir.strip_prefix(root).unwrap_or(dir));
+    let mut paths = options.recursive_paths.iter().map(|p| p.trim_matches('/'));
+    if paths.clone().any(|p| p == rel) {
+        Some(Reach::Deep)
+    } else if paths.any(|p| {
+        p.strip_prefix(rel.as_str())
+            .is_some_and(|r| r.starts_with('/'))
+    }) {
+        Some(Reach::Transit)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    /// The files a recursive walk of `dir` yields, relative to it and in
+    /// walk order.
+    fn walked(dir: &TestDir, options: &CountOptions) -> Vec<String> {
+        walk_recursive(dir.path(), options)
+            .map(Result::unwrap)
+            .filter(|p| !p.is_dir())
+            .map(|p| to_slash(p.strip_prefix(dir.path()).unwrap()))
+            .collect()
+    }
+
+    fn recursive() -> CountOptions {
+        CountOptions {
+            recursive: true,
+            ..CountOptions::default()
+        }
+    }
+
+    #[test]
+    fn max_depth_stops_below_the_given_depth() {
+        let dir = TestDir::new();
+        dir.file("root.rs", "");
+        dir.file("a/one.rs", "");
+        dir.file("a/b/two.rs", "");
+        dir.file("a/b/c/three.rs", "");
+        let depth = |max| CountOptions {
+            max_depth: Some(max),
+            ..CountOptions::default()
+        };
+        assert_eq!(walked(&dir, &depth(0)), ["root.rs"]);
+        assert_eq!(walked(&dir, &depth(1)), ["a/one.rs", "root.rs"]);
+        assert_eq!(
+            walked(&dir, &recursive()),
+            ["a/b/c/three.rs", "a/b/two.rs", "a/one.rs", "root.rs"]
+        );
+    }
+
+    #[test]
+    fn walk_order_decides_when_subdirectories_come() {
+        let dir = TestDir::new();
+        dir.file("top.rs", "");
+        dir.file("a/y.rs", "");
+        dir.file("a/deep/x.rs", "");
+        dir.file("b/z.rs", "");
+        let bfs = CountOptions {
+            walk_order: WalkOrder::BreadthFirst,
+            ..recursive()
+        };
+        assert_eq!(
+            walked(&dir, &recursive()),
+            ["a/deep/x.rs", "a/y.rs", "b/z.rs", "top.rs"]
+        );
+        assert_eq!(
+            walked(&dir, &bfs),
+            ["top.rs", "a/y.rs", "b/z.rs", "a/deep/x.rs"]
+        );
+        let shallow_bfs = CountOptions {
+            max_depth: Some(1),
+            ..bfs.clone()
+        };
+        assert_eq!(walked(&dir, &shallow_bfs), ["top.rs", "a/y.rs", "b/z.rs"]);
+
+        // Directories come right after they're listed.
+        let all: Vec<String> = walk_recursive(dir.path(), &bfs)
+            .map(|p| to_slash(p.unwrap().strip_prefix(dir.path()).unwrap()))
+            .collect();
+        assert_eq!(
+            all,
+            [
+                "",
+                "a",
+                "b",
+                "top.rs",
+                "a/deep",
+                "a/y.rs",
+                "b/z.rs",
+                "a/deep/x.rs"
+            ]
+        );
+    }
+
+    /// Without known names, so the ignore files themselves stay hidden.
+    fn gitignore() -> CountOptions {
+        CountOptions {
+            gitignore: true,
+            names: Vec::new(),
+            ..recursive()
+        }
+    }
+
+    #[test]
+    fn a_nested_gitignore_only_applies_below_its_directory() {
+        let dir = TestDir::new();
+        dir.file("sub/.gitignore", "gen.rs\n!keep.txt\n");
+        dir.file(".gitignore", "*.txt\n");
+        for name in [
+            "gen.rs",
+            "keep.txt",
+            "other/gen.rs",
+            "sub/gen.rs",
+            "sub/deep/gen.rs",
+        ] {
+            dir.file(name, "");
+        }
+        dir.file("sub/keep.txt", "");
+        dir.file("sub/drop.txt", "");
+        assert_eq!(
+            walked(&dir, &gitignore()),
+            ["gen.rs", "other/gen.rs", "sub/keep.txt"]
+        );
+    }
+
+    #[test]
+    fn the_last_matching_pattern_wins_so_negation_re_includes() {
+        let dir = TestDir::new();
+        dir.file(".gitignore", "*.rs\n!main.rs\n");
+        dir.file("lib.rs", "");
+        dir.file("main.rs", "");
+        dir.file("a/main.rs", "");
+        assert_eq!(walked(&dir, &gitignore()), ["a/main.rs", "main.rs"]);
+
+        // A negation before the pattern it would undo has no effect.
+        dir.file(".gitignore", "!main.rs\n*.rs\n");
+        assert!(walked(&dir, &gitignore()).is_empty());
+    }
+
+    #[test]
+    fn a_trailing_slash_only_matches_directories() {
+        let dir = TestDir::new();
+        dir.file(".gitignore", "build/\n");
+        dir.file("build/out.rs", "");
+        dir.file("src/build/out.rs", "");
+        dir.file("src/build.rs", "");
+        dir.file("tools/build", "");
+        assert_eq!(walked(&dir, &gitignore()), ["src/build.rs", "tools/build"]);
+    }
+
+    #[test]
+    fn a_slash_anchors_to_the_ignore_files_directory() {
+        let dir = TestDir::new();
+        dir.file(".gitignore", "/top.rs\ndocs/gen.rs\n");
+        dir.file("sub/.gitignore", "/local.rs\n");
+        for name in [
+            "top.rs",
+            "sub/top.rs",
+            "docs/gen.rs",
+            "sub/docs/gen.rs",
+            "local.rs",
+            "sub/local.rs",
+            "sub/deeper/local.rs",
+        ] {
+            dir.file(name, "");
+        }
+        assert_eq!(
+            walked(&dir, &gitignore()),
+            [
+                "local.rs",
+                "sub/deeper/local.rs",
+                "sub/docs/gen.rs",
+                "sub/top.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn linecountignore_overrides_gitignore() {
+        let dir = TestDir::new();
+        dir.file(".gitignore", "*.rs\n!keep.txt\n");
+        dir.file(".linecountignore", "!keep.rs\n*.txt\n");
+        for name in ["drop.rs", "keep.rs", "keep.txt", "sub/keep.rs"] {
+            dir.file(name, "");
+        }
+        assert_eq!(walked(&dir, &gitignore()), ["keep.rs", "sub/keep.rs"]);
+
+        // Without `.gitignore` support its patterns don't apply at all.
+        let no_git = CountOptions {
+            gitignore: false,
+            ..gitignore()
+        };
+        assert_eq!(walked(&dir, &no_git), ["drop.rs", "keep.rs", "sub/keep.rs"]);
+        let no_files = CountOptions {
+            ignore_files: false,
+            ..gitignore()
+        };
+        assert_eq!(walked(&dir, &no_files), ["keep.txt"]);
+    }
+
+    #[test]
+    fn excluded_directories_are_not_descended() {
+        let dir = TestDir::new();
+        dir.file("src/main.rs", "");
+        dir.file("target/debug/build.rs", "");
+        dir.file("sub/target/gen.rs", "");
+        dir.file("targets.rs", "");
+        let options = CountOptions {
+            exclude: vec!["target".to_string()],
+            ..recursive()
+        };
+        assert_eq!(walked(&dir, &options), ["src/main.rs", "targets.rs"]);
+        // With a slash, a pattern is matched against the relative path.
+        let options = CountOptions {
+            exclude: vec!["sub/target".to_string()],
+            ..recursive()
+        };
+        assert_eq!(
+            walked(&dir, &options),
+            ["src/main.rs", "target/debug/build.rs", "targets.rs"]
+        );
+    }
+
+    #[test]
+    fn version_control_directories_are_only_walked_with_count_vcs() {
+        let dir = TestDir::new();
+        dir.file(".git/config.txt", "");
+        dir.file(".svn/entries.txt", "");
+        dir.file("src/.git/nested.txt", "");
+        dir.file("main.rs", "");
+        let hidden = CountOptions {
+            hidden: true,
+            ..recursive()
+        };
+        assert_eq!(walked(&dir, &hidden), ["main.rs"]);
+        let vcs = CountOptions {
+            count_vcs: true,
+            ..hidden
+        };
+        assert_eq!(
+            walked(&dir, &vcs),
+            [
+                ".git/config.txt",
+                ".svn/entries.txt",
+                "main.rs",
+                "src/.git/nested.txt"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_cycle_is_walked_once() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TestDir::new();
+        dir.file("top.rs", "");
+        dir.file("a/inner.rs", "");
+        symlink(dir.path(), dir.path().join("a/up")).unwrap();
+        symlink("../a", dir.path().join("a/self")).unwrap();
+        let options = CountOptions {
+            follow_symlinks: true,
+            ..recursive()
+        };
+        assert_eq!(walked(&dir, &options), ["a/inner.rs", "top.rs"]);
+    }
+
+    // Permissions don't stop root, and a directory that's gone by the time
+    // it's listed fails `is_dir` first, so this needs a normal user.
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "needs non-root"]
+    fn an_unreadable_directory_is_an_error_and_the_walk_goes_on() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::new();
+        dir.file("a.rs", "");
+        dir.file("locked/hidden.rs", "");
+        dir.file("z/b.rs", "");
+        let locked = dir.path().join("locked");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        let items: Vec<WalkItem> = walk_recursive(dir.path(), &recursive()).collect();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let errors: Vec<&LineCountError> = items.iter().filter_map(|i| i.as_ref().err()).collect();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], LineCountError::ReadDir { path, .. } if *path == locked));
+        assert!(errors[0].is_permission_denied());
+        let files: Vec<&PathBuf> = items
+            .iter()
+            .filter_map(|i| i.as_ref().ok())
+            .filter(|p| p.is_file())
+            .collect();
+        assert_eq!(
+            files,
+            [&dir.path().join("a.rs"), &dir.path().join("z/b.rs")]
+        );
+    }
+
+    #[test]
+    fn hidden_known_names_are_walked() {
+        let dir = TestDir::new();
+        dir.file(".gitignore", "");
+        dir.file(".editorconfig", "");
+        dir.file(".env", "");
+        dir.file(".github/workflow.yml", "");
+        dir.file("sub/.dockerignore", "");
+        // A directory of a known name is still hidden.
+        dir.file("sub/.gitattributes/inside.rs", "");
+        assert_eq!(
+            walked(&dir, &recursive()),
+            [".editorconfig", ".gitignore", "sub/.dockerignore"]
+        );
+    }
+}
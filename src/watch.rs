@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+/// How long the tree has to stay quiet before a recount, so a burst of
+/// changes such as a `git checkout` only triggers one.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Calls `run` once, then again after every batch of changes under `paths`.
+/// Changes to `ignored`, files `run` writes itself, don't count. Only
+/// returns if watching fails.
+pub fn watch<F: FnMut()>(
+    paths: &[PathBuf],
+    ignored: Vec<PathBuf>,
+    mut run: F,
+) -> notify::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if event.is_ok_and(|e| {
+            is_content_change(&e.kind) && !e.paths.iter().all(|p| ignored.contains(p))
+        }) {
+            let _ = tx.send(());
+        }
+    })?;
+    for path in paths {
+        watcher.watch(path, RecursiveMode::Recursive)?;
+    }
+
+    loop {
+        run();
+        if rx.recv().is_err() {
+            return Ok(());
+        }
+        while rx.recv_timeout(DEBOUNCE).is_ok() {}
+    }
+}
+
+/// Reading files while counting them produces access and metadata events,
+/// which must not trigger another count.
+fn is_content_change(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+// The same scratch directories as the unit tests use.
+#[path = "../src/testdir.rs"]
+mod testdir;
+
+use testdir::TestDir;
+
+/// A linecount command run in `dir` with `args`.
+fn command(dir: &TestDir, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_linecount"));
+    command.current_dir(dir.path()).args(args);
+    command
+}
+
+/// Runs linecount in `dir` with `args`.
+fn linecount(dir: &TestDir, args: &[&str]) -> Output {
+    command(dir, args).output().unwrap()
+}
+
+/// Runs linecount like [`linecount`], with `home` as the home directory.
+fn linecount_at_home(dir: &TestDir, home: &Path, args: &[&str]) -> Output {
+    command(dir, args).env("HOME", home).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn two_directories_sum() {
+    let dir = TestDir::new();
+    dir.file("a/one.rs", "1\n2\n");
+    dir.file("a/two.rs", "1\n");
+    dir.file("b/three.rs", "1\n2\n3\n4\n");
+    for (args, total) in [
+        (&["a"][..], 3),
+        (&["b"], 4),
+        (&["a", "b"], 7),
+        (&["b", "--recursive", "a"], 7),
+    ] {
+        let output = linecount(&dir, &[args, &["--quiet"]].concat());
+        assert!(output.status.success());
+        assert_eq!(stdout(&output), format!("{}\n", total), "{:?}", args);
+    }
+}
+
+#[test]
+fn a_missing_target_fails_but_the_others_are_counted() {
+    let dir = TestDir::new();
+    dir.file("here.rs", "1\n2\n");
+    let output = linecount(&dir, &["here.rs", "missing.rs", "--quiet"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "2\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("No such file or directory: missing.rs"),
+        "{}",
+        stderr
+    );
+
+    // With nothing left to count there's no total at all.
+    let output = linecount(&dir, &["missing.rs"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn a_named_file_skips_the_extension_filter_but_a_walked_one_does_not() {
+    let dir = TestDir::new();
+    dir.file("notes.xyz", "1\n2\n3\n");
+    dir.file("main.rs", "1\n");
+    let named = linecount(&dir, &["notes.xyz", "--quiet"]);
+    assert_eq!(stdout(&named), "3\n");
+    let walked = linecount(&dir, &[".", "--quiet"]);
+    assert_eq!(stdout(&walked), "1\n");
+    // A glob match goes through the filter too.
+    let globbed = linecount(&dir, &["*.xyz", "--quiet"]);
+    assert_eq!(stdout(&globbed), "0\n");
+}
+
+/// The per-file lines of a `--per-file` run.
+fn per_file(dir: &TestDir, args: &[&str]) -> Vec<String> {
+    let output = linecount(dir, &[&[".", "--recursive", "--per-file"], args].concat());
+    stdout(&output)
+        .lines()
+        .take_while(|line| !line.starts_with("Files:"))
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn per_file_order_is_stable_across_runs_and_jobs() {
+    let dir = TestDir::new();
+    for name in ["b.rs", "a/z.rs", "a/b.rs", "c/d/e.rs", "B.rs", "a.rs"] {
+        dir.file(name, "x\n");
+    }
+    let expected = [
+        "1\t./B.rs",
+        "1\t./a/b.rs",
+        "1\t./a/z.rs",
+        "1\t./a.rs",
+        "1\t./b.rs",
+        "1\t./c/d/e.rs",
+    ];
+    for _ in 0..3 {
+        assert_eq!(per_file(&dir, &["--jobs=1"]), expected);
+        assert_eq!(per_file(&dir, &["--jobs=8"]), expected);
+    }
+}
+
+#[test]
+fn per_file_order_with_many_jobs_matches_one_job() {
+    let dir = TestDir::new();
+    // Files of very different sizes, so workers finish out of order.
+    for i in 0..300 {
+        let lines = if i % 7 == 0 { 20_000 } else { i % 5 };
+        dir.file(&format!("d{}/f{}.txt", i % 4, i), "x\n".repeat(lines));
+    }
+    let one = per_file(&dir, &["--jobs=1"]);
+    assert_eq!(one.len(), 300);
+    for _ in 0..4 {
+        assert_eq!(per_file(&dir, &["--jobs=8"]), one);
+    }
+}
+
+#[test]
+fn the_global_ignore_file_applies_and_linecountignore_overrides_it() {
+    let dir = TestDir::new();
+    dir.file("home/.config/linecount/ignore", "*.txt\ngen/\n");
+    dir.file("proj/.linecountignore", "!keep.txt\n");
+    dir.file("proj/main.rs", "1\n");
+    dir.file("proj/drop.txt", "1\n2\n");
+    dir.file("proj/keep.txt", "1\n2\n3\n");
+    dir.file("proj/gen/out.rs", "1\n2\n3\n4\n");
+    let home = dir.path().join("home");
+    let args = ["proj", "--recursive", "--quiet"];
+    assert_eq!(stdout(&linecount_at_home(&dir, &home, &args)), "4\n");
+    let all = [&args[..], &["--no-ignore-files"]].concat();
+    assert_eq!(stdout(&linecount_at_home(&dir, &home, &all)), "10\n");
+}
+
+#[test]
+fn an_existing_path_with_glob_characters_is_not_a_pattern() {
+    let dir = TestDir::new();
+    dir.file("a[1].rs", "1\n2\n");
+    dir.file("a1.rs", "1\n");
+    assert_eq!(stdout(&linecount(&dir, &["a[1].rs", "--quiet"])), "2\n");
+    // Once it doesn't exist, the same name is expanded.
+    fs::remove_file(dir.path().join("a[1].rs")).unwrap();
+    assert_eq!(stdout(&linecount(&dir, &["a[1].rs", "--quiet"])), "1\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn help_and_version_into_a_closed_pipe_exit_quietly() {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::process::Stdio;
+
+    let dir = TestDir::new();
+    for arg in ["--help", "--version"] {
+        let mut fds = [0; 2];
+        // SAFETY: pipe only writes the two descriptors it opens to `fds`.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: both descriptors were just opened and nothing else owns them.
+        let (reader, writer) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        drop(reader);
+        let output = command(&dir, &[arg])
+            .stdout(Stdio::from(writer))
+            .stderr(Stdio::piped())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", arg);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "", "{}", arg);
+    }
+}